@@ -0,0 +1,12 @@
+CREATE TABLE status_overrides (
+id uuid NOT NULL UNIQUE,
+PRIMARY KEY (id),
+record_type TEXT NOT NULL,
+record_id uuid NOT NULL,
+from_status TEXT,
+to_status TEXT NOT NULL,
+reason TEXT NOT NULL,
+operator TEXT NOT NULL,
+created TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX status_overrides_record_id_idx ON status_overrides (record_id);
@@ -0,0 +1,2 @@
+ALTER TABLE aic_archive
+ADD COLUMN archived TIMESTAMPTZ;
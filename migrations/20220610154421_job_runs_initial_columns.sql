@@ -0,0 +1,13 @@
+CREATE TABLE job_runs (
+id uuid NOT NULL UNIQUE,
+PRIMARY KEY (id),
+job TEXT NOT NULL,
+started TIMESTAMPTZ NOT NULL,
+ended TIMESTAMPTZ,
+processed BIGINT NOT NULL DEFAULT 0,
+succeeded BIGINT NOT NULL DEFAULT 0,
+skipped BIGINT NOT NULL DEFAULT 0,
+failed BIGINT NOT NULL DEFAULT 0,
+error TEXT
+);
+CREATE INDEX job_runs_job_started_idx ON job_runs (job, started);
@@ -0,0 +1,18 @@
+CREATE TABLE commissions (
+commission_id TEXT NOT NULL UNIQUE,
+PRIMARY KEY (commission_id),
+order_id TEXT NOT NULL,
+original BOOLEAN NOT NULL,
+correction_reason TEXT,
+posting_date TIMESTAMPTZ NOT NULL,
+sale_amount INTEGER NOT NULL,
+commission_amount INTEGER,
+action_status TEXT,
+validation_status TEXT,
+publisher_id TEXT,
+skus TEXT[] NOT NULL,
+first_seen TIMESTAMPTZ NOT NULL,
+last_seen TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX commissions_order_id_idx ON commissions (order_id);
+CREATE INDEX commissions_posting_date_idx ON commissions (posting_date);
@@ -0,0 +1,2 @@
+ALTER TABLE commissions
+ADD COLUMN mismatch_reasons JSONB;
@@ -0,0 +1,7 @@
+CREATE TABLE exchange_rates (
+date DATE NOT NULL,
+currency TEXT NOT NULL,
+PRIMARY KEY (date, currency),
+rate DOUBLE PRECISION NOT NULL,
+loaded TIMESTAMPTZ NOT NULL
+);
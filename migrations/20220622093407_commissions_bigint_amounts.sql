@@ -0,0 +1,3 @@
+-- Amounts are kept as i64 minor units of the publisher currency
+ALTER TABLE commissions ALTER COLUMN sale_amount TYPE BIGINT;
+ALTER TABLE commissions ALTER COLUMN commission_amount TYPE BIGINT;
@@ -0,0 +1,6 @@
+-- Promotion codes are kept as a list, split on commas like coupons::parse_coupons
+ALTER TABLE subscriptions
+ALTER COLUMN coupons TYPE TEXT[]
+USING array_remove(regexp_split_to_array(trim(coalesce(coupons, '')), '\s*,\s*'), '');
+ALTER TABLE subscriptions ALTER COLUMN coupons SET DEFAULT '{}';
+ALTER TABLE subscriptions ALTER COLUMN coupons SET NOT NULL;
@@ -0,0 +1,3 @@
+-- The items a subscription was sold as, e.g. a bundle's plans or add-ons. Empty for
+-- subscriptions that are just their plan.
+ALTER TABLE subscriptions ADD COLUMN line_items JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,3 @@
+-- How many times verify_reports put the subscription back to be reported again because
+-- CJ didn't receive it. Status overrides don't count.
+ALTER TABLE subscriptions ADD COLUMN re_report_count INTEGER NOT NULL DEFAULT 0;
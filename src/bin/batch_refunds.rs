@@ -1,8 +1,24 @@
-use lib::{appconfig::CJ, jobs::batch_refunds::batch_refunds_by_day, telemetry::LogKey};
+use clap::Parser;
+use lib::{
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::batch_refunds::batch_refunds_by_day,
+    telemetry::LogKey,
+    worker::{Job, JobArgs},
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cj = CJ::new(LogKey::BatchRefunds).await;
-    batch_refunds_by_day(&cj.db_pool, &cj.statsd).await;
-    cj.shutdown().await
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::BatchRefunds, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    let result = cj
+        .run_job(
+            Job::BatchRefunds,
+            args.dry_run,
+            batch_refunds_by_day(&cj.db_pool, &cj.cj_client, &cj.statsd, args.dry_run),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
 }
@@ -0,0 +1,46 @@
+use clap::Parser;
+use lib::bigquery::emulator::{load_tables, run_emulator, EmulatorConfig, EmulatorState};
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Emulates BigQuery's query and getQueryResults endpoints for local development.
+///
+/// Point bigquery_domain at http://<addr>. BQ_ACCESS_TOKEN can be anything.
+#[derive(Parser)]
+#[clap(name = "bq_emulator")]
+struct Cli {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8020")]
+    addr: String,
+    /// Directory of <dataset>.<table>.ndjson files to serve
+    #[clap(long, default_value = "tests/fixtures/bigquery")]
+    data_dir: PathBuf,
+    /// Rows per page of results
+    #[clap(long, default_value_t = 1000)]
+    page_size: usize,
+    /// How many polls a job reports as incomplete before returning rows
+    #[clap(long, default_value_t = 0)]
+    incomplete_polls: usize,
+}
+
+// Like version, this is a development tool so doesn't set up tracing or Sentry.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let tables = load_tables(&cli.data_dir).map_err(std::io::Error::other)?;
+    let mut names: Vec<&String> = tables.keys().collect();
+    names.sort();
+    println!("BigQuery emulator running on http://{}", cli.addr);
+    for name in names {
+        println!("Serving {} ({} rows)", name, tables[name].len());
+    }
+    let state = Arc::new(Mutex::new(EmulatorState::with_tables(tables)));
+    let config = EmulatorConfig {
+        page_size: cli.page_size,
+        incomplete_polls: cli.incomplete_polls,
+    };
+    run_emulator(TcpListener::bind(&cli.addr)?, config, state)?.await
+}
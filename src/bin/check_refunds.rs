@@ -1,8 +1,24 @@
-use lib::{appconfig::CJ, jobs::check_refunds::fetch_and_process_refunds, telemetry::LogKey};
+use clap::Parser;
+use lib::{
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::check_refunds::fetch_and_process_refunds,
+    telemetry::LogKey,
+    worker::{Job, JobArgs},
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cj = CJ::new(LogKey::CheckRefunds).await;
-    fetch_and_process_refunds(&cj.bq_client, &cj.db_pool, &cj.statsd).await;
-    cj.shutdown().await
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::CheckRefunds, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    let result = cj
+        .run_job(
+            Job::CheckRefunds,
+            args.dry_run,
+            fetch_and_process_refunds(&cj.bq_client, &cj.db_pool, &cj.statsd, args.dry_run),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
 }
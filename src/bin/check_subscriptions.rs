@@ -1,11 +1,29 @@
+use clap::Parser;
 use lib::{
-    appconfig::CJ, jobs::check_subscriptions::fetch_and_process_new_subscriptions,
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::check_subscriptions::fetch_and_process_new_subscriptions,
     telemetry::LogKey,
+    worker::{Job, JobArgs},
 };
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cj = CJ::new(LogKey::CheckSubscriptions).await;
-    fetch_and_process_new_subscriptions(&cj.bq_client, &cj.db_pool, &cj.statsd).await;
-    cj.shutdown().await
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::CheckSubscriptions, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    let result = cj
+        .run_job(
+            Job::CheckSubscriptions,
+            args.dry_run,
+            fetch_and_process_new_subscriptions(
+                &cj.bq_client,
+                &cj.db_pool,
+                &cj.statsd,
+                args.dry_run,
+            ),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
 }
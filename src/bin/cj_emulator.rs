@@ -0,0 +1,37 @@
+use clap::Parser;
+use lib::cj::emulator::{run_emulator, EmulatorConfig};
+use std::{net::TcpListener, time::Duration};
+
+/// Emulates CJ's S2S and Commission Detail endpoints for local development.
+///
+/// Point cj_s2s_endpoint at http://<addr>/u and cj_commission_detail_endpoint at
+/// http://<addr>/query.
+#[derive(Parser)]
+#[clap(name = "cj_emulator")]
+struct Cli {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8010")]
+    addr: String,
+    /// Milliseconds to wait before every response
+    #[clap(long, default_value_t = 0)]
+    latency_ms: u64,
+    /// Chance, from 0 to 1, that a request gets a 500 instead
+    #[clap(long, default_value_t = 0.0)]
+    failure_rate: f64,
+    /// Records per Commission Detail page
+    #[clap(long, default_value_t = 100)]
+    page_size: usize,
+}
+
+// Like version, this is a development tool so doesn't set up tracing or Sentry.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let config = EmulatorConfig {
+        latency: Duration::from_millis(cli.latency_ms),
+        failure_rate: cli.failure_rate,
+        page_size: cli.page_size,
+    };
+    println!("CJ emulator running on http://{}", cli.addr);
+    run_emulator(TcpListener::bind(&cli.addr)?, config, Default::default())?.await
+}
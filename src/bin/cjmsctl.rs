@@ -0,0 +1,32 @@
+use clap::Parser;
+use lib::{
+    appconfig::CJ,
+    cjmsctl::{run, Cli},
+    error::exit_on_error,
+    error_and_incr,
+    telemetry::LogKey,
+};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let cj = CJ::new(LogKey::Cjmsctl).await.unwrap_or_else(exit_on_error);
+    let result = run(&cli.command, &cj.db_pool, &cj.settings, &cj.statsd).await;
+    match &result {
+        Ok(output) => println!("{}", output.render(cli.json)),
+        Err(e) => {
+            error_and_incr!(
+                cj.statsd,
+                LogKey::CjmsctlFailed,
+                error = e,
+                "cjmsctl failed"
+            );
+        }
+    }
+    cj.shutdown().await?;
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+    Ok(())
+}
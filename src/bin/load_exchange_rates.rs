@@ -0,0 +1,24 @@
+use clap::Parser;
+use lib::{
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::load_exchange_rates::load_exchange_rates,
+    telemetry::LogKey,
+    worker::{Job, JobArgs},
+};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::LoadExchangeRates, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    let result = cj
+        .run_job(
+            Job::LoadExchangeRates,
+            args.dry_run,
+            load_exchange_rates(&cj.db_pool, &cj.settings, &cj.statsd, args.dry_run),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
+}
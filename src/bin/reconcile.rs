@@ -0,0 +1,25 @@
+use clap::Parser;
+use lib::{
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::reconcile::reconcile,
+    telemetry::LogKey,
+    worker::{Job, JobArgs},
+};
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::Reconcile, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    // Only reads, so --dry-run changes nothing
+    let result = cj
+        .run_job(
+            Job::Reconcile,
+            args.dry_run,
+            reconcile(&cj.db_pool, &cj.settings, &cj.statsd),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
+}
@@ -1,10 +1,30 @@
+use clap::Parser;
 use lib::{
-    appconfig::CJ, jobs::report_subscriptions::report_subscriptions_to_cj, telemetry::LogKey,
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::report_subscriptions::report_subscriptions,
+    telemetry::LogKey,
+    worker::{Job, JobArgs},
 };
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cj = CJ::new(LogKey::ReportSubscriptions).await;
-    report_subscriptions_to_cj(&cj.db_pool, &cj.cj_client, &cj.statsd).await;
-    cj.shutdown().await
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::ReportSubscriptions, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    let result = cj
+        .run_job(
+            Job::ReportSubscriptions,
+            args.dry_run,
+            report_subscriptions(
+                &cj.db_pool,
+                &cj.cj_client,
+                &cj.settings,
+                &cj.statsd,
+                args.dry_run,
+            ),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
 }
@@ -1,8 +1,30 @@
-use lib::{appconfig::CJ, jobs::verify_reports::verify_reports_with_cj, telemetry::LogKey};
+use clap::Parser;
+use lib::{
+    appconfig::CJ,
+    error::exit_on_error,
+    jobs::verify_reports::verify_reports,
+    telemetry::LogKey,
+    worker::{Job, JobArgs},
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cj = CJ::new(LogKey::VerifyReports).await;
-    verify_reports_with_cj(&cj.db_pool, &cj.cj_client, &cj.statsd).await;
-    cj.shutdown().await
+    let args = JobArgs::parse();
+    let cj = CJ::new_for_job(LogKey::VerifyReports, args.dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    let result = cj
+        .run_job(
+            Job::VerifyReports,
+            args.dry_run,
+            verify_reports(
+                &cj.db_pool,
+                &cj.cj_client,
+                &cj.settings,
+                &cj.statsd,
+                args.dry_run,
+            ),
+        )
+        .await;
+    cj.shutdown_after_job(result).await
 }
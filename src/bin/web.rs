@@ -1,5 +1,6 @@
 use lib::{
     appconfig::{run_server, CJ},
+    error::exit_on_error,
     info,
     telemetry::LogKey,
 };
@@ -7,7 +8,7 @@ use std::net::TcpListener;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let cj = CJ::new(LogKey::WebApp).await;
+    let cj = CJ::new(LogKey::WebApp).await.unwrap_or_else(exit_on_error);
     let addr = cj.settings.server_address();
     info!(
         LogKey::WebApp,
@@ -19,7 +20,8 @@ async fn main() -> std::io::Result<()> {
         TcpListener::bind(addr)?,
         cj.db_pool.clone(),
         cj.statsd.clone(),
-    )?
+    )
+    .unwrap_or_else(exit_on_error)
     .await?;
     cj.shutdown().await
 }
@@ -0,0 +1,51 @@
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use lib::{
+    appconfig::CJ,
+    error::{exit_on_error, CjmsError},
+    telemetry::LogKey,
+    worker::{parse_schedules, run_scheduler, Job},
+};
+
+/// Runs cjms jobs, either once by name or on the cron schedules from settings.
+#[derive(Parser)]
+#[clap(name = "worker")]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run one job and exit. One of check_subscriptions, report_subscriptions, check_refunds, batch_refunds, verify_reports, cleanup, reconcile, load_exchange_rates
+    Run {
+        job: Job,
+        /// Log what the job would do without writing anything or reporting to CJ
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Run jobs on the schedules setting until SIGTERM
+    Schedule,
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let dry_run = matches!(cli.command, Command::Run { dry_run: true, .. });
+    let cj = CJ::new_for_job(LogKey::Worker, dry_run)
+        .await
+        .unwrap_or_else(exit_on_error);
+    match cli.command {
+        Command::Run { job, dry_run } => {
+            let result = job.run(&cj, dry_run).await;
+            return cj.shutdown_after_job(result).await;
+        }
+        Command::Schedule => {
+            // Can't run with a broken schedule.
+            let scheduled = parse_schedules(&cj.settings.schedules, Utc::now())
+                .unwrap_or_else(|e| exit_on_error(CjmsError::Configuration(e)));
+            run_scheduler(&cj, scheduled).await?;
+        }
+    }
+    cj.shutdown().await
+}
@@ -3,23 +3,30 @@ use actix_web::{
     dev::{Server, ServiceRequest},
     error::ErrorUnauthorized,
     http,
-    web::{get, post, put, resource, Data},
+    web::{get, post, put, resource, scope, Data},
     App, Error, HttpServer,
 };
 use actix_web_httpauth::extractors::basic::BasicAuth;
 use actix_web_httpauth::middleware::HttpAuthentication;
+use futures::FutureExt;
 use sentry::ClientInitGuard;
 use sqlx::{migrate, PgPool};
-use std::net::TcpListener;
+use std::{any::Any, future::Future, net::TcpListener, panic::AssertUnwindSafe};
 use time::OffsetDateTime;
 use tracing_actix_web_mozlog::MozLog;
+use uuid::Uuid;
 
 use crate::{
     bigquery::client::{get_bqclient, BQClient},
-    cj::client::CJClient,
-    controllers, info_and_incr,
+    cj::{client::CJClient, programs::CJPrograms},
+    controllers, error,
+    error::{exit_on_error, CjmsError},
+    error_and_incr, info,
+    jobs::outcome::JobOutcome,
+    models::{job_locks::JobLockModel, job_runs::JobRunModel},
     settings::{get_settings, Settings},
     telemetry::{init_sentry, init_tracing, LogKey, StatsD},
+    worker::Job,
 };
 
 pub struct CJ {
@@ -34,21 +41,40 @@ pub struct CJ {
 }
 
 impl CJ {
-    pub async fn new(name: LogKey) -> Self {
+    pub async fn new(name: LogKey) -> Result<Self, CjmsError> {
+        CJ::new_for_job(name, false).await
+    }
+
+    // For the job binaries. A dry run doesn't migrate the database, it only reads.
+    pub async fn new_for_job(name: LogKey, dry_run: bool) -> Result<Self, CjmsError> {
         let start = OffsetDateTime::now_utc();
         let settings = get_settings();
         let _guard = init_sentry(&settings);
-        if name != LogKey::Test {
-            init_tracing(&name.to_string(), &settings.log_level, std::io::stdout);
+        match name {
+            LogKey::Test => {}
+            // Keep stdout free for command output
+            LogKey::Cjmsctl => {
+                init_tracing(&name.to_string(), &settings.log_level, std::io::stderr)
+            }
+            _ => init_tracing(&name.to_string(), &settings.log_level, std::io::stdout),
         }
-        let db_pool = connect_to_database_and_migrate(&settings.database_url).await;
-        let bq_client = get_bqclient(&settings).await;
-        let cj_client = CJClient::new(&settings, None, None, None);
+        let db_pool = match dry_run {
+            true => connect_to_database(&settings.database_url).await?,
+            false => connect_to_database_and_migrate(&settings.database_url).await?,
+        };
+        let bq_client = get_bqclient(&settings).await?;
+        let cj_client = CJClient::new(
+            &settings,
+            settings.cj_s2s_endpoint.as_deref(),
+            settings.cj_commission_detail_endpoint.as_deref(),
+            None,
+        )?;
         let statsd = StatsD::new(&settings);
 
-        info_and_incr!(statsd, &name.add_suffix("starting"), "Application starting");
+        info!(&name.add_suffix("starting"), "Application starting");
+        statsd.incr(&name.add_suffix("starting"));
 
-        CJ {
+        Ok(CJ {
             _guard,
             name,
             start,
@@ -57,15 +83,177 @@ impl CJ {
             db_pool,
             settings,
             statsd,
+        })
+    }
+
+    // Every job entry point goes through here. Takes the job's advisory lock so runs of
+    // the same job can't overlap, then runs the job and records it in job_runs. If the
+    // lock is held elsewhere the job is skipped. A dry run takes no lock and isn't
+    // recorded, so it can't make a real run skip.
+    // Errors if the job errored, panicked or too many of its records failed.
+    pub async fn run_job<F: Future<Output = Result<JobOutcome, CjmsError>>>(
+        &self,
+        job: Job,
+        dry_run: bool,
+        f: F,
+    ) -> Result<JobOutcome, CjmsError> {
+        let key = job.log_key();
+        let job_locks = JobLockModel {
+            db_pool: &self.db_pool,
+        };
+        let lock = match dry_run {
+            true => None,
+            false => match job_locks.try_lock(&job.lock_name()).await {
+                Ok(Some(lock)) => Some(lock),
+                Ok(None) => {
+                    info!(
+                        &key.add_suffix("locked"),
+                        "Job is already running. Skipping."
+                    );
+                    self.statsd.incr(&key.add_suffix("locked"));
+                    return Ok(JobOutcome::default());
+                }
+                // The job didn't run, so the run fails
+                Err(e) => {
+                    error!(
+                        LogKey::JobLockFailed,
+                        error = e,
+                        job = job.to_string().as_str(),
+                        "Could not take job lock. Not running."
+                    );
+                    self.statsd.incr(&LogKey::JobLockFailed);
+                    return Err(CjmsError::Database(e));
+                }
+            },
+        };
+        let job_run = match dry_run {
+            true => None,
+            false => self.start_job_run(job).await,
+        };
+        let (outcome, error) = match self.catch_panic(f).await {
+            Ok(outcome) => {
+                outcome.report(&self.statsd, &key);
+                let error = self.check_failure_threshold(job, &outcome).err();
+                (outcome, error)
+            }
+            Err(e) => {
+                error_and_incr!(
+                    self.statsd,
+                    LogKey::JobFailed,
+                    error = e,
+                    job = job.to_string().as_str(),
+                    "Job failed"
+                );
+                (JobOutcome::default(), Some(e))
+            }
+        };
+        self.finish_job_run(job_run, &outcome, error.as_ref().map(|e| e.to_string()))
+            .await;
+        if let Some(lock) = lock {
+            if let Err(e) = lock.release().await {
+                error!(
+                    LogKey::JobLockFailed,
+                    error = e,
+                    job = job.to_string().as_str(),
+                    "Could not release job lock"
+                );
+            }
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(outcome),
         }
     }
 
-    pub async fn shutdown(&self) -> std::io::Result<()> {
-        info_and_incr!(
-            &self.statsd,
-            &self.name.add_suffix("ending"),
-            "Application ending"
+    fn check_failure_threshold(&self, job: Job, outcome: &JobOutcome) -> Result<(), CjmsError> {
+        let max = self.settings.max_job_failure_percent;
+        let failure_percent = outcome.failure_percent();
+        if failure_percent <= max {
+            return Ok(());
+        }
+        error_and_incr!(
+            self.statsd,
+            LogKey::JobFailureThresholdExceeded,
+            job = job.to_string().as_str(),
+            failed = outcome.failed,
+            processed = outcome.processed(),
+            "Too many records failed"
         );
+        Err(CjmsError::TooManyFailures(format!(
+            "{} failed for {} of {} records ({}%, more than {}%)",
+            job,
+            outcome.failed,
+            outcome.processed(),
+            failure_percent,
+            max
+        )))
+    }
+
+    // Starts a job_runs entry. Failing to record the run is logged
+    // but does not stop the job.
+    async fn start_job_run(&self, job: Job) -> Option<Uuid> {
+        let job_runs = JobRunModel {
+            db_pool: &self.db_pool,
+        };
+        match job_runs.start(&job.to_string()).await {
+            Ok(run) => Some(run.id),
+            Err(e) => {
+                error!(
+                    LogKey::JobRunRecordFailed,
+                    error = e,
+                    job = job.to_string().as_str(),
+                    "Could not record job run start"
+                );
+                None
+            }
+        }
+    }
+
+    // Runs the future, turning a panic into an error.
+    async fn catch_panic<F: Future<Output = Result<JobOutcome, CjmsError>>>(
+        &self,
+        f: F,
+    ) -> Result<JobOutcome, CjmsError> {
+        match AssertUnwindSafe(f).catch_unwind().await {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                error!(
+                    LogKey::JobPanicked,
+                    panic_message = message.as_str(),
+                    "Job panicked"
+                );
+                Err(CjmsError::JobPanicked(message))
+            }
+        }
+    }
+
+    // Completes the job_runs entry, if there is one, with the job's outcome and the
+    // error if the job panicked or failed.
+    async fn finish_job_run(
+        &self,
+        job_run: Option<Uuid>,
+        outcome: &JobOutcome,
+        error: Option<String>,
+    ) {
+        if let Some(id) = job_run {
+            let job_runs = JobRunModel {
+                db_pool: &self.db_pool,
+            };
+            if let Err(e) = job_runs.finish(&id, outcome, error).await {
+                error!(
+                    LogKey::JobRunRecordFailed,
+                    error = e,
+                    job_run_id = id.to_string().as_str(),
+                    "Could not record job run end"
+                );
+            }
+        }
+    }
+
+    pub async fn shutdown(&self) -> std::io::Result<()> {
+        info!(&self.name.add_suffix("ending"), "Application ending");
+        self.statsd.incr(&self.name.add_suffix("ending"));
 
         self.db_pool.close().await;
         self.statsd.time(
@@ -74,6 +262,26 @@ impl CJ {
         );
         Ok(())
     }
+
+    // For the job binaries. Shuts down and exits with the error's exit code if the job
+    // failed.
+    pub async fn shutdown_after_job(
+        &self,
+        result: Result<JobOutcome, CjmsError>,
+    ) -> std::io::Result<()> {
+        self.shutdown().await?;
+        result.map(|_| ()).or_else(exit_on_error)
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
 }
 
 async fn basic_auth_middleware(
@@ -98,9 +306,12 @@ pub fn run_server(
     listener: TcpListener,
     db_pool: PgPool,
     statsd: StatsD,
-) -> Result<Server, std::io::Error> {
+) -> Result<Server, CjmsError> {
+    // Checked before serving, so a bad cj_programs or plans setting stops the server
+    let programs = CJPrograms::new(&settings)?;
     let server = HttpServer::new(move || {
         let db_pool_d = Data::new(db_pool.clone());
+        let programs_d = Data::new(programs.clone());
         let settings_d = Data::new(settings.clone());
         let statsd_d = Data::new(statsd.clone());
         let cors = get_cors(settings.clone());
@@ -121,6 +332,7 @@ pub fn run_server(
                 resource("/__error_panic__").route(get().to(controllers::custodial::error_panic)),
             )
             .service(resource("/__metrics__").route(get().to(controllers::custodial::metrics)))
+            .service(resource("/__jobs__").route(get().to(controllers::custodial::jobs)))
             // AIC
             .service(resource("/aic").route(post().to(controllers::aic::create)))
             .service(resource("/aic/{aic_id}").route(put().to(controllers::aic::update)))
@@ -131,27 +343,61 @@ pub fn run_server(
             .service(
                 resource("/corrections/{day}.csv")
                     .route(get().to(controllers::corrections::by_day))
-                    .wrap(auth),
+                    .wrap(auth.clone()),
+            )
+            .service(
+                resource("/corrections/{program}/today.csv")
+                    .route(get().to(controllers::corrections::program_today)),
+            )
+            .service(
+                resource("/corrections/{program}/{day}.csv")
+                    .route(get().to(controllers::corrections::program_by_day))
+                    .wrap(auth.clone()),
+            )
+            // Admin
+            .service(
+                scope("/admin")
+                    .wrap(auth)
+                    .service(
+                        resource("/subscriptions")
+                            .route(get().to(controllers::admin::subscriptions)),
+                    )
+                    .service(
+                        resource("/subscriptions/{id}/status")
+                            .route(post().to(controllers::admin::override_subscription_status)),
+                    )
+                    .service(resource("/refunds").route(get().to(controllers::admin::refunds)))
+                    .service(
+                        resource("/refunds/{refund_id}/status")
+                            .route(post().to(controllers::admin::override_refund_status)),
+                    )
+                    .service(resource("/aics").route(get().to(controllers::admin::aics)))
+                    .service(resource("/timeline").route(get().to(controllers::admin::timeline)))
+                    .service(
+                        resource("/reconciliation")
+                            .route(get().to(controllers::admin::reconciliation)),
+                    ),
             )
             // Make data objects available to all routes
             .app_data(db_pool_d)
             .app_data(settings_d)
             .app_data(statsd_d)
+            .app_data(programs_d)
     })
-    .listen(listener)?
+    .listen(listener)
+    .map_err(|e| CjmsError::Configuration(format!("Could not listen. {}", e)))?
     .run();
     Ok(server)
 }
 
-pub async fn connect_to_database_and_migrate(database_url: &str) -> PgPool {
-    let connection_pool = PgPool::connect(database_url)
-        .await
-        .expect("Failed to connect to Postgres.");
-    migrate!("./migrations")
-        .run(&connection_pool)
-        .await
-        .expect("Failed to migrate database.");
-    connection_pool
+pub async fn connect_to_database(database_url: &str) -> Result<PgPool, CjmsError> {
+    Ok(PgPool::connect(database_url).await?)
+}
+
+pub async fn connect_to_database_and_migrate(database_url: &str) -> Result<PgPool, CjmsError> {
+    let connection_pool = connect_to_database(database_url).await?;
+    migrate!("./migrations").run(&connection_pool).await?;
+    Ok(connection_pool)
 }
 
 fn get_cors(settings: Settings) -> Cors {
@@ -2,29 +2,36 @@ use async_trait::async_trait;
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::settings::Settings;
+use crate::{error::CjmsError, settings::Settings};
 
 pub use super::model::{BQError, ResultSet};
 use super::model::{GetQueryResultsResponse, QueryResponse};
 
-pub async fn get_bqclient(settings: &Settings) -> BQClient {
+pub async fn get_bqclient(settings: &Settings) -> Result<BQClient, CjmsError> {
     // Note we don't have tests that check:
     // - the correct setting of token when using metadata
     // - the correct setting of project when using metadata
     // Take appropriate caution when updating this function.
-    match use_env(settings) {
-        true => BQClient::new(&settings.gcp_project, AccessTokenFromEnv {}, None).await,
-        false => BQClient::new(&settings.gcp_project, AccessTokenFromMetadata {}, None).await,
+    let domain = settings.bigquery_domain.as_deref();
+    match use_env(settings)? {
+        true => BQClient::new(&settings.gcp_project, AccessTokenFromEnv {}, domain).await,
+        false => BQClient::new(&settings.gcp_project, AccessTokenFromMetadata {}, domain).await,
     }
 }
 
-fn use_env(settings: &Settings) -> bool {
+fn use_env(settings: &Settings) -> Result<bool, CjmsError> {
     match settings.environment.as_str() {
-        "dev" | "stage" | "prod" => false,
-        "local" => true,
-        _ => panic!("Invalid environment value. Must be local | dev | stage | prod."),
+        "dev" | "stage" | "prod" => Ok(false),
+        "local" => Ok(true),
+        _ => Err(CjmsError::Configuration(
+            "Invalid environment value. Must be local | dev | stage | prod.".to_string(),
+        )),
     }
 }
+// Each poll of an incomplete job waits up to JOB_POLL_TIMEOUT_MS on BigQuery's side
+const JOB_POLL_TIMEOUT_MS: u64 = 10_000;
+const MAX_JOB_POLLS: usize = 30;
+
 pub struct BQClient {
     domain: String,
     pub project: String,
@@ -33,14 +40,18 @@ pub struct BQClient {
 }
 
 impl BQClient {
-    pub async fn new(project: &str, token: impl GetAccessToken, domain: Option<&str>) -> BQClient {
+    pub async fn new(
+        project: &str,
+        token: impl GetAccessToken,
+        domain: Option<&str>,
+    ) -> Result<BQClient, CjmsError> {
         let domain = domain.unwrap_or("https://www.googleapis.com");
-        BQClient {
+        Ok(BQClient {
             domain: domain.to_string(),
             project: project.to_string(),
-            access_token: token.get().await,
+            access_token: token.get().await?,
             client: reqwest::Client::new(),
-        }
+        })
     }
     pub fn query_api_url(&self) -> String {
         format!(
@@ -48,7 +59,13 @@ impl BQClient {
             self.domain, self.project
         )
     }
-    pub async fn get_bq_results(&self, query: &str) -> ResultSet {
+    pub fn query_results_api_url(&self, job_id: &str) -> String {
+        format!("{}/{}", self.query_api_url(), job_id)
+    }
+
+    // Waits for the query's job to complete and pages through its results, so the
+    // result set has every row.
+    pub async fn get_bq_results(&self, query: &str) -> Result<ResultSet, CjmsError> {
         let resp = self
             .client
             .post(self.query_api_url().as_str())
@@ -59,21 +76,78 @@ impl BQClient {
                 "useLegacySql": false,
             }))
             .send()
-            .await
-            .expect("Did not successfully query bigquery");
-        if resp.status() != 200 {
-            panic!("Did not successfully query bigquery. {:?}", resp)
+            .await;
+        let mut query_results = self.read_response(resp).await?;
+        let mut polls = 0;
+        while !query_results.job_complete.unwrap_or(false) {
+            polls += 1;
+            if polls > MAX_JOB_POLLS {
+                return Err(BQError::JobIncomplete {
+                    polls: MAX_JOB_POLLS,
+                }
+                .into());
+            }
+            query_results = self.get_query_results(&query_results, None).await?;
+        }
+        let mut rows = query_results.rows.take().unwrap_or_default();
+        while let Some(page_token) = query_results.page_token.take() {
+            let mut page = self
+                .get_query_results(&query_results, Some(&page_token))
+                .await?;
+            rows.append(&mut page.rows.take().unwrap_or_default());
+            query_results.page_token = page.page_token;
+        }
+        query_results.rows = Some(rows);
+        Ok(ResultSet::new(QueryResponse::from(query_results)))
+    }
+
+    // Long polls the job for up to JOB_POLL_TIMEOUT_MS
+    async fn get_query_results(
+        &self,
+        previous: &GetQueryResultsResponse,
+        page_token: Option<&str>,
+    ) -> Result<GetQueryResultsResponse, CjmsError> {
+        let job_reference = previous.job_reference.as_ref();
+        let job_id = job_reference
+            .and_then(|r| r.job_id.as_deref())
+            .ok_or(BQError::NoJobId)?;
+        let mut params = vec![("timeoutMs", JOB_POLL_TIMEOUT_MS.to_string())];
+        if let Some(location) = job_reference.and_then(|r| r.location.clone()) {
+            params.push(("location", location));
+        }
+        if let Some(page_token) = page_token {
+            params.push(("pageToken", page_token.to_string()));
+        }
+        let resp = self
+            .client
+            .get(self.query_results_api_url(job_id))
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .query(&params)
+            .send()
+            .await;
+        self.read_response(resp).await
+    }
+
+    async fn read_response(
+        &self,
+        resp: Result<reqwest::Response, reqwest::Error>,
+    ) -> Result<GetQueryResultsResponse, CjmsError> {
+        let resp = resp.map_err(BQError::from)?;
+        let status = resp.status();
+        if status != 200 {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(BQError::Status { status, body }.into());
         }
-        let query_results: GetQueryResultsResponse =
-            resp.json().await.expect("Couldn't extract body.");
-        ResultSet::new(QueryResponse::from(query_results))
+        resp.json()
+            .await
+            .map_err(|e| BQError::Body(e.to_string()).into())
     }
 }
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait GetAccessToken {
-    async fn get(&self) -> String;
+    async fn get(&self) -> Result<String, CjmsError>;
 }
 
 #[derive(Deserialize, Debug)]
@@ -90,7 +164,7 @@ pub struct AccessTokenFromMetadata {}
 #[async_trait]
 impl GetAccessToken for AccessTokenFromMetadata {
     // GCP docs on how this works https://cloud.google.com/run/docs/securing/service-identity#fetching_identity_and_access_tokens_using_the_metadata_server
-    async fn get(&self) -> String {
+    async fn get(&self) -> Result<String, CjmsError> {
         let client = reqwest::Client::new();
         let resp = client
             .get("http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token")
@@ -100,22 +174,28 @@ impl GetAccessToken for AccessTokenFromMetadata {
         match resp {
             Ok(r) => {
                 if r.status() == 200 {
-                    let content: WorkloadIdentityAccessToken = r
-                        .json()
-                        .await
-                        .expect("Couldn't deserialize metadata for pod");
-                    content.access_token
+                    let content: WorkloadIdentityAccessToken = r.json().await.map_err(|e| {
+                        CjmsError::Configuration(format!(
+                            "Couldn't deserialize metadata for pod. {}",
+                            e
+                        ))
+                    })?;
+                    Ok(content.access_token)
                 } else {
                     let body = r
                         .text()
                         .await
                         .unwrap_or_else(|_| String::from("Failed to get body text."));
-                    panic!("Couldn't get metadata for pod. {:?}", body);
+                    Err(CjmsError::Configuration(format!(
+                        "Couldn't get metadata for pod. {:?}",
+                        body
+                    )))
                 }
             }
-            Err(e) => {
-                panic!("Couldn't get metadata for pod. {:?}", e);
-            }
+            Err(e) => Err(CjmsError::Configuration(format!(
+                "Couldn't get metadata for pod. {:?}",
+                e
+            ))),
         }
     }
 }
@@ -123,8 +203,9 @@ impl GetAccessToken for AccessTokenFromMetadata {
 pub struct AccessTokenFromEnv {}
 #[async_trait]
 impl GetAccessToken for AccessTokenFromEnv {
-    async fn get(&self) -> String {
-        std::env::var("BQ_ACCESS_TOKEN").expect("BQ_ACCESS_TOKEN not found in env.")
+    async fn get(&self) -> Result<String, CjmsError> {
+        std::env::var("BQ_ACCESS_TOKEN")
+            .map_err(|_| CjmsError::Configuration("BQ_ACCESS_TOKEN not found in env.".to_string()))
     }
 }
 
@@ -156,7 +237,7 @@ mod tests {
     fn test_use_env_true() {
         let mut settings = empty_settings();
         settings.environment = "local".to_string();
-        let use_env = use_env(&settings);
+        let use_env = use_env(&settings).unwrap();
         assert!(use_env);
     }
 
@@ -165,17 +246,20 @@ mod tests {
         let mut settings = empty_settings();
         for test_case in ["dev", "stage", "prod"] {
             settings.environment = test_case.to_string();
-            let use_env = use_env(&settings);
+            let use_env = use_env(&settings).unwrap();
             assert!(!use_env);
         }
     }
 
     #[test]
-    #[should_panic(expected = "Invalid environment value. Must be local | dev |")]
     fn test_use_env_invalid() {
         let mut settings = empty_settings();
         settings.environment = "misc".to_string();
-        use_env(&settings);
+        let e = use_env(&settings).unwrap_err();
+        assert!(matches!(e, CjmsError::Configuration(_)));
+        assert!(e
+            .to_string()
+            .contains("Invalid environment value. Must be local | dev |"));
     }
 
     #[tokio::test]
@@ -184,7 +268,7 @@ mod tests {
         env::set_var("BQ_ACCESS_TOKEN", "a token");
         let mut settings = get_test_settings("test_gcp_project");
         settings.environment = "local".to_string();
-        let bq = get_bqclient(&settings).await;
+        let bq = get_bqclient(&settings).await.unwrap();
         assert_eq!(bq.project, "test_gcp_project");
         env::remove_var("BQ_ACCESS_TOKEN");
     }
@@ -194,9 +278,9 @@ mod tests {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(random_simple_ascii_string);
+            .returning(|| Ok(random_simple_ascii_string()));
         let project = random_simple_ascii_string();
-        let bq = BQClient::new(&project, mock_token, None).await;
+        let bq = BQClient::new(&project, mock_token, None).await.unwrap();
         assert_eq!(
             bq.query_api_url(),
             format!(
@@ -211,8 +295,10 @@ mod tests {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(random_simple_ascii_string);
-        let bq = BQClient::new("its_a_project", mock_token, Some("http://localhost")).await;
+            .returning(|| Ok(random_simple_ascii_string()));
+        let bq = BQClient::new("its_a_project", mock_token, Some("http://localhost"))
+            .await
+            .unwrap();
         assert_eq!(
             bq.query_api_url(),
             "http://localhost/bigquery/v2/projects/its_a_project/queries"
@@ -225,26 +311,34 @@ mod tests {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(|| access_token.to_string());
-        let bq = BQClient::new(&random_simple_ascii_string(), mock_token, None).await;
+            .returning(|| Ok(access_token.to_string()));
+        let bq = BQClient::new(&random_simple_ascii_string(), mock_token, None)
+            .await
+            .unwrap();
         assert_eq!(bq.access_token, access_token);
     }
 
     #[tokio::test]
     #[serial]
-    #[should_panic(expected = "BQ_ACCESS_TOKEN not found in env.")]
-    async fn missing_env_var_panics() {
+    async fn missing_env_var_errors() {
         std::env::remove_var("BQ_ACCESS_TOKEN");
         let token_from_env = AccessTokenFromEnv {};
-        BQClient::new(&random_simple_ascii_string(), token_from_env, None).await;
+        let e = BQClient::new(&random_simple_ascii_string(), token_from_env, None)
+            .await
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("BQ_ACCESS_TOKEN not found in env."));
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Couldn't get metadata for pod.")]
-    async fn pod_metadata_panics() {
-        // As we can't simulate a pod, we test the panic.
+    async fn pod_metadata_errors() {
+        // As we can't simulate a pod, we test the error.
         let token_from_metadata = AccessTokenFromMetadata {};
-        BQClient::new(&random_simple_ascii_string(), token_from_metadata, None).await;
+        let e = BQClient::new(&random_simple_ascii_string(), token_from_metadata, None)
+            .await
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("Couldn't get metadata for pod."));
     }
 
     #[tokio::test]
@@ -253,7 +347,9 @@ mod tests {
         let access_token = "env_access_token";
         std::env::set_var("BQ_ACCESS_TOKEN", access_token);
         let token_from_env = AccessTokenFromEnv {};
-        let bq = BQClient::new(&random_simple_ascii_string(), token_from_env, None).await;
+        let bq = BQClient::new(&random_simple_ascii_string(), token_from_env, None)
+            .await
+            .unwrap();
         assert_eq!(bq.access_token, access_token);
         std::env::remove_var("BQ_ACCESS_TOKEN");
     }
@@ -264,14 +360,15 @@ mod tests {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(|| access_token.to_string());
+            .returning(|| Ok(access_token.to_string()));
         let mock_google = MockServer::start().await;
         let bq = BQClient::new(
             &random_simple_ascii_string(),
             mock_token,
             Some(&mock_google.uri()),
         )
-        .await;
+        .await
+        .unwrap();
         let url = bq.query_api_url();
         let expected_path = url.trim_start_matches(&mock_google.uri());
         let query = "SELECT * FROM `dataset.table`;";
@@ -282,7 +379,7 @@ mod tests {
                 "Authorization",
                 format!("Bearer {}", access_token).as_str(),
             ))
-            .and(body_json(&json!({
+            .and(body_json(json!({
                 "kind": "bigquery#queryResponse",
                 "query": query,
                 "useLegacySql": false,
@@ -292,55 +389,54 @@ mod tests {
             .mount(&mock_google)
             .await;
 
-        bq.get_bq_results(query).await;
+        bq.get_bq_results(query).await.unwrap();
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Did not successfully query bigquery.")]
-    async fn bq_client_query_panics_on_500() {
-        // This tests the manual panic, not the expect.
-        // Not sure how to generate a redirect loop to test the first.
-        // This is fine.
+    async fn bq_client_query_errors_on_500() {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(random_simple_ascii_string);
+            .returning(|| Ok(random_simple_ascii_string()));
         let mock_google = MockServer::start().await;
         let bq = BQClient::new(
             &random_simple_ascii_string(),
             mock_token,
             Some(&mock_google.uri()),
         )
-        .await;
+        .await
+        .unwrap();
         Mock::given(any())
             .respond_with(ResponseTemplate::new(500))
             .mount(&mock_google)
             .await;
-        bq.get_bq_results("").await;
+        let e = bq.get_bq_results("").await.err().unwrap();
+        assert!(matches!(e, CjmsError::BigQuery(_)));
+        assert!(e
+            .to_string()
+            .contains("Did not successfully query bigquery."));
     }
 
     #[tokio::test]
-    #[should_panic(expected = "Couldn't extract body.")]
-    async fn bq_client_query_panics_on_bad_body() {
-        // This tests the manual panic, not the expect.
-        // Not sure how to generate a redirect loop to test the first.
-        // This is fine.
+    async fn bq_client_query_errors_on_bad_body() {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(random_simple_ascii_string);
+            .returning(|| Ok(random_simple_ascii_string()));
         let mock_google = MockServer::start().await;
         let bq = BQClient::new(
             &random_simple_ascii_string(),
             mock_token,
             Some(&mock_google.uri()),
         )
-        .await;
+        .await
+        .unwrap();
         Mock::given(any())
             .respond_with(ResponseTemplate::new(200))
             .mount(&mock_google)
             .await;
-        bq.get_bq_results("").await;
+        let e = bq.get_bq_results("").await.err().unwrap();
+        assert!(e.to_string().contains("Couldn't extract body."));
     }
 
     #[tokio::test]
@@ -348,14 +444,15 @@ mod tests {
         let mut mock_token = MockGetAccessToken::new();
         mock_token
             .expect_get()
-            .returning(random_simple_ascii_string);
+            .returning(|| Ok(random_simple_ascii_string()));
         let mock_google = MockServer::start().await;
         let bq = BQClient::new(
             &random_simple_ascii_string(),
             mock_token,
             Some(&mock_google.uri()),
         )
-        .await;
+        .await
+        .unwrap();
 
         let response = ResponseTemplate::new(200).set_body_json(fixture_bigquery_response());
         Mock::given(any())
@@ -363,7 +460,10 @@ mod tests {
             .mount(&mock_google)
             .await;
 
-        let mut rs = bq.get_bq_results("SELECT * FROM `dataset.table`;").await;
+        let mut rs = bq
+            .get_bq_results("SELECT * FROM `dataset.table`;")
+            .await
+            .unwrap();
         assert_eq!(rs.row_count(), 3);
         struct TestItem {
             start_date: OffsetDateTime,
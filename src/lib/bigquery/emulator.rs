@@ -0,0 +1,393 @@
+use actix_web::{
+    dev::Server,
+    web::{get, post, put, resource, Data, Json, Path, Query},
+    App, HttpResponse, HttpServer,
+};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    net::TcpListener,
+    path::Path as FsPath,
+    sync::{Arc, Mutex},
+};
+
+// A stand in for BigQuery for local development and tests. Tables are lists of JSON
+// objects, loaded from <dataset>.<table>.ndjson files or replaced with
+// PUT /__tables__/{dataset.table}. Queries return every row of the table named in
+// backticks, e.g. SELECT * FROM `cjms_bigquery.subscriptions_v1`, with a schema
+// inferred from the values. RFC 3339 strings are TIMESTAMPs, and arrays of objects are
+// REPEATED RECORDs.
+
+#[derive(Clone, Debug)]
+pub struct EmulatorConfig {
+    // Rows per page of results
+    pub page_size: usize,
+    // How many times getQueryResults reports a job as incomplete before returning rows
+    pub incomplete_polls: usize,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig {
+            page_size: 1000,
+            incomplete_polls: 0,
+        }
+    }
+}
+
+pub type Table = Vec<Map<String, Value>>;
+
+struct EmulatedJob {
+    schema: Value,
+    rows: Vec<Value>,
+    polls_left: usize,
+}
+
+#[derive(Default)]
+pub struct EmulatorState {
+    pub tables: HashMap<String, Table>,
+    jobs: HashMap<String, EmulatedJob>,
+    next_job_id: u64,
+}
+
+impl EmulatorState {
+    pub fn with_tables(tables: HashMap<String, Table>) -> Self {
+        EmulatorState {
+            tables,
+            ..Default::default()
+        }
+    }
+}
+
+pub type SharedEmulatorState = Arc<Mutex<EmulatorState>>;
+
+pub fn parse_ndjson(ndjson: &str) -> Result<Table, String> {
+    ndjson
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| match serde_json::from_str(line) {
+            Ok(Value::Object(row)) => Ok(row),
+            Ok(_) => Err(format!("Line {} is not an object", i + 1)),
+            Err(e) => Err(format!("Line {} is not valid JSON. {}", i + 1, e)),
+        })
+        .collect()
+}
+
+// Loads every <dataset>.<table>.ndjson file in dir
+pub fn load_tables(dir: &FsPath) -> Result<HashMap<String, Table>, String> {
+    let mut tables = HashMap::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("Could not read {:?}. {}", dir, e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("ndjson") {
+            continue;
+        }
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let contents =
+            fs::read_to_string(&path).map_err(|e| format!("Could not read {:?}. {}", path, e))?;
+        let table = parse_ndjson(&contents).map_err(|e| format!("{:?}: {}", path, e))?;
+        tables.insert(name, table);
+    }
+    Ok(tables)
+}
+
+fn as_timestamp(value: &str) -> Option<String> {
+    // Formatted like BigQuery, e.g. 1.64790089E9
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| format!("{:E}", t.timestamp_millis() as f64 / 1000.0))
+}
+
+// From the column's non-null values. Arrays are REPEATED, of their elements' type, and
+// objects are RECORDs with a field for every key any of them has.
+fn field_schema(name: &str, values: &[&Value]) -> Value {
+    let field_type = match values.first() {
+        Some(Value::Array(_)) => {
+            let elements: Vec<&Value> = values
+                .iter()
+                .filter_map(|v| v.as_array())
+                .flatten()
+                .filter(|v| !v.is_null())
+                .collect();
+            let mut field = field_schema(name, &elements);
+            field["mode"] = json!("REPEATED");
+            return field;
+        }
+        Some(Value::Object(_)) => {
+            let objects: Vec<&Map<String, Value>> =
+                values.iter().filter_map(|v| v.as_object()).collect();
+            return json!({
+                "name": name,
+                "type": "RECORD",
+                "mode": "NULLABLE",
+                "fields": record_fields(&objects),
+            });
+        }
+        Some(Value::Bool(_)) => "BOOLEAN",
+        Some(Value::Number(n)) if n.is_i64() || n.is_u64() => "INTEGER",
+        Some(Value::Number(_)) => "FLOAT",
+        Some(Value::String(s)) if as_timestamp(s).is_some() => "TIMESTAMP",
+        _ => "STRING",
+    };
+    json!({"name": name, "type": field_type, "mode": "NULLABLE"})
+}
+
+fn record_fields(rows: &[&Map<String, Value>]) -> Vec<Value> {
+    let columns: BTreeSet<&String> = rows.iter().flat_map(|row| row.keys()).collect();
+    columns
+        .iter()
+        .map(|column| {
+            let values: Vec<&Value> = rows
+                .iter()
+                .filter_map(|row| row.get(*column))
+                .filter(|v| !v.is_null())
+                .collect();
+            field_schema(column, &values)
+        })
+        .collect()
+}
+
+// Cells are strings, like BigQuery sends them. A RECORD's are in the order of its
+// schema's fields.
+fn cell(value: Option<&Value>, field: &Value) -> Value {
+    let v = match value {
+        None | Some(Value::Null) => Value::Null,
+        Some(Value::String(s)) => Value::String(as_timestamp(s).unwrap_or_else(|| s.clone())),
+        Some(Value::Array(values)) => {
+            Value::Array(values.iter().map(|v| cell(Some(v), field)).collect())
+        }
+        Some(Value::Object(record)) => record_row(record, &field["fields"]),
+        Some(other) => Value::String(other.to_string()),
+    };
+    json!({ "v": v })
+}
+
+fn record_row(record: &Map<String, Value>, fields: &Value) -> Value {
+    let fields = fields.as_array().map(Vec::as_slice).unwrap_or_default();
+    let cells: Vec<Value> = fields
+        .iter()
+        .map(|field| cell(field["name"].as_str().and_then(|n| record.get(n)), field))
+        .collect();
+    json!({ "f": cells })
+}
+
+fn to_schema_and_rows(table: &Table) -> (Value, Vec<Value>) {
+    let rows: Vec<&Map<String, Value>> = table.iter().collect();
+    let fields = Value::Array(record_fields(&rows));
+    let rows = table.iter().map(|row| record_row(row, &fields)).collect();
+    (json!({ "fields": fields }), rows)
+}
+
+fn bigquery_error(code: u16, message: &str) -> HttpResponse {
+    let body = json!({"error": {"code": code, "message": message, "status": "INVALID_ARGUMENT"}});
+    match code {
+        404 => HttpResponse::NotFound().json(body),
+        _ => HttpResponse::BadRequest().json(body),
+    }
+}
+
+// The results page starting at page_token, or whether the job is still running
+fn job_response(
+    project: &str,
+    job_id: &str,
+    job: &EmulatedJob,
+    page_token: Option<&str>,
+    page_size: usize,
+) -> HttpResponse {
+    let job_reference = json!({"projectId": project, "jobId": job_id, "location": "US"});
+    if job.polls_left > 0 {
+        return HttpResponse::Ok().json(json!({
+            "kind": "bigquery#getQueryResultsResponse",
+            "jobReference": job_reference,
+            "jobComplete": false,
+        }));
+    }
+    let start = page_token.and_then(|t| t.parse().ok()).unwrap_or(0);
+    let end = (start + page_size.max(1)).min(job.rows.len());
+    let rows = job.rows.get(start..end).unwrap_or_default();
+    let mut body = json!({
+        "kind": "bigquery#getQueryResultsResponse",
+        "schema": job.schema,
+        "jobReference": job_reference,
+        "totalRows": job.rows.len().to_string(),
+        "rows": rows,
+        "totalBytesProcessed": "0",
+        "jobComplete": true,
+        "cacheHit": false,
+    });
+    if end < job.rows.len() {
+        body["pageToken"] = json!(end.to_string());
+    }
+    HttpResponse::Ok().json(body)
+}
+
+#[derive(Deserialize)]
+pub struct QueryRequest {
+    query: String,
+}
+
+pub async fn query(
+    project: Path<String>,
+    request: Json<QueryRequest>,
+    config: Data<EmulatorConfig>,
+    state: Data<Mutex<EmulatorState>>,
+) -> HttpResponse {
+    let table_name = match request.query.split('`').nth(1) {
+        Some(name) => name,
+        None => return bigquery_error(400, "Table names must be in backticks."),
+    };
+    let mut state = state.lock().expect("Emulator state poisoned");
+    let (schema, rows) = match state.tables.get(table_name) {
+        Some(table) => to_schema_and_rows(table),
+        None => return bigquery_error(404, &format!("Not found: Table {}", table_name)),
+    };
+    state.next_job_id += 1;
+    let job_id = format!("job_emulated_{}", state.next_job_id);
+    let job = EmulatedJob {
+        schema,
+        rows,
+        polls_left: config.incomplete_polls,
+    };
+    let response = job_response(&project, &job_id, &job, None, config.page_size);
+    state.jobs.insert(job_id, job);
+    response
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetQueryResultsParams {
+    page_token: Option<String>,
+}
+
+pub async fn get_query_results(
+    path: Path<(String, String)>,
+    params: Query<GetQueryResultsParams>,
+    config: Data<EmulatorConfig>,
+    state: Data<Mutex<EmulatorState>>,
+) -> HttpResponse {
+    let (project, job_id) = path.into_inner();
+    let mut state = state.lock().expect("Emulator state poisoned");
+    match state.jobs.get_mut(&job_id) {
+        Some(job) => {
+            let response = job_response(
+                &project,
+                &job_id,
+                job,
+                params.page_token.as_deref(),
+                config.page_size,
+            );
+            job.polls_left = job.polls_left.saturating_sub(1);
+            response
+        }
+        None => bigquery_error(404, &format!("Not found: Job {}", job_id)),
+    }
+}
+
+// Replaces a table with the NDJSON body
+pub async fn put_table(
+    table_name: Path<String>,
+    body: String,
+    state: Data<Mutex<EmulatorState>>,
+) -> HttpResponse {
+    match parse_ndjson(&body) {
+        Ok(table) => {
+            let n_rows = table.len();
+            let mut state = state.lock().expect("Emulator state poisoned");
+            state.tables.insert(table_name.into_inner(), table);
+            HttpResponse::Ok().json(json!({ "rows": n_rows }))
+        }
+        Err(e) => bigquery_error(400, &e),
+    }
+}
+
+pub fn run_emulator(
+    listener: TcpListener,
+    config: EmulatorConfig,
+    state: SharedEmulatorState,
+) -> Result<Server, std::io::Error> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(Data::new(config.clone()))
+            .app_data(Data::from(state.clone()))
+            .service(resource("/bigquery/v2/projects/{project}/queries").route(post().to(query)))
+            .service(
+                resource("/bigquery/v2/projects/{project}/queries/{job_id}")
+                    .route(get().to(get_query_results)),
+            )
+            .service(resource("/__tables__/{table}").route(put().to(put_table)))
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_and_rows_are_inferred_from_values() {
+        let table = parse_ndjson(
+            r#"{"id": "a", "amount": 5988, "created": "2022-03-21T22:14:50Z"}
+
+{"id": "b", "amount": null, "created": "2022-03-21T22:14:51Z", "reason": "fraudulent"}"#,
+        )
+        .unwrap();
+        let (schema, rows) = to_schema_and_rows(&table);
+        assert_eq!(
+            schema,
+            json!({"fields": [
+                {"name": "amount", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "created", "type": "TIMESTAMP", "mode": "NULLABLE"},
+                {"name": "id", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "reason", "type": "STRING", "mode": "NULLABLE"},
+            ]})
+        );
+        assert_eq!(
+            rows[0],
+            json!({"f": [{"v": "5988"}, {"v": "1.64790089E9"}, {"v": "a"}, {"v": null}]})
+        );
+        assert_eq!(rows[1]["f"][0], json!({ "v": null }));
+    }
+
+    #[test]
+    fn arrays_of_objects_are_repeated_records() {
+        let table = parse_ndjson(
+            r#"{"id": "a", "line_items": [{"plan_id": "x", "quantity": 2}, {"plan_id": "y", "amount": 100}]}
+{"id": "b", "line_items": []}
+{"id": "c", "line_items": null}"#,
+        )
+        .unwrap();
+        let (schema, rows) = to_schema_and_rows(&table);
+        assert_eq!(
+            schema["fields"][1],
+            json!({"name": "line_items", "type": "RECORD", "mode": "REPEATED", "fields": [
+                {"name": "amount", "type": "INTEGER", "mode": "NULLABLE"},
+                {"name": "plan_id", "type": "STRING", "mode": "NULLABLE"},
+                {"name": "quantity", "type": "INTEGER", "mode": "NULLABLE"},
+            ]})
+        );
+        assert_eq!(
+            rows[0]["f"][1],
+            json!({"v": [
+                {"v": {"f": [{"v": null}, {"v": "x"}, {"v": "2"}]}},
+                {"v": {"f": [{"v": "100"}, {"v": "y"}, {"v": null}]}},
+            ]})
+        );
+        assert_eq!(rows[1]["f"][1], json!({ "v": [] }));
+        assert_eq!(rows[2]["f"][1], json!({ "v": null }));
+    }
+
+    #[test]
+    fn parse_ndjson_rejects_lines_that_are_not_objects() {
+        assert!(parse_ndjson("[1, 2]").is_err());
+        assert!(parse_ndjson("{\"a\": ").is_err());
+    }
+}
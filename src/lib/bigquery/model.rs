@@ -56,6 +56,24 @@ pub enum BQError {
 
     #[error("BQError: Could not cast integer from i64 to i32")]
     IntegerCastUnsuccessful,
+
+    #[error("BQError: Did not successfully query bigquery. {0}")]
+    Transport(#[from] reqwest::Error),
+
+    #[error("BQError: Did not successfully query bigquery. (status: {status}, body: {body})")]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+
+    #[error("BQError: Couldn't extract body. {0}")]
+    Body(String),
+
+    #[error("BQError: Response has no job id.")]
+    NoJobId,
+
+    #[error("BQError: Job did not complete after {polls} polls.")]
+    JobIncomplete { polls: usize },
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -402,6 +420,55 @@ impl ResultSet {
         }
     }
 
+    // A REPEATED RECORD column as a result set with a row for each record, e.g. to read a
+    // subscription's line items. A null column has no records.
+    pub fn get_records(&self, col_index: usize) -> Result<ResultSet, BQError> {
+        let field = self
+            .query_response
+            .schema
+            .as_ref()
+            .and_then(|schema| schema.fields.as_ref())
+            .and_then(|fields| fields.get(col_index))
+            .ok_or(BQError::InvalidColumnIndex { col_index })?;
+        let invalid_type = |col_type: String| BQError::InvalidColumnType {
+            col_index,
+            col_type,
+            type_requested: "Records".into(),
+        };
+        let nested_fields = match (&field.r#type, &field.fields) {
+            (FieldType::Record | FieldType::Struct, Some(fields)) => fields.clone(),
+            (field_type, _) => return Err(invalid_type(format!("{:?}", field_type))),
+        };
+        let records = match self.get_json_value(col_index)? {
+            None | Some(Value::Null) => vec![],
+            Some(Value::Array(cells)) => cells,
+            Some(json_value) => return Err(invalid_type(ResultSet::json_type(&json_value))),
+        };
+        let rows = records
+            .into_iter()
+            .map(|cell| serde_json::from_value::<TableRow>(cell["v"].clone()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid_type("Array".into()))?;
+        Ok(ResultSet::new(QueryResponse {
+            job_complete: Some(true),
+            rows: Some(rows),
+            schema: Some(TableSchema {
+                fields: Some(nested_fields),
+            }),
+            ..Default::default()
+        }))
+    }
+
+    pub fn get_records_by_name(&self, col_name: &str) -> Result<ResultSet, BQError> {
+        let col_index = self.fields.get(col_name);
+        match col_index {
+            None => Err(BQError::InvalidColumnName {
+                col_name: col_name.into(),
+            }),
+            Some(col_index) => self.get_records(*col_index),
+        }
+    }
+
     pub fn get_json_value(&self, col_index: usize) -> Result<Option<serde_json::Value>, BQError> {
         if self.cursor < 0 || self.cursor == self.row_count {
             return Err(BQError::NoDataAvailable);
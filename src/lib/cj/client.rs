@@ -1,46 +1,156 @@
-use crate::{info, models::subscriptions::Subscription, settings::Settings, telemetry::LogKey};
+use crate::{
+    coupons::{format_coupons, CouponRules},
+    error::CjmsError,
+    info,
+    models::{refunds::Refund, subscriptions::Subscription},
+    money::{Decimal, Money},
+    network::{AffiliateNetwork, NetworkCommission, NetworkCommissionItem},
+    plans::PlanCatalog,
+    settings::Settings,
+    telemetry::LogKey,
+};
+use async_trait::async_trait;
+use chrono::DateTime;
 use rand::{thread_rng, Rng};
-use reqwest::{Client, Error, Response, Url};
+use reqwest::{header::RETRY_AFTER, Client, RequestBuilder, Response, StatusCode, Url};
 use serde::{de, Deserialize, Deserializer};
 use serde_json::{json, Value};
+use std::time::Duration as StdDuration;
+use thiserror::Error;
 use time::{Duration, OffsetDateTime};
 
-use super::country_codes::get_iso_code_3_from_iso_code_2;
+use super::{
+    country_codes::get_iso_code_3_from_iso_code_2,
+    programs::{CJProgram, CJPrograms},
+};
+
+// Longest we'll wait between attempts, whatever Retry-After says
+const MAX_RETRY_DELAY: StdDuration = StdDuration::from_secs(60);
+
+// Longest posting date range CJ allows in one Commission Detail query
+const MAX_QUERY_DAYS: i64 = 31;
+
+#[derive(Error, Debug)]
+pub enum CJError {
+    #[error("CJError: Request failed. {0}")]
+    Transport(#[from] reqwest::Error),
+
+    #[error("CJError: Unexpected status (status: {status}, body: {body})")]
+    Status { status: StatusCode, body: String },
+
+    #[error("CJError: GraphQL errors. {0}")]
+    GraphQL(String),
+
+    #[error("CJError: Response did not match schema. {0}")]
+    Schema(String),
+
+    #[error("CJError: No CJ program named {0}")]
+    UnknownProgram(String),
+}
+
+impl CJError {
+    // 5xx, 429, timeouts and failures to connect are worth another attempt
+    fn is_retryable(&self) -> bool {
+        match self {
+            CJError::Transport(e) => e.is_timeout() || e.is_connect(),
+            CJError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            CJError::GraphQL(_) | CJError::Schema(_) | CJError::UnknownProgram(_) => false,
+        }
+    }
+}
 
 pub struct CJClient {
-    advertiser_id: String,
     client: reqwest::Client,
-    cj_cid: String,
-    cj_type: String,
-    cj_signature: String,
+    max_retries: u32,
+    retry_base_delay: StdDuration,
     commission_detail_endpoint: Url,
-    commission_detail_api_token: String,
     s2s_endpoint: Url,
     random_minutes: Duration,
+    plans: PlanCatalog,
+    coupon_rules: CouponRules,
+    programs: CJPrograms,
+    publisher_currency: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CommissionDetailItem {
     pub sku: String,
+    #[serde(default)]
+    pub quantity: Option<i32>,
+    #[serde(default)]
+    pub per_item_sale_amount_pub_currency: Option<Decimal>,
+    // Taken off the line by CJ, for the coupon on the conversion
+    #[serde(default)]
+    pub discount_pub_currency: Option<Decimal>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CommissionDetailRecord {
+    pub commission_id: String,
+    #[serde(deserialize_with = "offset_date_time_from_str")]
+    pub posting_date: OffsetDateTime,
     pub original: bool,
     pub order_id: String,
     pub correction_reason: Option<String>,
-    #[serde(deserialize_with = "f32_from_str")]
-    pub sale_amount_pub_currency: f32,
+    pub sale_amount_pub_currency: Decimal,
+    #[serde(default)]
+    pub pub_commission_amount_pub_currency: Option<Decimal>,
+    pub action_status: Option<String>,
+    pub validation_status: Option<String>,
+    pub publisher_id: Option<String>,
+    pub coupon: Option<String>,
     pub items: Vec<CommissionDetailItem>,
 }
 
+impl CommissionDetailRecord {
+    // CJ's amounts are decimals in publisher_currency
+    pub fn to_network_commission(&self, publisher_currency: &str) -> NetworkCommission {
+        let money = |d: &Decimal| Money::from_decimal(d, publisher_currency);
+        NetworkCommission {
+            id: self.commission_id.clone(),
+            order_id: self.order_id.clone(),
+            posting_date: self.posting_date,
+            original: self.original,
+            correction_reason: self.correction_reason.clone(),
+            sale_amount: money(&self.sale_amount_pub_currency),
+            commission_amount: self.pub_commission_amount_pub_currency.as_ref().map(money),
+            status: self.action_status.clone(),
+            validation_status: self.validation_status.clone(),
+            publisher_id: self.publisher_id.clone(),
+            items: self
+                .items
+                .iter()
+                .map(|item| NetworkCommissionItem {
+                    sku: item.sku.clone(),
+                    quantity: item.quantity,
+                    discount: item.discount_pub_currency.as_ref().map(money),
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CommissionDetailRecordSet {
     pub count: usize,
+    // False if there are more records after max_commission_id. A response without it is
+    // the only page.
+    #[serde(default = "default_payload_complete")]
+    pub payload_complete: bool,
+    #[serde(default)]
+    pub max_commission_id: Option<String>,
     pub records: Vec<CommissionDetailRecord>,
 }
 
+fn default_payload_complete() -> bool {
+    true
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AdvertiserCommissions {
@@ -53,16 +163,27 @@ struct CommissionDetailQueryResponse {
     errors: Option<Value>,
 }
 
-pub fn convert_amount_to_decimal(plan_amount: i32) -> f32 {
-    plan_amount as f32 / 100.0
-}
-
-fn f32_from_str<'de, D>(deserializer: D) -> Result<f32, D::Error>
+// CJ sends RFC 3339 dates, e.g. 2022-06-01T12:00:00Z
+fn offset_date_time_from_str<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
-    s.parse::<f32>().map_err(de::Error::custom)
+    let parsed = DateTime::parse_from_rfc3339(&s).map_err(de::Error::custom)?;
+    Ok(OffsetDateTime::from_unix_timestamp(parsed.timestamp()))
+}
+
+// Seconds form only. CJ doesn't send the HTTP-date form.
+fn get_retry_after(resp: &Response) -> Option<StdDuration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse::<u64>().ok().map(StdDuration::from_secs)
+}
+
+// Full jitter: a random delay up to base * 2^(attempt - 1)
+fn get_backoff_with_jitter(base: StdDuration, attempt: u32) -> StdDuration {
+    let max = base.saturating_mul(2u32.saturating_pow(attempt - 1));
+    let mut rng = thread_rng();
+    max.mul_f64(rng.gen_range(0.0..=1.0))
 }
 
 fn get_random_minutes() -> Duration {
@@ -77,22 +198,39 @@ impl CJClient {
         s2s_endpoint: Option<&str>,
         commission_detail_endpoint: Option<&str>,
         random_minutes: Option<Duration>,
-    ) -> CJClient {
+    ) -> Result<CJClient, CjmsError> {
         let s2s_endpoint = s2s_endpoint.unwrap_or("https://www.emjcd.com/u");
         let commission_detail_endpoint =
             commission_detail_endpoint.unwrap_or("https://commissions.api.cj.com/query");
-        CJClient {
-            advertiser_id: settings.cj_sftp_user.clone(),
-            client: Client::new(),
-            cj_cid: settings.cj_cid.clone(),
-            cj_type: settings.cj_type.clone(),
-            cj_signature: settings.cj_signature.clone(),
-            commission_detail_endpoint: Url::parse(commission_detail_endpoint)
-                .expect("Could not parse commission_detail_endpoint"),
-            commission_detail_api_token: settings.cj_api_access_token.clone(),
-            s2s_endpoint: Url::parse(s2s_endpoint).expect("Could not parse s2s_endpoint"),
+        let parse_endpoint = |name: &str, endpoint: &str| {
+            Url::parse(endpoint).map_err(|e| {
+                CjmsError::Configuration(format!("Could not parse {} {}. {}", name, endpoint, e))
+            })
+        };
+        Ok(CJClient {
+            client: Client::builder()
+                .timeout(StdDuration::from_secs(settings.cj_timeout_seconds))
+                .build()
+                .map_err(|e| {
+                    CjmsError::Configuration(format!("Could not build CJ http client. {}", e))
+                })?,
+            max_retries: settings.cj_max_retries,
+            retry_base_delay: StdDuration::from_millis(settings.cj_retry_base_delay_ms),
+            commission_detail_endpoint: parse_endpoint(
+                "cj_commission_detail_endpoint",
+                commission_detail_endpoint,
+            )?,
+            s2s_endpoint: parse_endpoint("cj_s2s_endpoint", s2s_endpoint)?,
             random_minutes: random_minutes.unwrap_or_else(get_random_minutes),
-        }
+            plans: settings.plans.clone(),
+            coupon_rules: settings.coupon_rules.clone(),
+            programs: CJPrograms::new(settings)?,
+            publisher_currency: settings.publisher_currency.clone(),
+        })
+    }
+
+    pub fn programs(&self) -> &CJPrograms {
+        &self.programs
     }
 
     fn randomize_and_format_event_time(&self, original_event_time: OffsetDateTime) -> String {
@@ -104,68 +242,176 @@ impl CJClient {
 
     fn get_url_for_sub(&self, sub: &Subscription) -> Url {
         let event_time = self.randomize_and_format_event_time(sub.subscription_created);
+        let program = self.programs.for_plan(&sub.plan_id);
         let mut url_for_sub = self.s2s_endpoint.clone();
         url_for_sub
             .query_pairs_mut()
-            .append_pair("CID", &self.cj_cid)
-            .append_pair("TYPE", &self.cj_type)
-            .append_pair("SIGNATURE", &self.cj_signature)
-            .append_pair("METHOD", "S2S")
+            .append_pair("CID", &program.cj_cid)
             .append_pair(
-                "CJEVENT",
-                sub.cj_event_value.as_ref().unwrap_or(&String::from("n/a")),
+                "TYPE",
+                self.coupon_rules
+                    .cj_type(&sub.coupons)
+                    .unwrap_or(&program.cj_type),
             )
+            .append_pair("SIGNATURE", &program.cj_signature)
+            .append_pair("METHOD", "S2S")
+            .append_pair("CJEVENT", self.click_id(sub).unwrap_or("n/a"))
             .append_pair("EVENTTIME", &event_time)
             .append_pair("OID", &sub.id.to_string())
             .append_pair("CURRENCY", &sub.plan_currency)
-            .append_pair("ITEM1", &sub.plan_id)
-            .append_pair(
-                "AMT1",
-                &format!("{}", convert_amount_to_decimal(sub.plan_amount)),
-            )
-            .append_pair("QTY1", &format!("{}", sub.quantity))
             .append_pair(
                 "CUST_COUNTRY",
                 get_iso_code_3_from_iso_code_2(sub.country.as_ref().unwrap_or(&String::from(""))),
             )
-            .append_pair("COUPON", sub.coupons.as_ref().unwrap_or(&String::from("")));
+            .append_pair("COUPON", &format_coupons(&sub.coupons));
+        // ITEMn, AMTn, QTYn and DCNTn for each item, numbered from 1
+        for (i, item) in self.coupon_rules.reported_items(sub).iter().enumerate() {
+            let n = i + 1;
+            let money =
+                |amount: i32| Money::new(amount.into(), &sub.plan_currency).to_decimal_string();
+            url_for_sub
+                .query_pairs_mut()
+                .append_pair(&format!("ITEM{}", n), self.plans.cj_sku(&item.plan_id))
+                .append_pair(&format!("AMT{}", n), &money(item.amount))
+                .append_pair(&format!("QTY{}", n), &item.quantity.to_string())
+                .append_pair(&format!("DCNT{}", n), &money(item.discount));
+        }
         url_for_sub
     }
 
-    pub async fn report_subscription(&self, sub: &Subscription) -> Result<Response, Error> {
+    // Sends the request built by make_request, retrying retryable failures up to
+    // max_retries times. Any non-2xx status is an error.
+    async fn send_with_retries<F>(&self, make_request: F) -> Result<Response, CJError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            let (error, retry_after) = match make_request().send().await {
+                Ok(resp) if resp.status().is_success() => return Ok(resp),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let retry_after = get_retry_after(&resp);
+                    let body = resp.text().await.unwrap_or_default();
+                    (CJError::Status { status, body }, retry_after)
+                }
+                Err(e) => (CJError::Transport(e), None),
+            };
+            if attempt >= self.max_retries || !error.is_retryable() {
+                return Err(error);
+            }
+            attempt += 1;
+            let delay = retry_after
+                .unwrap_or_else(|| get_backoff_with_jitter(self.retry_base_delay, attempt))
+                .min(MAX_RETRY_DELAY);
+            info!(
+                LogKey::CjRequestRetry,
+                error = error.to_string().as_str(),
+                attempt = attempt,
+                delay_ms = delay.as_millis() as u64,
+                "Retrying CJ request"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    pub async fn report_subscription(&self, sub: &Subscription) -> Result<Response, CJError> {
         let url_for_sub = self.get_url_for_sub(sub);
-        self.client.get(url_for_sub).send().await
+        self.send_with_retries(|| self.client.get(url_for_sub.clone()))
+            .await
     }
 
+    // Splits min to max into windows CJ allows and pages through each with
+    // sinceCommissionId, merging every record for program's advertiser into one set.
     pub async fn query_commission_detail_api_between_dates(
         &self,
+        program: &CJProgram,
         min: OffsetDateTime,
         max: OffsetDateTime,
-    ) -> CommissionDetailRecordSet {
-        // We format to the beginning of the day of min and the beginning of the next day of max
-        let format_string = "%FT00:00:00Z";
-        let since = min.format(format_string);
-        let before = (max + Duration::days(1)).format(format_string);
+    ) -> Result<CommissionDetailRecordSet, CJError> {
+        let mut records = vec![];
+        let mut max_commission_id = None;
+        for (since, before) in get_query_windows(min, max) {
+            let mut since_commission_id: Option<String> = None;
+            loop {
+                let page = self
+                    .query_commission_detail_page(
+                        program,
+                        &since,
+                        &before,
+                        since_commission_id.as_deref(),
+                    )
+                    .await?;
+                records.extend(page.records);
+                if page.max_commission_id.is_some() {
+                    max_commission_id = page.max_commission_id.clone();
+                }
+                if page.payload_complete {
+                    break;
+                }
+                // Guard against looping forever on a cursor that doesn't move
+                match page.max_commission_id {
+                    Some(id) if Some(&id) != since_commission_id.as_ref() => {
+                        since_commission_id = Some(id);
+                    }
+                    _ => {
+                        return Err(CJError::Schema(
+                            "Incomplete payload without a new maxCommissionId.".to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(CommissionDetailRecordSet {
+            count: records.len(),
+            payload_complete: true,
+            max_commission_id,
+            records,
+        })
+    }
+
+    async fn query_commission_detail_page(
+        &self,
+        program: &CJProgram,
+        since: &str,
+        before: &str,
+        since_commission_id: Option<&str>,
+    ) -> Result<CommissionDetailRecordSet, CJError> {
+        let since_commission_id = since_commission_id
+            .map(|id| format!("\n            sinceCommissionId:\"{}\",", id))
+            .unwrap_or_default();
         // Format query
         let query = format!(
             r#"{{
         advertiserCommissions(
             forAdvertisers: ["{}"],
             sincePostingDate:"{}",
-            beforePostingDate:"{}",
+            beforePostingDate:"{}",{}
         ) {{
             count
+            payloadComplete
+            maxCommissionId
             records {{
+                commissionId
+                postingDate
                 original
                 orderId
                 correctionReason
                 saleAmountPubCurrency
+                pubCommissionAmountPubCurrency
+                actionStatus
+                validationStatus
+                publisherId
+                coupon
                 items {{
                     sku
+                    quantity
+                    perItemSaleAmountPubCurrency
+                    discountPubCurrency
                 }}
             }}
         }}}}"#,
-            self.advertiser_id, since, before
+            program.cj_sftp_user, since, before, since_commission_id
         );
         info!(
             LogKey::VerifyReportsQuery,
@@ -173,55 +419,122 @@ impl CJClient {
             "Query sent to CommissionDetail API"
         );
         // Make request
+        let body = json!({ "query": query });
         let resp = self
-            .client
-            .post(self.commission_detail_endpoint.clone())
-            .header(
-                "Authorization",
-                format!("Bearer {}", self.commission_detail_api_token),
-            )
-            .json(&json!({ "query": query }))
-            .send()
-            .await
-            .expect("Call to CJ failed.");
-        if resp.status() != 200 {
-            panic!("CJ did not return a 200. {:?}", resp)
-        }
+            .send_with_retries(|| {
+                self.client
+                    .post(self.commission_detail_endpoint.clone())
+                    .header(
+                        "Authorization",
+                        format!(
+                            "Bearer {}",
+                            program.cj_api_access_token.as_deref().unwrap_or_default()
+                        ),
+                    )
+                    .json(&body)
+            })
+            .await?;
         // Parse and handle the response
-        let query_result: CommissionDetailQueryResponse = match resp.json().await {
-            Ok(data) => data,
-            Err(e) => {
-                panic!("Could not deserialize data from CJ call. {}", e);
-            }
-        };
+        let bytes = resp.bytes().await?;
+        let query_result: CommissionDetailQueryResponse =
+            serde_json::from_slice(&bytes).map_err(|e| CJError::Schema(e.to_string()))?;
         match query_result.data {
             Some(data) => {
                 info!(
                     LogKey::VerifyReports,
                     "Successfully received data from CommissionDetail API."
                 );
-                data.advertiser_commissions
+                Ok(data.advertiser_commissions)
             }
             None => match query_result.errors {
-                Some(error) => {
-                    panic!("Got no data from CJ. {}", error);
-                }
-                None => {
-                    panic!("Got no data and no errors from CJ.");
-                }
+                Some(errors) => Err(CJError::GraphQL(errors.to_string())),
+                None => Err(CJError::Schema("Got no data and no errors.".to_string())),
             },
         }
     }
 }
 
-#[cfg(test)]
-pub mod test_telemetry {
-    use super::*;
+// Accounts are CJ programs, by name
+#[async_trait]
+impl AffiliateNetwork for CJClient {
+    type Error = CJError;
 
-    #[test]
-    fn test_convert_plan_amount_to_decimal() {
-        assert_eq!(convert_amount_to_decimal(999), 9.99);
-        assert_eq!(convert_amount_to_decimal(5988), 59.88);
+    fn accounts(&self) -> Vec<&str> {
+        self.programs
+            .all()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect()
+    }
+
+    // The cjevent the AIC endpoint stored
+    fn click_id<'a>(&self, sub: &'a Subscription) -> Option<&'a str> {
+        sub.cj_event_value.as_deref()
+    }
+
+    fn account_for_plan(&self, plan_id: &str) -> &str {
+        &self.programs.for_plan(plan_id).name
+    }
+
+    async fn report_conversion(&self, sub: &Subscription) -> Result<(), CJError> {
+        self.report_subscription(sub).await.map(|_| ())
+    }
+
+    // The S2S URL, without the program's SIGNATURE
+    fn describe_conversion(&self, sub: &Subscription) -> String {
+        let url = self.get_url_for_sub(sub);
+        let mut redacted = url.clone();
+        redacted
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(url.query_pairs().map(|(key, value)| match key.as_ref() {
+                "SIGNATURE" => (key, "REDACTED".into()),
+                _ => (key, value),
+            }));
+        redacted.to_string()
+    }
+
+    // CJ fetches the corrections file for the refund's correction_file_date, see
+    // controllers::corrections
+    async fn submit_correction(&self, _refund: &Refund) -> Result<(), CJError> {
+        Ok(())
+    }
+
+    async fn query_commissions(
+        &self,
+        account: &str,
+        min: OffsetDateTime,
+        max: OffsetDateTime,
+    ) -> Result<Vec<NetworkCommission>, CJError> {
+        let program = self
+            .programs
+            .get(account)
+            .ok_or_else(|| CJError::UnknownProgram(account.to_string()))?;
+        let result = self
+            .query_commission_detail_api_between_dates(program, min, max)
+            .await?;
+        Ok(result
+            .records
+            .iter()
+            .map(|r| r.to_network_commission(&self.publisher_currency))
+            .collect())
+    }
+}
+
+// Formatted (sincePostingDate, beforePostingDate) pairs, from the beginning of the day
+// of min to the beginning of the day after max, at most MAX_QUERY_DAYS each.
+fn get_query_windows(min: OffsetDateTime, max: OffsetDateTime) -> Vec<(String, String)> {
+    let format_string = "%FT00:00:00Z";
+    let end = max.date().next_day();
+    let mut since = min.date();
+    let mut windows = vec![];
+    loop {
+        let before = (since + Duration::days(MAX_QUERY_DAYS)).min(end);
+        windows.push((since.format(format_string), before.format(format_string)));
+        if before >= end {
+            return windows;
+        }
+        since = before;
     }
 }
 
@@ -229,27 +542,95 @@ pub mod test_telemetry {
 mod tests {
 
     use time::{date, time, PrimitiveDateTime};
+    use wiremock::{
+        matchers::{body_string_contains, path},
+        Mock, MockServer, ResponseTemplate,
+    };
 
     use super::*;
     use crate::{
-        models::subscriptions::test_subscriptions::make_fake_sub, test_utils::empty_settings,
+        cj::programs::DEFAULT_PROGRAM,
+        coupons::CouponRule,
+        models::subscriptions::{test_subscriptions::make_fake_sub, LineItem},
+        plans::Plan,
+        test_utils::empty_settings,
     };
 
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn default_program(cj: &CJClient) -> &CJProgram {
+        cj.programs().get(DEFAULT_PROGRAM).unwrap()
+    }
+
     #[test]
-    fn commission_detail_record_parses_f32() {
+    fn commission_detail_record_parses_decimals() {
         let json = json!({
+            "commissionId": "1",
+            "postingDate": "2022-06-01T12:00:00Z",
             "original": true,
             "orderId": "abc123",
             "saleAmountPubCurrency": "9.99",
             "items": vec![json!({"sku": "abc123"})],
         });
         let result = serde_json::from_value::<CommissionDetailRecord>(json).unwrap();
-        assert_eq!(result.sale_amount_pub_currency, 9.99f32)
+        assert_eq!(result.sale_amount_pub_currency, decimal("9.99"));
+        assert_eq!(result.pub_commission_amount_pub_currency, None);
+    }
+
+    #[test]
+    fn commission_detail_record_parses_payout_fields() {
+        let json = json!({
+            "commissionId": "2980311457",
+            "postingDate": "2022-06-01T12:34:56Z",
+            "original": true,
+            "orderId": "abc123",
+            "correctionReason": null,
+            "saleAmountPubCurrency": "59.88",
+            "pubCommissionAmountPubCurrency": "5.99",
+            "actionStatus": "locked",
+            "validationStatus": "ACCEPTED",
+            "publisherId": "100357191",
+            "coupon": "SPRING",
+            "items": vec![json!({
+                "sku": "abc123",
+                "quantity": 2,
+                "perItemSaleAmountPubCurrency": "29.94",
+                "discountPubCurrency": "0.00",
+            })],
+        });
+        let result = serde_json::from_value::<CommissionDetailRecord>(json).unwrap();
+        assert_eq!(result.commission_id, "2980311457");
+        assert_eq!(result.coupon, Some("SPRING".to_string()));
+        assert_eq!(result.items[0].quantity, Some(2));
+        assert_eq!(
+            result.items[0].per_item_sale_amount_pub_currency,
+            Some(decimal("29.94"))
+        );
+        assert_eq!(result.items[0].discount_pub_currency, Some(decimal("0.00")));
+        assert_eq!(
+            result.posting_date,
+            PrimitiveDateTime::new(date!(2022 - 06 - 01), time!(12:34:56)).assume_utc()
+        );
+        assert_eq!(
+            result.pub_commission_amount_pub_currency,
+            Some(decimal("5.99"))
+        );
+        assert_eq!(result.action_status, Some("locked".to_string()));
+        assert_eq!(result.validation_status, Some("ACCEPTED".to_string()));
+        assert_eq!(result.publisher_id, Some("100357191".to_string()));
+        assert_eq!(
+            Money::from_decimal(&result.sale_amount_pub_currency, "usd"),
+            Money::new(5988, "usd")
+        );
     }
 
     #[test]
-    fn commission_detail_record_parses_f32_err_on_invalid_value() {
+    fn commission_detail_record_parses_decimals_err_on_invalid_value() {
         let json = json!({
+            "commissionId": "1",
+            "postingDate": "2022-06-01T12:00:00Z",
             "original": true,
             "orderId": "abc123",
             "saleAmountPubCurrency": "notgood",
@@ -263,7 +644,7 @@ mod tests {
     fn random_minutes_should_be_set_on_cjclient_if_passed() {
         // This is used for tests settings
         let settings = empty_settings();
-        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(88)));
+        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(88))).unwrap();
         assert_eq!(cj.random_minutes.whole_minutes(), 88);
     }
 
@@ -271,7 +652,7 @@ mod tests {
     fn random_minutes_should_be_greated_than_15_and_less_than_60_if_on_cjclient_if_not_passed() {
         let settings = empty_settings();
         for _ in 0..10 {
-            let cj = CJClient::new(&settings, None, None, None);
+            let cj = CJClient::new(&settings, None, None, None).unwrap();
             let minutes = cj.random_minutes.whole_minutes();
             assert!(
                 minutes >= 15,
@@ -289,20 +670,282 @@ mod tests {
     #[test]
     fn randomize_and_format_event_time_adds_minutes_and_formats_string_correctly() {
         let settings = empty_settings();
-        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(9)));
+        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(9))).unwrap();
         let event_time =
             PrimitiveDateTime::new(date!(2019 - 01 - 01), time!(11:11:11.111111)).assume_utc();
         let result = cj.randomize_and_format_event_time(event_time);
         assert_eq!(result, "2019-01-01T11:20:00.000Z");
     }
 
+    #[test]
+    fn backoff_with_jitter_is_at_most_base_doubled_per_attempt() {
+        let base = StdDuration::from_millis(100);
+        for _ in 0..10 {
+            assert!(get_backoff_with_jitter(base, 1) <= StdDuration::from_millis(100));
+            assert!(get_backoff_with_jitter(base, 3) <= StdDuration::from_millis(400));
+        }
+    }
+
+    fn commission_detail_response() -> Value {
+        json!({"data": {"advertiserCommissions": {"count": 0, "records": []}}})
+    }
+
+    async fn query(settings: &Settings, mock_cj: &MockServer) -> Result<usize, CJError> {
+        let cj = CJClient::new(settings, None, Some(&mock_cj.uri()), None).unwrap();
+        let now = OffsetDateTime::now_utc();
+        cj.query_commission_detail_api_between_dates(default_program(&cj), now, now)
+            .await
+            .map(|r| r.count)
+    }
+
+    #[tokio::test]
+    async fn query_retries_server_errors() {
+        let mut settings = empty_settings();
+        settings.cj_max_retries = 3;
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_cj)
+            .await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(commission_detail_response()))
+            .expect(1)
+            .mount(&mock_cj)
+            .await;
+        assert_eq!(query(&settings, &mock_cj).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn query_gives_up_after_max_retries() {
+        let mut settings = empty_settings();
+        settings.cj_max_retries = 2;
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_cj)
+            .await;
+        let result = query(&settings, &mock_cj).await;
+        assert!(matches!(
+            result,
+            Err(CJError::Status {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn query_honors_retry_after_on_429() {
+        let mut settings = empty_settings();
+        settings.cj_max_retries = 1;
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&mock_cj)
+            .await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(commission_detail_response()))
+            .mount(&mock_cj)
+            .await;
+        let start = std::time::Instant::now();
+        assert!(query(&settings, &mock_cj).await.is_ok());
+        assert!(start.elapsed() >= StdDuration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn query_does_not_retry_client_errors() {
+        let mut settings = empty_settings();
+        settings.cj_max_retries = 3;
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("bad token"))
+            .expect(1)
+            .mount(&mock_cj)
+            .await;
+        match query(&settings, &mock_cj).await {
+            Err(CJError::Status { status, body }) => {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(body, "bad token");
+            }
+            other => panic!("Expected a status error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_times_out() {
+        let mut settings = empty_settings();
+        settings.cj_timeout_seconds = 1;
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(commission_detail_response())
+                    .set_delay(StdDuration::from_secs(3)),
+            )
+            .mount(&mock_cj)
+            .await;
+        match query(&settings, &mock_cj).await {
+            Err(CJError::Transport(e)) => assert!(e.is_timeout()),
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_distinguishes_graphql_and_schema_errors() {
+        let settings = empty_settings();
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"errors": [{"message": "Bad query"}]})),
+            )
+            .up_to_n_times(1)
+            .mount(&mock_cj)
+            .await;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"data": {"count": 1}})))
+            .mount(&mock_cj)
+            .await;
+        assert!(matches!(
+            query(&settings, &mock_cj).await,
+            Err(CJError::GraphQL(_))
+        ));
+        assert!(matches!(
+            query(&settings, &mock_cj).await,
+            Err(CJError::Schema(_))
+        ));
+    }
+
+    #[test]
+    fn query_windows_cover_min_to_day_after_max_in_allowed_spans() {
+        let min = PrimitiveDateTime::new(date!(2022 - 01 - 01), time!(13:00)).assume_utc();
+        assert_eq!(
+            get_query_windows(min, min),
+            vec![(
+                "2022-01-01T00:00:00Z".to_string(),
+                "2022-01-02T00:00:00Z".to_string()
+            )]
+        );
+        let max = PrimitiveDateTime::new(date!(2022 - 03 - 10), time!(01:00)).assume_utc();
+        assert_eq!(
+            get_query_windows(min, max),
+            vec![
+                (
+                    "2022-01-01T00:00:00Z".to_string(),
+                    "2022-02-01T00:00:00Z".to_string()
+                ),
+                (
+                    "2022-02-01T00:00:00Z".to_string(),
+                    "2022-03-04T00:00:00Z".to_string()
+                ),
+                (
+                    "2022-03-04T00:00:00Z".to_string(),
+                    "2022-03-11T00:00:00Z".to_string()
+                ),
+            ]
+        );
+    }
+
+    fn commission_detail_page(complete: bool, max_commission_id: &str, order_id: &str) -> Value {
+        json!({"data": {"advertiserCommissions": {
+            "count": 1,
+            "payloadComplete": complete,
+            "maxCommissionId": max_commission_id,
+            "records": [{
+                "commissionId": max_commission_id,
+                "postingDate": "2022-06-01T12:00:00Z",
+                "original": true,
+                "orderId": order_id,
+                "correctionReason": null,
+                "saleAmountPubCurrency": "9.99",
+                "items": [{"sku": "sku"}],
+            }],
+        }}})
+    }
+
+    #[tokio::test]
+    async fn query_pages_with_since_commission_id_and_merges_records() {
+        let settings = empty_settings();
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .and(body_string_contains(r#"sinceCommissionId:\"100\""#))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(commission_detail_page(true, "200", "b")),
+            )
+            .expect(1)
+            .mount(&mock_cj)
+            .await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(commission_detail_page(false, "100", "a")),
+            )
+            .expect(1)
+            .mount(&mock_cj)
+            .await;
+        let cj = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+        let now = OffsetDateTime::now_utc();
+        let result = cj
+            .query_commission_detail_api_between_dates(default_program(&cj), now, now)
+            .await
+            .unwrap();
+        assert_eq!(result.count, 2);
+        let order_ids: Vec<&str> = result.records.iter().map(|r| r.order_id.as_str()).collect();
+        assert_eq!(order_ids, vec!["a", "b"]);
+        assert_eq!(result.max_commission_id, Some("200".to_string()));
+    }
+
+    #[tokio::test]
+    async fn query_splits_long_ranges_into_windows() {
+        let settings = empty_settings();
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(commission_detail_page(true, "1", "a")),
+            )
+            .expect(3)
+            .mount(&mock_cj)
+            .await;
+        let cj = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+        let now = OffsetDateTime::now_utc();
+        let result = cj
+            .query_commission_detail_api_between_dates(
+                default_program(&cj),
+                now - Duration::days(70),
+                now,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.count, 3);
+    }
+
+    #[tokio::test]
+    async fn query_errors_if_cursor_does_not_move() {
+        let settings = empty_settings();
+        let mock_cj = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(commission_detail_page(false, "100", "a")),
+            )
+            .expect(2)
+            .mount(&mock_cj)
+            .await;
+        assert!(matches!(
+            query(&settings, &mock_cj).await,
+            Err(CJError::Schema(_))
+        ));
+    }
+
     #[test]
     fn event_time_in_url_should_by_randomized_by_duration() {
         let mut sub = make_fake_sub();
         sub.subscription_created =
             PrimitiveDateTime::new(date!(2021 - 12 - 31), time!(23:59:59.999999)).assume_utc();
         let settings = empty_settings();
-        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(44)));
+        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(44))).unwrap();
         let url = cj.get_url_for_sub(&sub);
         for (key, value) in url.query_pairs() {
             if key == "EVENTTIME" {
@@ -310,4 +953,137 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn endpoints_that_cannot_be_parsed_are_configuration_errors() {
+        let settings = empty_settings();
+        for (s2s, commission_detail) in [(Some("not a url"), None), (None, Some("not a url"))] {
+            match CJClient::new(&settings, s2s, commission_detail, None) {
+                Err(CjmsError::Configuration(_)) => {}
+                Err(e) => panic!("Expected a configuration error, got {:?}", e),
+                Ok(_) => panic!("Expected a configuration error"),
+            }
+        }
+    }
+
+    #[test]
+    fn item_in_url_is_the_cj_sku_from_the_plan_catalog() {
+        let mut sub = make_fake_sub();
+        let mut settings = empty_settings();
+        let item = |cj: &CJClient, sub: &Subscription| {
+            cj.get_url_for_sub(sub)
+                .query_pairs()
+                .find(|(key, _)| key == "ITEM1")
+                .map(|(_, value)| value.to_string())
+        };
+        let cj = CJClient::new(&settings, None, None, None).unwrap();
+        assert_eq!(item(&cj, &sub), Some(sub.plan_id.clone()));
+        sub.plan_id = "price_Monthly".to_string();
+        settings.plans = PlanCatalog(vec![Plan {
+            plan_id: "price_Monthly".to_string(),
+            cj_sku: Some("vpn-monthly".to_string()),
+            product_name: None,
+            eligible: true,
+            ineligible_reason: None,
+            cj_program: None,
+        }]);
+        let cj = CJClient::new(&settings, None, None, None).unwrap();
+        assert_eq!(item(&cj, &sub), Some("vpn-monthly".to_string()));
+    }
+
+    #[test]
+    fn coupons_and_coupon_rules_in_url() {
+        let mut sub = make_fake_sub();
+        sub.plan_amount = 999;
+        sub.plan_currency = "usd".to_string();
+        sub.coupons = vec!["SPRING".to_string(), "HALF".to_string()];
+        let mut settings = empty_settings();
+        let param = |cj: &CJClient, name: &str| {
+            cj.get_url_for_sub(&sub)
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+                .unwrap()
+        };
+        let cj = CJClient::new(&settings, None, None, None).unwrap();
+        assert_eq!(param(&cj, "COUPON"), "SPRING,HALF");
+        assert_eq!(param(&cj, "AMT1"), "9.99");
+        assert_eq!(param(&cj, "TYPE"), settings.cj_type);
+        settings.coupon_rules = CouponRules(vec![CouponRule {
+            code: "half".to_string(),
+            exclude: false,
+            reason: None,
+            amount: Some(499),
+            cj_type: Some("half-price".to_string()),
+        }]);
+        let cj = CJClient::new(&settings, None, None, None).unwrap();
+        assert_eq!(param(&cj, "AMT1"), "4.99");
+        assert_eq!(param(&cj, "TYPE"), "half-price");
+    }
+
+    #[test]
+    fn each_line_item_is_numbered_in_url() {
+        let mut sub = make_fake_sub();
+        sub.plan_currency = "usd".to_string();
+        sub.set_line_items(&[
+            LineItem {
+                plan_id: "bundle".to_string(),
+                quantity: 1,
+                amount: 1000,
+                discount: 150,
+            },
+            LineItem {
+                plan_id: "add-on".to_string(),
+                quantity: 3,
+                amount: 250,
+                discount: 0,
+            },
+        ]);
+        let cj = CJClient::new(&empty_settings(), None, None, None).unwrap();
+        let url = cj.get_url_for_sub(&sub);
+        let params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| {
+                ["ITEM", "AMT", "QTY", "DCNT"]
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix))
+            })
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let expected = [
+            ("ITEM1", "bundle"),
+            ("AMT1", "10.00"),
+            ("QTY1", "1"),
+            ("DCNT1", "1.50"),
+            ("ITEM2", "add-on"),
+            ("AMT2", "2.50"),
+            ("QTY2", "3"),
+            ("DCNT2", "0.00"),
+        ];
+        assert_eq!(
+            params,
+            expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn described_conversion_is_the_url_without_the_signature() {
+        let mut sub = make_fake_sub();
+        sub.plan_currency = "usd".to_string();
+        let settings = empty_settings();
+        let cj = CJClient::new(&settings, None, None, Some(Duration::minutes(0))).unwrap();
+        let described = Url::parse(&cj.describe_conversion(&sub)).unwrap();
+        let param = |name: &str| {
+            described
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        assert_eq!(param("OID"), Some(sub.id.to_string()));
+        assert_eq!(param("CID"), Some(settings.cj_cid.clone()));
+        assert_eq!(param("SIGNATURE"), Some("REDACTED".to_string()));
+    }
 }
@@ -0,0 +1,331 @@
+use actix_web::{
+    dev::Server,
+    web::{get, post, resource, Data, Json, Query},
+    App, HttpResponse, HttpServer,
+};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::Duration as StdDuration,
+};
+use time::OffsetDateTime;
+
+use crate::money::{Decimal, Money};
+
+// A stand in for CJ for local development and tests. Conversions reported to the S2S
+// endpoint become records in Commission Detail queries, and corrections files posted to
+// /corrections become original: false records for the conversions they return.
+
+#[derive(Clone, Debug)]
+pub struct EmulatorConfig {
+    // Added before every response
+    pub latency: StdDuration,
+    // Chance, from 0 to 1, that a request gets a 500 instead
+    pub failure_rate: f64,
+    // Records per Commission Detail page
+    pub page_size: usize,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig {
+            latency: StdDuration::ZERO,
+            failure_rate: 0.0,
+            page_size: 100,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmulatedRecord {
+    // CJ sends ids as strings
+    #[serde(serialize_with = "serialize_as_string")]
+    pub commission_id: u64,
+    pub posting_date: String,
+    pub original: bool,
+    pub order_id: String,
+    pub correction_reason: Option<String>,
+    pub sale_amount_pub_currency: String,
+    pub pub_commission_amount_pub_currency: String,
+    pub action_status: String,
+    pub validation_status: String,
+    pub publisher_id: String,
+    pub coupon: Option<String>,
+    pub items: Vec<Value>,
+}
+
+fn serialize_as_string<S: Serializer>(id: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&id.to_string())
+}
+
+// Every emulated conversion comes from this publisher and earns it this share of the sale
+const PUBLISHER_ID: &str = "1234567";
+const COMMISSION_RATE: f64 = 0.1;
+
+fn commission_for(sale_amount: &Money) -> Money {
+    let commission = (sale_amount.minor_units as f64 * COMMISSION_RATE).round() as i64;
+    Money::new(commission, &sale_amount.currency)
+}
+
+// The emulator doesn't convert, records are in the currency the conversion was reported in
+fn sale_amount_for(amount: &str, quantity: i32, currency: &str) -> Money {
+    let amount = amount
+        .parse::<Decimal>()
+        .map_or(0, |d| Money::from_decimal(&d, currency).minor_units);
+    Money::new(amount * i64::from(quantity), currency)
+}
+
+#[derive(Default)]
+pub struct EmulatorState {
+    next_commission_id: u64,
+    pub records: Vec<EmulatedRecord>,
+}
+
+impl EmulatorState {
+    fn add(&mut self, mut record: EmulatedRecord) {
+        self.next_commission_id += 1;
+        record.commission_id = self.next_commission_id;
+        self.records.push(record);
+    }
+}
+
+pub type SharedEmulatorState = Arc<Mutex<EmulatorState>>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ConversionQuery {
+    oid: String,
+    currency: Option<String>,
+    coupon: Option<String>,
+    // ITEMn, AMTn, QTYn and DCNTn for each item
+    #[serde(flatten)]
+    items: HashMap<String, String>,
+}
+
+impl ConversionQuery {
+    // (sku, amount, quantity, discount) for ITEM1, ITEM2, ... until one is missing
+    fn items(&self) -> Vec<(String, String, i32, String)> {
+        let mut items = vec![];
+        for n in 1.. {
+            let param = |name: &str| self.items.get(&format!("{}{}", name, n)).cloned();
+            let sku = match param("ITEM") {
+                Some(sku) => sku,
+                None => break,
+            };
+            items.push((
+                sku,
+                param("AMT").unwrap_or_default(),
+                param("QTY").and_then(|q| q.parse().ok()).unwrap_or(1),
+                param("DCNT").unwrap_or_default(),
+            ));
+        }
+        items
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CommissionDetailQuery {
+    query: String,
+}
+
+fn now_posting_date() -> String {
+    OffsetDateTime::now_utc().format("%FT%H:%M:%SZ")
+}
+
+// Value of a string argument in the GraphQL query, e.g. sincePostingDate:"..."
+fn get_argument<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    let start = query.find(&format!("{}:\"", name))? + name.len() + 2;
+    let end = start + query[start..].find('"')?;
+    Some(&query[start..end])
+}
+
+async fn delay_or_fail(config: &EmulatorConfig) -> Option<HttpResponse> {
+    tokio::time::sleep(config.latency).await;
+    match thread_rng().gen_bool(config.failure_rate.clamp(0.0, 1.0)) {
+        true => Some(HttpResponse::InternalServerError().finish()),
+        false => None,
+    }
+}
+
+pub async fn conversion(
+    query: Query<ConversionQuery>,
+    config: Data<EmulatorConfig>,
+    state: Data<Mutex<EmulatorState>>,
+) -> HttpResponse {
+    if let Some(failure) = delay_or_fail(&config).await {
+        return failure;
+    }
+    let currency = query.currency.as_deref().unwrap_or("usd");
+    let mut sale_amount = Money::new(0, currency);
+    let mut items = vec![];
+    for (sku, amount, quantity, discount) in query.items() {
+        let discount = sale_amount_for(&discount, 1, currency);
+        sale_amount.minor_units +=
+            sale_amount_for(&amount, quantity, currency).minor_units - discount.minor_units;
+        items.push(json!({
+            "sku": sku,
+            "quantity": quantity,
+            "perItemSaleAmountPubCurrency": amount,
+            "discountPubCurrency": discount.to_decimal_string(),
+        }));
+    }
+    let mut state = state.lock().expect("Emulator state poisoned");
+    state.add(EmulatedRecord {
+        commission_id: 0,
+        posting_date: now_posting_date(),
+        original: true,
+        order_id: query.oid.clone(),
+        correction_reason: None,
+        pub_commission_amount_pub_currency: commission_for(&sale_amount).to_decimal_string(),
+        sale_amount_pub_currency: sale_amount.to_decimal_string(),
+        action_status: "new".to_string(),
+        validation_status: "ACCEPTED".to_string(),
+        publisher_id: PUBLISHER_ID.to_string(),
+        // Sent empty when there isn't one
+        coupon: query.coupon.clone().filter(|c| !c.is_empty()),
+        // The emulator takes off the DCNTs it's sent, but doesn't discount coupons
+        items,
+    });
+    HttpResponse::Ok().finish()
+}
+
+// Takes a corrections file, as served by /corrections/{day}.csv, and adds a correction
+// for each RETRN line that matches a conversion.
+pub async fn corrections(
+    body: String,
+    config: Data<EmulatorConfig>,
+    state: Data<Mutex<EmulatorState>>,
+) -> HttpResponse {
+    if let Some(failure) = delay_or_fail(&config).await {
+        return failure;
+    }
+    let mut state = state.lock().expect("Emulator state poisoned");
+    let mut n_corrections = 0;
+    for order_id in body
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("RETRN,,"))
+    {
+        let original = state
+            .records
+            .iter()
+            .find(|r| r.original && r.order_id == order_id)
+            .cloned();
+        if let Some(original) = original {
+            state.add(EmulatedRecord {
+                commission_id: 0,
+                posting_date: now_posting_date(),
+                original: false,
+                order_id: original.order_id,
+                correction_reason: Some("RETURNED_MERCHANDISE".to_string()),
+                sale_amount_pub_currency: format!("-{}", original.sale_amount_pub_currency),
+                pub_commission_amount_pub_currency: format!(
+                    "-{}",
+                    original.pub_commission_amount_pub_currency
+                ),
+                action_status: "new".to_string(),
+                validation_status: "ACCEPTED".to_string(),
+                publisher_id: original.publisher_id,
+                coupon: original.coupon,
+                items: original.items,
+            });
+            n_corrections += 1;
+        }
+    }
+    HttpResponse::Ok().json(json!({ "corrections": n_corrections }))
+}
+
+pub async fn commission_detail(
+    body: Json<CommissionDetailQuery>,
+    config: Data<EmulatorConfig>,
+    state: Data<Mutex<EmulatorState>>,
+) -> HttpResponse {
+    if let Some(failure) = delay_or_fail(&config).await {
+        return failure;
+    }
+    let (since, before) = match (
+        get_argument(&body.query, "sincePostingDate"),
+        get_argument(&body.query, "beforePostingDate"),
+    ) {
+        (Some(since), Some(before)) => (since, before),
+        _ => {
+            return HttpResponse::Ok().json(json!({
+                "data": null,
+                "errors": [{"message": "sincePostingDate and beforePostingDate are required"}]
+            }))
+        }
+    };
+    let since_commission_id = get_argument(&body.query, "sinceCommissionId")
+        .and_then(|id| id.parse::<u64>().ok())
+        .unwrap_or(0);
+    let state = state.lock().expect("Emulator state poisoned");
+    // Posting dates share a format with the arguments, so compare as strings
+    let matching: Vec<&EmulatedRecord> = state
+        .records
+        .iter()
+        .filter(|r| {
+            r.commission_id > since_commission_id
+                && r.posting_date.as_str() >= since
+                && r.posting_date.as_str() < before
+        })
+        .collect();
+    let page: Vec<&EmulatedRecord> = matching.iter().take(config.page_size).cloned().collect();
+    HttpResponse::Ok().json(json!({
+        "data": {
+            "advertiserCommissions": {
+                "count": page.len(),
+                "payloadComplete": page.len() == matching.len(),
+                "maxCommissionId": page.last().map(|r| r.commission_id.to_string()),
+                "records": page,
+            }
+        }
+    }))
+}
+
+// Everything the emulator holds, to check on from a browser or a test
+pub async fn records(state: Data<Mutex<EmulatorState>>) -> HttpResponse {
+    let state = state.lock().expect("Emulator state poisoned");
+    HttpResponse::Ok().json(&state.records)
+}
+
+pub fn run_emulator(
+    listener: TcpListener,
+    config: EmulatorConfig,
+    state: SharedEmulatorState,
+) -> Result<Server, std::io::Error> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(Data::new(config.clone()))
+            .app_data(Data::from(state.clone()))
+            .service(resource("/u").route(get().to(conversion)))
+            .service(resource("/query").route(post().to(commission_detail)))
+            .service(resource("/corrections").route(post().to(corrections)))
+            .service(resource("/__records__").route(get().to(records)))
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_argument_reads_quoted_arguments() {
+        let query = r#"advertiserCommissions(
+            sincePostingDate:"2022-01-01T00:00:00Z",
+            sinceCommissionId:"12",
+        )"#;
+        assert_eq!(
+            get_argument(query, "sincePostingDate"),
+            Some("2022-01-01T00:00:00Z")
+        );
+        assert_eq!(get_argument(query, "sinceCommissionId"), Some("12"));
+        assert_eq!(get_argument(query, "beforePostingDate"), None);
+    }
+}
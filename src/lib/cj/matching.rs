@@ -0,0 +1,580 @@
+use serde::Serialize;
+
+use crate::{
+    coupons::CouponRules,
+    models::{refunds::Refund, subscriptions::Subscription},
+    money::Money,
+    network::NetworkCommission,
+    plans::PlanCatalog,
+};
+
+// Why a network's commission doesn't match what we reported. Amounts are in minor units of the
+// publisher currency.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum MismatchReason {
+    // We reported an item CJ has no line for
+    MissingItem {
+        sku: String,
+    },
+    // CJ has a line for an item we didn't report
+    UnexpectedItem {
+        sku: String,
+    },
+    Quantity {
+        sku: String,
+        expected: i32,
+        found: i32,
+    },
+    // CJ took a discount off a conversion we reported without a coupon or a discount
+    UnexpectedDiscount {
+        discount: i64,
+    },
+    Amount {
+        expected: i64,
+        found: i64,
+        tolerance: i64,
+    },
+    CorrectionReason {
+        expected: String,
+        found: Option<String>,
+    },
+}
+
+pub struct ExpectedItem {
+    pub sku: String,
+    // None if any quantity is fine
+    pub quantity: Option<i32>,
+}
+
+// What we expect CJ to have for a conversion or a correction
+pub struct ExpectedSale {
+    pub items: Vec<ExpectedItem>,
+    // Before any discount
+    pub amount: Money,
+    // What we reported as DCNTn, taken off amount
+    pub discount: Money,
+    pub coupons: Vec<String>,
+    pub correction_reason: Option<String>,
+}
+
+// A line for each SKU we reported. Items that are the same SKU are one line with their
+// quantities added up.
+fn expected_items(
+    sub: &Subscription,
+    plans: &PlanCatalog,
+    coupon_rules: &CouponRules,
+) -> Vec<ExpectedItem> {
+    let mut expected: Vec<ExpectedItem> = vec![];
+    for item in coupon_rules.reported_items(sub) {
+        let sku = plans.cj_sku(&item.plan_id);
+        match expected.iter_mut().find(|e| e.sku == sku) {
+            Some(e) => e.quantity = e.quantity.map(|q| q + item.quantity),
+            None => expected.push(ExpectedItem {
+                sku: sku.to_string(),
+                quantity: Some(item.quantity),
+            }),
+        }
+    }
+    expected
+}
+
+impl ExpectedSale {
+    // CJ sells each item we reported, for its amount times its quantity less its
+    // discount, as its plan's SKU
+    pub fn for_subscription(
+        sub: &Subscription,
+        plans: &PlanCatalog,
+        coupon_rules: &CouponRules,
+    ) -> Self {
+        let items = coupon_rules.reported_items(sub);
+        ExpectedSale {
+            items: expected_items(sub, plans, coupon_rules),
+            amount: Money::new(
+                items
+                    .iter()
+                    .map(|i| i64::from(i.amount) * i64::from(i.quantity))
+                    .sum(),
+                &sub.plan_currency,
+            ),
+            discount: Money::new(
+                items.iter().map(|i| i64::from(i.discount)).sum(),
+                &sub.plan_currency,
+            ),
+            coupons: sub.coupons.clone(),
+            correction_reason: None,
+        }
+    }
+
+    // A refund can be for part of the quantity, so it isn't checked. If a coupon rule
+    // changed the amount we reported, CJ reverses the refund's share of that instead.
+    pub fn for_refund(
+        refund: &Refund,
+        sub: &Subscription,
+        plans: &PlanCatalog,
+        coupon_rules: &CouponRules,
+    ) -> Self {
+        let amount = match coupon_rules.amount(&sub.coupons) {
+            Some(_) => coupon_rules.refunded_total(sub, refund.refund_amount),
+            None => i64::from(refund.refund_amount),
+        };
+        let items = expected_items(sub, plans, coupon_rules)
+            .into_iter()
+            .map(|item| ExpectedItem {
+                quantity: None,
+                ..item
+            })
+            .collect();
+        ExpectedSale {
+            items,
+            amount: Money::new(-amount, &sub.plan_currency),
+            discount: Money::new(0, &sub.plan_currency),
+            coupons: sub.coupons.clone(),
+            correction_reason: Some("RETURNED_MERCHANDISE".to_string()),
+        }
+    }
+}
+
+// How an expected amount is compared with the network's, which is in the publisher currency
+pub struct AmountCheck {
+    // The value of one unit of the expected currency in the publisher currency
+    pub rate: f64,
+    // In minor units of the publisher currency
+    pub tolerance: i64,
+    // On top of tolerance, for amounts converted at a rate that isn't CJ's
+    pub tolerance_percent: u32,
+}
+
+impl AmountCheck {
+    pub fn same_currency(tolerance: i64) -> Self {
+        AmountCheck {
+            rate: 1.0,
+            tolerance,
+            tolerance_percent: 0,
+        }
+    }
+
+    fn convert(&self, amount: &Money, publisher_currency: &str) -> i64 {
+        amount.convert(self.rate, publisher_currency).minor_units
+    }
+
+    fn tolerance(&self, amount: i64) -> i64 {
+        let percent = (amount.abs() as f64 * self.tolerance_percent as f64 / 100.0).round();
+        self.tolerance.max(percent as i64)
+    }
+}
+
+// Every way record differs from expected, none if it matches. The record's amounts are
+// in the publisher currency. Amounts aren't compared without an amount_check, e.g.
+// without a rate for the currency. Discounts the network took off an original are
+// allowed if we reported a coupon or a discount. With a coupon, the network's discounts
+// are taken off what we expect, otherwise ours are. A refund is for what was paid, so a
+// correction is compared as is.
+pub fn find_mismatches(
+    record: &NetworkCommission,
+    expected: &ExpectedSale,
+    amount_check: Option<&AmountCheck>,
+) -> Vec<MismatchReason> {
+    let publisher_currency = record.sale_amount.currency.as_str();
+    let mut mismatches = vec![];
+    if expected.correction_reason.is_some()
+        && record.correction_reason != expected.correction_reason
+    {
+        mismatches.push(MismatchReason::CorrectionReason {
+            expected: expected.correction_reason.clone().unwrap_or_default(),
+            found: record.correction_reason.clone(),
+        });
+    }
+    for item in &expected.items {
+        let lines: Vec<_> = record.items.iter().filter(|i| i.sku == item.sku).collect();
+        if lines.is_empty() {
+            mismatches.push(MismatchReason::MissingItem {
+                sku: item.sku.clone(),
+            });
+            continue;
+        }
+        // CJ doesn't always say, a line without a quantity is one
+        let found: i32 = lines.iter().map(|i| i.quantity.unwrap_or(1)).sum();
+        match item.quantity {
+            Some(expected) if expected != found => mismatches.push(MismatchReason::Quantity {
+                sku: item.sku.clone(),
+                expected,
+                found,
+            }),
+            _ => {}
+        }
+    }
+    for line in &record.items {
+        if !expected.items.iter().any(|i| i.sku == line.sku) {
+            mismatches.push(MismatchReason::UnexpectedItem {
+                sku: line.sku.clone(),
+            });
+        }
+    }
+    let discount = match record.original {
+        true => record
+            .items
+            .iter()
+            .filter_map(|i| i.discount.as_ref())
+            .map(|d| d.minor_units)
+            .sum(),
+        false => 0,
+    };
+    if discount != 0 && expected.coupons.is_empty() && expected.discount.minor_units == 0 {
+        mismatches.push(MismatchReason::UnexpectedDiscount { discount });
+    }
+    if let Some(check) = amount_check {
+        let converted = check.convert(&expected.amount, publisher_currency);
+        let expected = match expected.coupons.is_empty() {
+            false => converted - discount,
+            true => converted - check.convert(&expected.discount, publisher_currency),
+        };
+        let found = record.sale_amount.minor_units;
+        let tolerance = check.tolerance(expected);
+        if (found - expected).abs() > tolerance {
+            mismatches.push(MismatchReason::Amount {
+                expected,
+                found,
+                tolerance,
+            });
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        cj::client::CommissionDetailRecord,
+        coupons::CouponRule,
+        models::{
+            refunds::PartialRefund,
+            subscriptions::{test_subscriptions::make_fake_sub, LineItem},
+        },
+    };
+
+    fn make_record(
+        original: bool,
+        sale_amount: &str,
+        items: serde_json::Value,
+    ) -> NetworkCommission {
+        make_record_in("usd", original, sale_amount, items)
+    }
+
+    // A CJ record with CJ's amounts in publisher_currency
+    fn make_record_in(
+        publisher_currency: &str,
+        original: bool,
+        sale_amount: &str,
+        items: serde_json::Value,
+    ) -> NetworkCommission {
+        serde_json::from_value::<CommissionDetailRecord>(json!({
+            "commissionId": "1",
+            "postingDate": "2022-06-01T12:00:00Z",
+            "original": original,
+            "orderId": "order",
+            "correctionReason": if original { None } else { Some("RETURNED_MERCHANDISE") },
+            "saleAmountPubCurrency": sale_amount,
+            "items": items,
+        }))
+        .unwrap()
+        .to_network_commission(publisher_currency)
+    }
+
+    const USD: AmountCheck = AmountCheck {
+        rate: 1.0,
+        tolerance: 0,
+        tolerance_percent: 0,
+    };
+    const USD_WITHIN_A_CENT: AmountCheck = AmountCheck {
+        rate: 1.0,
+        tolerance: 1,
+        tolerance_percent: 0,
+    };
+
+    fn make_expected(quantity: i32, amount: i64, coupons: Option<&str>) -> ExpectedSale {
+        ExpectedSale {
+            items: vec![ExpectedItem {
+                sku: "sku".to_string(),
+                quantity: Some(quantity),
+            }],
+            amount: Money::new(amount, "usd"),
+            discount: Money::new(0, "usd"),
+            coupons: coupons.map(String::from).into_iter().collect(),
+            correction_reason: None,
+        }
+    }
+
+    #[test]
+    fn quantity_is_part_of_the_amount() {
+        let record = make_record(true, "19.98", json!([{"sku": "sku", "quantity": 2}]));
+        assert_eq!(
+            find_mismatches(&record, &make_expected(2, 1998, None), Some(&USD)),
+            vec![]
+        );
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 999, None), Some(&USD)),
+            vec![
+                MismatchReason::Quantity {
+                    sku: "sku".to_string(),
+                    expected: 1,
+                    found: 2
+                },
+                MismatchReason::Amount {
+                    expected: 999,
+                    found: 1998,
+                    tolerance: 0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn discounts_are_allowed_with_a_coupon() {
+        let record = make_record(
+            true,
+            "7.99",
+            json!([{"sku": "sku", "quantity": 1, "discountPubCurrency": "2.00"}]),
+        );
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 999, Some("SPRING")), Some(&USD)),
+            vec![]
+        );
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 999, None), Some(&USD)),
+            vec![
+                MismatchReason::UnexpectedDiscount { discount: 200 },
+                MismatchReason::Amount {
+                    expected: 999,
+                    found: 799,
+                    tolerance: 0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn amounts_match_within_tolerance() {
+        let record = make_record(true, "9.98", json!([{"sku": "sku"}]));
+        assert_eq!(
+            find_mismatches(
+                &record,
+                &make_expected(1, 999, None),
+                Some(&USD_WITHIN_A_CENT)
+            ),
+            vec![]
+        );
+        assert_eq!(
+            find_mismatches(
+                &record,
+                &make_expected(1, 1000, None),
+                Some(&USD_WITHIN_A_CENT)
+            ),
+            vec![MismatchReason::Amount {
+                expected: 1000,
+                found: 998,
+                tolerance: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn amounts_are_converted_with_a_percentage_tolerance() {
+        // 50.00 EUR at 1.07 is 53.50 USD, CJ converted at a slightly different rate
+        let record = make_record(true, "53.20", json!([{"sku": "sku"}]));
+        let check = AmountCheck {
+            rate: 1.07,
+            tolerance: 0,
+            tolerance_percent: 1,
+        };
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 5000, None), Some(&check)),
+            vec![]
+        );
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 6000, None), Some(&check)),
+            vec![MismatchReason::Amount {
+                expected: 6420,
+                found: 5320,
+                tolerance: 64
+            }]
+        );
+        // Without a rate, amounts aren't compared
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 6000, None), None),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn amounts_are_compared_in_the_minor_units_of_each_currency() {
+        // 1000 JPY at 0.0074 is 7.40 USD
+        let mut expected = make_expected(1, 1000, None);
+        expected.amount = Money::new(1000, "jpy");
+        let check = AmountCheck {
+            rate: 0.0074,
+            tolerance: 0,
+            tolerance_percent: 0,
+        };
+        let record = make_record(true, "7.40", json!([{"sku": "sku"}]));
+        assert_eq!(find_mismatches(&record, &expected, Some(&check)), vec![]);
+        // A publisher currency without minor units
+        let record = make_record_in("jpy", true, "1000", json!([{"sku": "sku"}]));
+        assert_eq!(find_mismatches(&record, &expected, Some(&USD)), vec![]);
+        let record = make_record_in("jpy", true, "1001", json!([{"sku": "sku"}]));
+        assert_eq!(
+            find_mismatches(&record, &expected, Some(&USD_WITHIN_A_CENT)),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn every_item_line_is_checked() {
+        let record = make_record(
+            true,
+            "9.99",
+            json!([{"sku": "other", "quantity": 1}, {"sku": "extra", "quantity": 1}]),
+        );
+        assert_eq!(
+            find_mismatches(&record, &make_expected(1, 999, None), Some(&USD)),
+            vec![
+                MismatchReason::MissingItem {
+                    sku: "sku".to_string()
+                },
+                MismatchReason::UnexpectedItem {
+                    sku: "other".to_string()
+                },
+                MismatchReason::UnexpectedItem {
+                    sku: "extra".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn corrections_are_compared_as_is() {
+        let record = make_record(
+            false,
+            "-9.99",
+            json!([{"sku": "sku", "quantity": 1, "discountPubCurrency": "-2.00"}]),
+        );
+        let mut expected = make_expected(1, -999, Some("SPRING"));
+        expected.correction_reason = Some("RETURNED_MERCHANDISE".to_string());
+        assert_eq!(find_mismatches(&record, &expected, Some(&USD)), vec![]);
+        expected.correction_reason = Some("OTHER".to_string());
+        assert_eq!(
+            find_mismatches(&record, &expected, Some(&USD)),
+            vec![MismatchReason::CorrectionReason {
+                expected: "OTHER".to_string(),
+                found: Some("RETURNED_MERCHANDISE".to_string())
+            }]
+        );
+    }
+
+    #[test]
+    fn coupon_rule_amounts_are_expected_for_subscriptions_and_refunds() {
+        let mut sub = make_fake_sub();
+        sub.quantity = 2;
+        sub.plan_amount = 999;
+        sub.coupons = vec!["PARTNER".to_string()];
+        let refund = Refund::new(PartialRefund {
+            id: Uuid::new_v4(),
+            refund_id: "refund".to_string(),
+            subscription_id: sub.subscription_id.clone(),
+            refund_created: OffsetDateTime::now_utc(),
+            refund_amount: 1998,
+            refund_status: None,
+            refund_reason: None,
+            correction_file_date: None,
+        });
+        let plans = PlanCatalog::default();
+        let rules = CouponRules(vec![CouponRule {
+            code: "partner".to_string(),
+            exclude: false,
+            reason: None,
+            amount: Some(100),
+            cj_type: None,
+        }]);
+        assert_eq!(
+            ExpectedSale::for_subscription(&sub, &plans, &CouponRules::default())
+                .amount
+                .minor_units,
+            1998
+        );
+        assert_eq!(
+            ExpectedSale::for_subscription(&sub, &plans, &rules)
+                .amount
+                .minor_units,
+            200
+        );
+        assert_eq!(
+            ExpectedSale::for_refund(&refund, &sub, &plans, &rules)
+                .amount
+                .minor_units,
+            -200
+        );
+        let mut partial_refund = refund;
+        partial_refund.refund_amount = 999;
+        assert_eq!(
+            ExpectedSale::for_refund(&partial_refund, &sub, &plans, &rules)
+                .amount
+                .minor_units,
+            -100
+        );
+    }
+
+    #[test]
+    fn every_line_item_is_expected() {
+        let mut sub = make_fake_sub();
+        sub.plan_currency = "usd".to_string();
+        sub.coupons = vec![];
+        sub.set_line_items(&[
+            LineItem {
+                plan_id: "bundle".to_string(),
+                quantity: 1,
+                amount: 1000,
+                discount: 150,
+            },
+            LineItem {
+                plan_id: "add-on".to_string(),
+                quantity: 3,
+                amount: 250,
+                discount: 0,
+            },
+        ]);
+        let expected =
+            ExpectedSale::for_subscription(&sub, &PlanCatalog::default(), &CouponRules::default());
+        let record = make_record(
+            true,
+            "16.00",
+            json!([
+                {"sku": "bundle", "quantity": 1, "discountPubCurrency": "1.50"},
+                {"sku": "add-on", "quantity": 3, "discountPubCurrency": "0.00"},
+            ]),
+        );
+        assert_eq!(find_mismatches(&record, &expected, Some(&USD)), vec![]);
+        let record = make_record(
+            true,
+            "8.50",
+            json!([{"sku": "bundle", "quantity": 1, "discountPubCurrency": "1.50"}]),
+        );
+        assert_eq!(
+            find_mismatches(&record, &expected, Some(&USD)),
+            vec![
+                MismatchReason::MissingItem {
+                    sku: "add-on".to_string()
+                },
+                MismatchReason::Amount {
+                    expected: 1600,
+                    found: 850,
+                    tolerance: 0
+                },
+            ]
+        );
+    }
+}
@@ -1,2 +1,5 @@
 pub mod client;
 pub mod country_codes;
+pub mod emulator;
+pub mod matching;
+pub mod programs;
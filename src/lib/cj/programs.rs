@@ -0,0 +1,175 @@
+use serde::Deserialize;
+
+use crate::{error::CjmsError, plans::PlanCatalog, settings::Settings};
+
+// The name of the program made from the top level cj_ settings
+pub const DEFAULT_PROGRAM: &str = "default";
+
+// A CJ advertiser account, with its own S2S action, Commission Detail records and
+// corrections file
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CJProgram {
+    pub name: String,
+    pub cj_cid: String,
+    pub cj_type: String,
+    pub cj_signature: String,
+    // The advertiser id, queried in the Commission Detail API and the CID of the
+    // corrections file
+    pub cj_sftp_user: String,
+    pub cj_subid: String,
+    // Optional, defaults to cj_api_access_token
+    #[serde(default)]
+    pub cj_api_access_token: Option<String>,
+    // Plan catalog product names whose subscriptions are reported to the program.
+    // Optional.
+    #[serde(default)]
+    pub products: Vec<String>,
+}
+
+// The default program and settings.cj_programs. Subscriptions are routed to a program
+// by their plan's cj_program, then by its product_name, then to the default program.
+#[derive(Debug, Clone)]
+pub struct CJPrograms {
+    programs: Vec<CJProgram>,
+    plans: PlanCatalog,
+}
+
+impl CJPrograms {
+    // Errors if program names aren't unique or a plan is routed to a program that
+    // doesn't exist, as reporting to the wrong advertiser can't be undone
+    pub fn new(settings: &Settings) -> Result<Self, CjmsError> {
+        let default = CJProgram {
+            name: DEFAULT_PROGRAM.to_string(),
+            cj_cid: settings.cj_cid.clone(),
+            cj_type: settings.cj_type.clone(),
+            cj_signature: settings.cj_signature.clone(),
+            cj_sftp_user: settings.cj_sftp_user.clone(),
+            cj_subid: settings.cj_subid.clone(),
+            cj_api_access_token: Some(settings.cj_api_access_token.clone()),
+            products: vec![],
+        };
+        let mut programs = vec![default];
+        for program in &settings.cj_programs {
+            if programs.iter().any(|p| p.name == program.name) {
+                return Err(CjmsError::Configuration(format!(
+                    "CJ program {} is configured more than once.",
+                    program.name
+                )));
+            }
+            let mut program = program.clone();
+            program
+                .cj_api_access_token
+                .get_or_insert_with(|| settings.cj_api_access_token.clone());
+            programs.push(program);
+        }
+        for plan in &settings.plans.0 {
+            if let Some(name) = &plan.cj_program {
+                if !programs.iter().any(|p| &p.name == name) {
+                    return Err(CjmsError::Configuration(format!(
+                        "Plan {} is routed to unknown CJ program {}.",
+                        plan.plan_id, name
+                    )));
+                }
+            }
+        }
+        Ok(CJPrograms {
+            programs,
+            plans: settings.plans.clone(),
+        })
+    }
+
+    pub fn all(&self) -> &[CJProgram] {
+        &self.programs
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CJProgram> {
+        self.programs.iter().find(|p| p.name == name)
+    }
+
+    pub fn for_plan(&self, plan_id: &str) -> &CJProgram {
+        let plan = self.plans.get(plan_id);
+        let by_name = plan
+            .and_then(|p| p.cj_program.as_deref())
+            .and_then(|name| self.get(name));
+        let by_product = || {
+            let product = plan.and_then(|p| p.product_name.as_ref())?;
+            self.programs.iter().find(|p| p.products.contains(product))
+        };
+        by_name.or_else(by_product).unwrap_or(&self.programs[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{plans::Plan, test_utils::empty_settings};
+
+    fn make_program(name: &str, products: &[&str]) -> CJProgram {
+        CJProgram {
+            name: name.to_string(),
+            cj_cid: format!("{}-cid", name),
+            cj_type: format!("{}-type", name),
+            cj_signature: format!("{}-signature", name),
+            cj_sftp_user: format!("{}-advertiser", name),
+            cj_subid: format!("{}-subid", name),
+            cj_api_access_token: None,
+            products: products.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    fn make_plan(plan_id: &str, cj_program: Option<&str>, product_name: Option<&str>) -> Plan {
+        Plan {
+            plan_id: plan_id.to_string(),
+            cj_sku: None,
+            product_name: product_name.map(String::from),
+            eligible: true,
+            ineligible_reason: None,
+            cj_program: cj_program.map(String::from),
+        }
+    }
+
+    #[test]
+    fn plans_are_routed_by_program_then_product() {
+        let mut settings = empty_settings();
+        settings.cj_programs = vec![make_program("relay", &["Relay"]), make_program("vpn", &[])];
+        settings.plans = PlanCatalog(vec![
+            make_plan("price_relay", None, Some("Relay")),
+            make_plan("price_vpn", Some("vpn"), Some("Relay")),
+            make_plan("price_other", None, Some("Other")),
+        ]);
+        let programs = CJPrograms::new(&settings).unwrap();
+        assert_eq!(programs.all().len(), 3);
+        assert_eq!(programs.for_plan("price_relay").name, "relay");
+        assert_eq!(programs.for_plan("price_vpn").name, "vpn");
+        assert_eq!(programs.for_plan("price_other").name, DEFAULT_PROGRAM);
+        assert_eq!(programs.for_plan("price_unknown").name, DEFAULT_PROGRAM);
+        assert_eq!(
+            programs.get("vpn").unwrap().cj_api_access_token,
+            Some(settings.cj_api_access_token.clone())
+        );
+        assert_eq!(
+            programs.get(DEFAULT_PROGRAM).unwrap().cj_sftp_user,
+            settings.cj_sftp_user
+        );
+    }
+
+    #[test]
+    fn plans_cannot_be_routed_to_unknown_programs() {
+        let mut settings = empty_settings();
+        settings.plans = PlanCatalog(vec![make_plan("price_vpn", Some("vpn"), None)]);
+        match CJPrograms::new(&settings) {
+            Err(CjmsError::Configuration(e)) => assert!(e.contains("unknown CJ program")),
+            other => panic!("Expected a configuration error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn program_names_are_unique() {
+        let mut settings = empty_settings();
+        settings.cj_programs = vec![make_program(DEFAULT_PROGRAM, &[])];
+        match CJPrograms::new(&settings) {
+            Err(CjmsError::Configuration(e)) => assert!(e.contains("configured more than once")),
+            other => panic!("Expected a configuration error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use std::str::FromStr;
+
+use clap::{ArgEnum, Parser, Subcommand};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sqlx::PgPool;
+use time::Date;
+use uuid::Uuid;
+
+use crate::{
+    cj::{
+        client::CJError,
+        programs::{CJPrograms, DEFAULT_PROGRAM},
+    },
+    controllers::{
+        admin::{AICRecordResponse, RefundResponse, SubscriptionResponse},
+        corrections::build_body_from_results,
+    },
+    error::CjmsError,
+    error_and_incr, info_and_incr,
+    models::{
+        aic::AICModel,
+        filters::{RecordFilter, DEFAULT_PAGE_SIZE},
+        refunds::RefundModel,
+        status_history::{Status, StatusCount},
+        status_overrides::StatusOverrideModel,
+        subscriptions::SubscriptionModel,
+    },
+    settings::Settings,
+    telemetry::{LogKey, StatsD},
+};
+
+/// Operator tooling for cjms. Connects to the database configured in settings.
+#[derive(Parser)]
+#[clap(name = "cjmsctl")]
+pub struct Cli {
+    /// Print JSON instead of text
+    #[clap(long, global = true)]
+    pub json: bool,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Clone, Debug, ArgEnum)]
+pub enum Record {
+    Subscription,
+    Refund,
+    Aic,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Show a record and any manual status overrides. Subscriptions and AICs are looked up by id, refunds by refund_id
+    Inspect {
+        #[clap(arg_enum)]
+        record: Record,
+        id: String,
+    },
+    /// List records, oldest first
+    List {
+        #[clap(arg_enum)]
+        record: Record,
+        /// Not available for AICs
+        #[clap(long)]
+        status: Option<String>,
+        /// List aic_archive instead of aic
+        #[clap(long)]
+        archived: bool,
+        #[clap(long, default_value_t = DEFAULT_PAGE_SIZE)]
+        limit: i64,
+    },
+    /// Set records back to NotReported so the next report or batch run picks them up again
+    Requeue {
+        #[clap(arg_enum)]
+        record: Record,
+        #[clap(required = true)]
+        ids: Vec<String>,
+        #[clap(long)]
+        reason: String,
+        #[clap(long)]
+        operator: String,
+    },
+    /// Manually set the status of a subscription or refund. The change is recorded in status_overrides
+    SetStatus {
+        #[clap(arg_enum)]
+        record: Record,
+        id: String,
+        /// One of NotReported, Reported, WillNotReport, CJReceived, CJNotReceived
+        status: String,
+        #[clap(long)]
+        reason: String,
+        #[clap(long)]
+        operator: String,
+    },
+    /// Move an AIC to aic_archive
+    ArchiveAic { id: Uuid },
+    /// Move an AIC back from aic_archive. Expired AICs will be archived again by the next cleanup
+    UnarchiveAic { id: Uuid },
+    /// Print the corrections file for a day (YYYY-MM-DD)
+    Corrections {
+        day: String,
+        /// The CJ program whose file to print
+        #[clap(long, default_value = DEFAULT_PROGRAM)]
+        program: String,
+    },
+    /// Print the number of subscriptions and refunds in each status
+    Counts,
+}
+
+// Every command builds JSON. Text output is derived from it unless the command has
+// a natural text form of its own, like the corrections file.
+pub struct Output {
+    pub value: Value,
+    pub text: Option<String>,
+}
+
+impl Output {
+    fn from_serializable<T: Serialize>(v: T) -> Result<Self, CjmsError> {
+        let value = serde_json::to_value(v).map_err(|e| CjmsError::DataIntegrity(e.to_string()))?;
+        Ok(Output { value, text: None })
+    }
+
+    pub fn render(&self, json: bool) -> String {
+        if json {
+            return serde_json::to_string_pretty(&self.value).unwrap_or_default();
+        }
+        match &self.text {
+            Some(text) => text.clone(),
+            None => render_text(&self.value),
+        }
+    }
+}
+
+fn render_scalar(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Null => "".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn render_object(map: &Map<String, Value>) -> String {
+    map.iter()
+        .map(|(k, v)| format!("{}: {}", k, render_scalar(v)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn render_text(value: &Value) -> String {
+    match value {
+        Value::Object(map) => render_object(map),
+        Value::Array(items) => items
+            .iter()
+            .map(render_text)
+            .collect::<Vec<String>>()
+            .join("\n\n"),
+        other => render_scalar(other),
+    }
+}
+
+fn parse_status(status: &str) -> Result<Status, CjmsError> {
+    Status::from_str(status)
+        .map_err(|_| CjmsError::InvalidInput(format!("Invalid status: {}", status)))
+}
+
+fn parse_uuid(id: &str) -> Result<Uuid, CjmsError> {
+    Uuid::parse_str(id).map_err(|_| CjmsError::InvalidInput(format!("Invalid id: {}", id)))
+}
+
+fn counts_to_json(counts: Vec<StatusCount>) -> Value {
+    let mut map = Map::new();
+    for c in counts {
+        map.insert(
+            c.status.unwrap_or_else(|| "None".to_string()),
+            json!(c.count),
+        );
+    }
+    Value::Object(map)
+}
+
+pub async fn run(
+    command: &Command,
+    db_pool: &PgPool,
+    settings: &Settings,
+    statsd: &StatsD,
+) -> Result<Output, CjmsError> {
+    match command {
+        Command::Inspect { record, id } => inspect(record, id, db_pool).await,
+        Command::List {
+            record,
+            status,
+            archived,
+            limit,
+        } => list(record, status, *archived, *limit, db_pool).await,
+        Command::Requeue {
+            record,
+            ids,
+            reason,
+            operator,
+        } => requeue(record, ids, reason, operator, db_pool, statsd).await,
+        Command::SetStatus {
+            record,
+            id,
+            status,
+            reason,
+            operator,
+        } => {
+            let status = parse_status(status)?;
+            let value = set_status(record, id, status, reason, operator, db_pool, statsd).await?;
+            Ok(Output { value, text: None })
+        }
+        Command::ArchiveAic { id } => {
+            let aics = AICModel { db_pool };
+            let aic = aics.fetch_one_by_id(id).await?;
+            aics.archive_aic(&aic).await?;
+            info_and_incr!(
+                statsd,
+                LogKey::CjmsctlAicArchived,
+                aic_id = id.to_string().as_str(),
+                "AIC archived from cjmsctl."
+            );
+            Output::from_serializable(AICRecordResponse::from(aic))
+        }
+        Command::UnarchiveAic { id } => {
+            let aics = AICModel { db_pool };
+            let aic = aics.unarchive_aic(id).await?;
+            info_and_incr!(
+                statsd,
+                LogKey::CjmsctlAicUnarchived,
+                aic_id = id.to_string().as_str(),
+                "AIC unarchived from cjmsctl."
+            );
+            Output::from_serializable(AICRecordResponse::from(aic))
+        }
+        Command::Corrections { day, program } => {
+            let day = Date::parse(day, "%F")
+                .map_err(|_| CjmsError::InvalidInput(format!("Invalid day: {}", day)))?;
+            let programs = CJPrograms::new(settings)?;
+            let program = programs
+                .get(program)
+                .ok_or_else(|| CJError::UnknownProgram(program.to_string()))?;
+            let refunds = RefundModel { db_pool }
+                .fetch_by_correction_file_day(&day)
+                .await?;
+            let body = build_body_from_results(&programs, program, refunds, db_pool, statsd).await;
+            Ok(Output {
+                value: json!({ "day": day.format("%F"), "body": body }),
+                text: Some(body),
+            })
+        }
+        Command::Counts => {
+            let subscriptions = SubscriptionModel { db_pool }.count_by_status().await?;
+            let refunds = RefundModel { db_pool }.count_by_status().await?;
+            let value = json!({
+                "subscriptions": counts_to_json(subscriptions),
+                "refunds": counts_to_json(refunds),
+            });
+            let text = format!(
+                "subscriptions\n{}\n\nrefunds\n{}",
+                render_text(&value["subscriptions"]),
+                render_text(&value["refunds"])
+            );
+            Ok(Output {
+                value,
+                text: Some(text),
+            })
+        }
+    }
+}
+
+async fn inspect(record: &Record, id: &str, db_pool: &PgPool) -> Result<Output, CjmsError> {
+    let overrides = StatusOverrideModel { db_pool };
+    let (mut value, record_id) = match record {
+        Record::Subscription => {
+            let sub = SubscriptionModel { db_pool }
+                .fetch_one_by_id(&parse_uuid(id)?)
+                .await?;
+            let record_id = sub.id;
+            (json!(SubscriptionResponse::from(sub)), record_id)
+        }
+        Record::Refund => {
+            let refund = RefundModel { db_pool }.fetch_one_by_refund_id(id).await?;
+            let record_id = refund.id;
+            (json!(RefundResponse::from(refund)), record_id)
+        }
+        Record::Aic => {
+            let id = parse_uuid(id)?;
+            let aics = AICModel { db_pool };
+            let (aic, archived) = match aics.fetch_one_by_id(&id).await {
+                Ok(aic) => (aic, false),
+                Err(sqlx::Error::RowNotFound) => {
+                    (aics.fetch_one_by_id_from_archive(&id).await?, true)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let mut value = json!(AICRecordResponse::from(aic));
+            value["archived"] = json!(archived);
+            return Ok(Output { value, text: None });
+        }
+    };
+    let entries = overrides.fetch_all_by_record_id(&record_id).await?;
+    value["status_overrides"] = json!(entries);
+    Ok(Output { value, text: None })
+}
+
+async fn list(
+    record: &Record,
+    status: &Option<String>,
+    archived: bool,
+    limit: i64,
+    db_pool: &PgPool,
+) -> Result<Output, CjmsError> {
+    let filter = RecordFilter {
+        status: status.as_deref().map(parse_status).transpose()?,
+        limit,
+        ..RecordFilter::default()
+    };
+    match record {
+        Record::Subscription => {
+            let subs = SubscriptionModel { db_pool }
+                .fetch_filtered(&filter)
+                .await?;
+            Output::from_serializable(
+                subs.into_iter()
+                    .map(SubscriptionResponse::from)
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Record::Refund => {
+            let refunds = RefundModel { db_pool }.fetch_filtered(&filter).await?;
+            Output::from_serializable(
+                refunds
+                    .into_iter()
+                    .map(RefundResponse::from)
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Record::Aic => {
+            if filter.status.is_some() {
+                return Err(CjmsError::InvalidInput("AICs have no status.".to_string()));
+            }
+            let aics = AICModel { db_pool };
+            let result = match archived {
+                true => aics.fetch_filtered_from_archive(&filter).await,
+                false => aics.fetch_filtered(&filter).await,
+            };
+            let aics = result?;
+            Output::from_serializable(
+                aics.into_iter()
+                    .map(AICRecordResponse::from)
+                    .collect::<Vec<_>>(),
+            )
+        }
+    }
+}
+
+async fn set_status(
+    record: &Record,
+    id: &str,
+    status: Status,
+    reason: &str,
+    operator: &str,
+    db_pool: &PgPool,
+    statsd: &StatsD,
+) -> Result<Value, CjmsError> {
+    let model = StatusOverrideModel { db_pool };
+    let value = match record {
+        Record::Subscription => {
+            let sub = model
+                .override_subscription_status(&parse_uuid(id)?, status.clone(), reason, operator)
+                .await?;
+            json!(SubscriptionResponse::from(sub))
+        }
+        Record::Refund => {
+            let refund = model
+                .override_refund_status(id, status.clone(), reason, operator)
+                .await?;
+            json!(RefundResponse::from(refund))
+        }
+        Record::Aic => return Err(CjmsError::InvalidInput("AICs have no status.".to_string())),
+    };
+    info_and_incr!(
+        statsd,
+        LogKey::CjmsctlStatusOverride,
+        record_id = id,
+        status = status.to_string().as_str(),
+        operator = operator,
+        "Status overridden from cjmsctl."
+    );
+    Ok(value)
+}
+
+async fn requeue(
+    record: &Record,
+    ids: &[String],
+    reason: &str,
+    operator: &str,
+    db_pool: &PgPool,
+    statsd: &StatsD,
+) -> Result<Output, CjmsError> {
+    let mut requeued = vec![];
+    let mut requeued_ids = vec![];
+    let mut failed = vec![];
+    for id in ids {
+        match set_status(
+            record,
+            id,
+            Status::NotReported,
+            reason,
+            operator,
+            db_pool,
+            statsd,
+        )
+        .await
+        {
+            Ok(value) => {
+                requeued.push(value);
+                requeued_ids.push(id.as_str());
+            }
+            Err(e) => {
+                error_and_incr!(
+                    statsd,
+                    LogKey::CjmsctlRequeueFailed,
+                    error = e,
+                    record_id = id.as_str(),
+                    "Could not requeue record. Continuing..."
+                );
+                failed.push(format!("{} ({})", id, e));
+            }
+        }
+    }
+    if !failed.is_empty() {
+        // Each requeue is committed on its own, so say which ones were
+        return Err(CjmsError::TooManyFailures(format!(
+            "Requeued {} of {}: {}. Failed: {}",
+            requeued.len(),
+            ids.len(),
+            match requeued_ids.is_empty() {
+                true => "none".to_string(),
+                false => requeued_ids.join(", "),
+            },
+            failed.join(", ")
+        )));
+    }
+    Ok(Output {
+        value: Value::Array(requeued),
+        text: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_text_lists_keys_and_values() {
+        let value = json!([{"a": "x", "b": 1, "c": null}, {"a": "y", "b": [1, 2]}]);
+        assert_eq!(
+            render_text(&value),
+            "a: x\nb: 1\nc: \n\na: y\nb: [1,2]".to_string()
+        );
+    }
+
+    #[test]
+    fn cli_parses_global_json_flag() {
+        let cli = Cli::parse_from(["cjmsctl", "counts", "--json"]);
+        assert!(cli.json);
+        assert!(matches!(cli.command, Command::Counts));
+        let cli = Cli::parse_from([
+            "cjmsctl",
+            "requeue",
+            "refund",
+            "a",
+            "b",
+            "--reason",
+            "Lost by CJ",
+            "--operator",
+            "ops",
+        ]);
+        assert!(!cli.json);
+        match cli.command {
+            Command::Requeue { ids, .. } => assert_eq!(ids, vec!["a", "b"]),
+            _ => panic!("Wrong command"),
+        }
+    }
+}
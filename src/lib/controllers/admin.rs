@@ -0,0 +1,546 @@
+use std::str::FromStr;
+
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use time::{Date, Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    error_and_incr, info_and_incr,
+    jobs::reconcile::{discrepancies_to_csv, find_discrepancies},
+    models::{
+        aic::{AICModel, AIC},
+        filters::{Cursor, RecordFilter, DEFAULT_PAGE_SIZE},
+        refunds::{Refund, RefundModel},
+        status_history::{Status, StatusHistory, UpdateStatus},
+        status_overrides::{StatusOverrideError, StatusOverrideModel},
+        subscriptions::{Subscription, SubscriptionModel},
+        timeline::{TimelineLookup, TimelineModel},
+    },
+    settings::Settings,
+    telemetry::{LogKey, StatsD},
+};
+
+#[derive(Deserialize)]
+pub struct AdminQuery {
+    pub status: Option<String>,
+    // Dates are YYYY-MM-DD. since is inclusive, before is exclusive.
+    pub since: Option<String>,
+    pub before: Option<String>,
+    pub plan_id: Option<String>,
+    pub country: Option<String>,
+    pub flow_id: Option<String>,
+    pub cj_event_value: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    // Only used by /admin/aics
+    #[serde(default)]
+    pub archived: bool,
+}
+
+fn parse_date(value: &Option<String>, name: &str) -> Result<Option<OffsetDateTime>, String> {
+    match value {
+        Some(v) => match Date::parse(v, "%F") {
+            Ok(d) => Ok(Some(d.midnight().assume_utc())),
+            Err(_) => Err(format!("Invalid {}. Expected YYYY-MM-DD.", name)),
+        },
+        None => Ok(None),
+    }
+}
+
+impl AdminQuery {
+    pub fn to_filter(&self) -> Result<RecordFilter, String> {
+        let status = match &self.status {
+            Some(v) => match Status::from_str(v) {
+                Ok(status) => Some(status),
+                Err(_) => return Err(format!("Invalid status: {}", v)),
+            },
+            None => None,
+        };
+        let after = match &self.cursor {
+            Some(v) => match Cursor::decode(v) {
+                Some(cursor) => Some(cursor),
+                None => return Err("Invalid cursor.".to_string()),
+            },
+            None => None,
+        };
+        Ok(RecordFilter {
+            status,
+            since: parse_date(&self.since, "since")?,
+            before: parse_date(&self.before, "before")?,
+            plan_id: self.plan_id.clone(),
+            country: self.country.clone(),
+            flow_id: self.flow_id.clone(),
+            cj_event_value: self.cj_event_value.clone(),
+            after,
+            limit: self.limit.unwrap_or(DEFAULT_PAGE_SIZE),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusHistoryEntryResponse {
+    pub status: Status,
+    #[serde(with = "time::serde::timestamp")]
+    pub t: OffsetDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+fn decode_status_history(status_history: Option<StatusHistory>) -> Vec<StatusHistoryEntryResponse> {
+    status_history
+        .map(|h| h.entries)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|e| StatusHistoryEntryResponse {
+            status: e.status,
+            t: e.t,
+            reason: e.reason,
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SubscriptionResponse {
+    pub id: Uuid,
+    pub flow_id: String,
+    pub subscription_id: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub report_timestamp: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub subscription_created: OffsetDateTime,
+    pub quantity: i32,
+    pub plan_id: String,
+    pub plan_currency: String,
+    pub plan_amount: i32,
+    pub country: Option<String>,
+    pub coupons: Vec<String>,
+    pub aic_id: Option<Uuid>,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub aic_expires: Option<OffsetDateTime>,
+    pub cj_event_value: Option<String>,
+    pub status: Option<Status>,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub status_t: Option<OffsetDateTime>,
+    pub status_history: Vec<StatusHistoryEntryResponse>,
+    pub re_report_count: i32,
+}
+
+impl From<Subscription> for SubscriptionResponse {
+    // fxa_uid is deliberately left out
+    fn from(sub: Subscription) -> Self {
+        SubscriptionResponse {
+            status: sub.get_status(),
+            status_t: sub.get_status_t(),
+            status_history: decode_status_history(sub.get_status_history()),
+            id: sub.id,
+            flow_id: sub.flow_id,
+            subscription_id: sub.subscription_id,
+            report_timestamp: sub.report_timestamp,
+            subscription_created: sub.subscription_created,
+            quantity: sub.quantity,
+            plan_id: sub.plan_id,
+            plan_currency: sub.plan_currency,
+            plan_amount: sub.plan_amount,
+            country: sub.country,
+            coupons: sub.coupons,
+            aic_id: sub.aic_id,
+            aic_expires: sub.aic_expires,
+            cj_event_value: sub.cj_event_value,
+            re_report_count: sub.re_report_count,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RefundResponse {
+    pub id: Uuid,
+    pub refund_id: String,
+    pub subscription_id: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub refund_created: OffsetDateTime,
+    pub refund_amount: i32,
+    pub refund_status: Option<String>,
+    pub refund_reason: Option<String>,
+    pub correction_file_date: Option<String>,
+    pub status: Option<Status>,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub status_t: Option<OffsetDateTime>,
+    pub status_history: Vec<StatusHistoryEntryResponse>,
+}
+
+impl From<Refund> for RefundResponse {
+    fn from(refund: Refund) -> Self {
+        RefundResponse {
+            status: refund.get_status(),
+            status_t: refund.get_status_t(),
+            status_history: decode_status_history(refund.get_status_history()),
+            id: refund.id,
+            refund_id: refund.refund_id,
+            subscription_id: refund.subscription_id,
+            refund_created: refund.refund_created,
+            refund_amount: refund.refund_amount,
+            refund_status: refund.refund_status,
+            refund_reason: refund.refund_reason,
+            correction_file_date: refund.correction_file_date.map(|d| d.format("%F")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AICRecordResponse {
+    pub id: Uuid,
+    pub cj_event_value: String,
+    pub flow_id: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub created: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub expires: OffsetDateTime,
+}
+
+impl From<AIC> for AICRecordResponse {
+    fn from(aic: AIC) -> Self {
+        AICRecordResponse {
+            id: aic.id,
+            cj_event_value: aic.cj_event_value,
+            flow_id: aic.flow_id,
+            created: aic.created,
+            expires: aic.expires,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page<T> {
+    pub records: Vec<T>,
+    // Pass back as ?cursor= to get the next page. None when there are no more records.
+    pub next_cursor: Option<String>,
+}
+
+fn next_cursor<T>(records: &[T], filter: &RecordFilter, key: fn(&T) -> Cursor) -> Option<String> {
+    if records.len() as i64 == filter.page_size() {
+        records.last().map(|r| key(r).encode())
+    } else {
+        None
+    }
+}
+
+fn bad_request(statsd: &StatsD, message: String) -> HttpResponse {
+    error_and_incr!(
+        statsd,
+        LogKey::AdminQueryInvalid,
+        message = message.as_str(),
+        "Invalid admin query."
+    );
+    HttpResponse::BadRequest().json(json!({ "error": message }))
+}
+
+fn internal_error(statsd: &StatsD, e: sqlx::Error) -> HttpResponse {
+    error_and_incr!(
+        statsd,
+        LogKey::AdminQueryFailed,
+        error = e,
+        "Admin query failed."
+    );
+    HttpResponse::InternalServerError().json(json!({ "error": "Query failed." }))
+}
+
+pub async fn subscriptions(
+    query: web::Query<AdminQuery>,
+    pool: web::Data<PgPool>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::AdminSubscriptionsAccessed,
+        "Admin subscriptions accessed"
+    );
+    let filter = match query.to_filter() {
+        Ok(filter) => filter,
+        Err(message) => return bad_request(&statsd, message),
+    };
+    let model = SubscriptionModel {
+        db_pool: pool.as_ref(),
+    };
+    match model.fetch_filtered(&filter).await {
+        Ok(subs) => {
+            let next_cursor = next_cursor(&subs, &filter, |s| Cursor {
+                t: s.subscription_created,
+                id: s.id,
+            });
+            HttpResponse::Ok().json(Page {
+                records: subs.into_iter().map(SubscriptionResponse::from).collect(),
+                next_cursor,
+            })
+        }
+        Err(e) => internal_error(&statsd, e),
+    }
+}
+
+pub async fn refunds(
+    query: web::Query<AdminQuery>,
+    pool: web::Data<PgPool>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::AdminRefundsAccessed,
+        "Admin refunds accessed"
+    );
+    let filter = match query.to_filter() {
+        Ok(filter) => filter,
+        Err(message) => return bad_request(&statsd, message),
+    };
+    let model = RefundModel {
+        db_pool: pool.as_ref(),
+    };
+    match model.fetch_filtered(&filter).await {
+        Ok(refunds) => {
+            let next_cursor = next_cursor(&refunds, &filter, |r| Cursor {
+                t: r.refund_created,
+                id: r.id,
+            });
+            HttpResponse::Ok().json(Page {
+                records: refunds.into_iter().map(RefundResponse::from).collect(),
+                next_cursor,
+            })
+        }
+        Err(e) => internal_error(&statsd, e),
+    }
+}
+
+pub async fn aics(
+    query: web::Query<AdminQuery>,
+    pool: web::Data<PgPool>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::AdminAicsAccessed,
+        "Admin aics accessed"
+    );
+    let filter = match query.to_filter() {
+        Ok(filter) => filter,
+        Err(message) => return bad_request(&statsd, message),
+    };
+    let model = AICModel {
+        db_pool: pool.as_ref(),
+    };
+    let result = match query.archived {
+        true => model.fetch_filtered_from_archive(&filter).await,
+        false => model.fetch_filtered(&filter).await,
+    };
+    match result {
+        Ok(aics) => {
+            let next_cursor = next_cursor(&aics, &filter, |a| Cursor {
+                t: a.created,
+                id: a.id,
+            });
+            HttpResponse::Ok().json(Page {
+                records: aics.into_iter().map(AICRecordResponse::from).collect(),
+                next_cursor,
+            })
+        }
+        Err(e) => internal_error(&statsd, e),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusOverrideRequest {
+    pub status: Status,
+    pub reason: String,
+    pub operator: String,
+}
+
+fn status_override_error(statsd: &StatsD, record_id: &str, e: StatusOverrideError) -> HttpResponse {
+    match e {
+        StatusOverrideError::Database(sqlx::Error::RowNotFound) => {
+            error_and_incr!(
+                statsd,
+                LogKey::AdminStatusOverrideNotFound,
+                record_id = record_id,
+                "Record to override could not be found."
+            );
+            HttpResponse::NotFound().json(json!({ "error": "Record not found." }))
+        }
+        StatusOverrideError::Database(e) => {
+            error_and_incr!(
+                statsd,
+                LogKey::AdminStatusOverrideFailed,
+                error = e,
+                record_id = record_id,
+                "Status override failed."
+            );
+            HttpResponse::InternalServerError().json(json!({ "error": "Status override failed." }))
+        }
+        _ => bad_request(statsd, e.to_string()),
+    }
+}
+
+pub async fn override_subscription_status(
+    path: web::Path<Uuid>,
+    data: web::Json<StatusOverrideRequest>,
+    pool: web::Data<PgPool>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let model = StatusOverrideModel {
+        db_pool: pool.as_ref(),
+    };
+    match model
+        .override_subscription_status(&id, data.status.clone(), &data.reason, &data.operator)
+        .await
+    {
+        Ok(sub) => {
+            info_and_incr!(
+                statsd.as_ref(),
+                LogKey::AdminStatusOverride,
+                sub_id = id.to_string().as_str(),
+                status = data.status.to_string().as_str(),
+                operator = data.operator.as_str(),
+                "Subscription status overridden."
+            );
+            HttpResponse::Ok().json(SubscriptionResponse::from(sub))
+        }
+        Err(e) => status_override_error(&statsd, &id.to_string(), e),
+    }
+}
+
+pub async fn override_refund_status(
+    path: web::Path<String>,
+    data: web::Json<StatusOverrideRequest>,
+    pool: web::Data<PgPool>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    let refund_id = path.into_inner();
+    let model = StatusOverrideModel {
+        db_pool: pool.as_ref(),
+    };
+    match model
+        .override_refund_status(
+            &refund_id,
+            data.status.clone(),
+            &data.reason,
+            &data.operator,
+        )
+        .await
+    {
+        Ok(refund) => {
+            info_and_incr!(
+                statsd.as_ref(),
+                LogKey::AdminStatusOverride,
+                refund_id = refund_id.as_str(),
+                status = data.status.to_string().as_str(),
+                operator = data.operator.as_str(),
+                "Refund status overridden."
+            );
+            HttpResponse::Ok().json(RefundResponse::from(refund))
+        }
+        Err(e) => status_override_error(&statsd, &refund_id, e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TimelineQuery {
+    pub flow_id: Option<String>,
+    pub subscription_id: Option<String>,
+    pub aic_id: Option<String>,
+}
+
+pub async fn timeline(
+    query: web::Query<TimelineQuery>,
+    pool: web::Data<PgPool>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::AdminTimelineAccessed,
+        "Admin timeline accessed"
+    );
+    let aic_id = match &query.aic_id {
+        Some(v) => match Uuid::parse_str(v) {
+            Ok(id) => Some(id),
+            Err(_) => return bad_request(&statsd, "Invalid aic_id.".to_string()),
+        },
+        None => None,
+    };
+    let lookup = match (&query.flow_id, &query.subscription_id, &aic_id) {
+        (Some(flow_id), None, None) => TimelineLookup::FlowId(flow_id),
+        (None, Some(subscription_id), None) => TimelineLookup::SubscriptionId(subscription_id),
+        (None, None, Some(aic_id)) => TimelineLookup::AICId(aic_id),
+        _ => {
+            return bad_request(
+                &statsd,
+                "Exactly one of flow_id, subscription_id or aic_id is required.".to_string(),
+            )
+        }
+    };
+    let model = TimelineModel {
+        db_pool: pool.as_ref(),
+    };
+    match model.fetch(lookup).await {
+        Ok(timeline) => HttpResponse::Ok().json(timeline),
+        Err(sqlx::Error::RowNotFound) => {
+            HttpResponse::NotFound().json(json!({ "error": "Nothing found for this lookup." }))
+        }
+        Err(e) => internal_error(&statsd, e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReconciliationQuery {
+    // csv or json, defaults to json
+    pub format: Option<String>,
+    // YYYY-MM-DD. Orders CJ posted a commission for from since up to before. Default to
+    // the last settings.reconcile_window_days.
+    pub since: Option<String>,
+    pub before: Option<String>,
+}
+
+pub async fn reconciliation(
+    query: web::Query<ReconciliationQuery>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<Settings>,
+    statsd: web::Data<StatsD>,
+) -> HttpResponse {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::AdminReconciliationAccessed,
+        "Admin reconciliation accessed"
+    );
+    let csv = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => {
+            return bad_request(
+                &statsd,
+                format!("Invalid format: {}. Expected csv or json.", other),
+            )
+        }
+    };
+    let (since, before) = match (
+        parse_date(&query.since, "since"),
+        parse_date(&query.before, "before"),
+    ) {
+        (Ok(since), Ok(before)) => {
+            let before = before.unwrap_or_else(OffsetDateTime::now_utc);
+            let since = since.unwrap_or_else(|| {
+                before - Duration::days(i64::from(settings.reconcile_window_days))
+            });
+            (since, before)
+        }
+        (Err(e), _) | (_, Err(e)) => return bad_request(&statsd, e),
+    };
+    match find_discrepancies(pool.as_ref(), &settings, &since, &before).await {
+        Ok(reconciliation) if csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .body(discrepancies_to_csv(&reconciliation.discrepancies)),
+        Ok(reconciliation) => HttpResponse::Ok().json(json!({
+            "checked": reconciliation.checked,
+            "discrepancies": reconciliation.discrepancies,
+        })),
+        Err(e) => internal_error(&statsd, e),
+    }
+}
@@ -4,17 +4,21 @@ use sqlx::PgPool;
 use time::{Date, OffsetDateTime};
 
 use crate::{
+    cj::programs::{CJProgram, CJPrograms, DEFAULT_PROGRAM},
+    error::CjmsError,
     error_and_incr, info_and_incr,
     models::{
         refunds::{Refund, RefundModel},
         subscriptions::SubscriptionModel,
     },
-    settings::Settings,
     telemetry::{LogKey, StatsD},
 };
 
-async fn build_body_from_results(
-    settings: &Settings,
+// The corrections file for program, with the refunds for subscriptions routed to it.
+// Refunds for other programs' subscriptions are in their own files.
+pub async fn build_body_from_results(
+    programs: &CJPrograms,
+    program: &CJProgram,
     results: Vec<Refund>,
     db_pool: &PgPool,
     statsd: &StatsD,
@@ -22,7 +26,7 @@ async fn build_body_from_results(
     let mut body = format!(
         r#"&CID={}
 &SUBID={}"#,
-        settings.cj_sftp_user, settings.cj_subid
+        program.cj_sftp_user, program.cj_subid
     );
     let subscriptions = SubscriptionModel { db_pool };
     for refund in results {
@@ -52,6 +56,9 @@ async fn build_body_from_results(
                 continue;
             }
         };
+        if programs.for_plan(&sub.plan_id).name != program.name {
+            continue;
+        }
         body.push_str(&format!(
             r#"
 RETRN,,{}"#,
@@ -61,13 +68,26 @@ RETRN,,{}"#,
     body
 }
 
-async fn get_results_for_day(db_pool: &PgPool, day: Date) -> Vec<Refund> {
+async fn get_results_for_day(
+    db_pool: &PgPool,
+    day: Date,
+    statsd: &StatsD,
+) -> Result<Vec<Refund>, CjmsError> {
     let refunds = RefundModel { db_pool };
-    // Intentional panic, can't continue if can't get refunds for today
+    // Can't build a partial file, so the whole request fails
     refunds
         .fetch_by_correction_file_day(&day)
         .await
-        .unwrap_or_else(|_| panic!("Could not fetch refunds for date: {}", day))
+        .map_err(|e| {
+            error_and_incr!(
+                statsd,
+                LogKey::CorrectionsReportFailed,
+                error = e,
+                day = day.to_string().as_str(),
+                "Could not fetch refunds for day"
+            );
+            e.into()
+        })
 }
 
 #[derive(Deserialize)]
@@ -89,37 +109,120 @@ mod date_parser {
     }
 }
 
+// The refunds for day in program's corrections file, 404 if there's no such program
+async fn respond_for_day(
+    program: &str,
+    day: Date,
+    pool: &PgPool,
+    programs: &CJPrograms,
+    statsd: &StatsD,
+) -> Result<HttpResponse, CjmsError> {
+    let program = match programs.get(program) {
+        Some(program) => program,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let results = get_results_for_day(pool, day, statsd).await?;
+    let body = build_body_from_results(programs, program, results, pool, statsd).await;
+    Ok(HttpResponse::Ok().body(body))
+}
+
 pub async fn by_day(
     path: web::Path<CorrectionsByDayPath>,
     pool: web::Data<PgPool>,
-    settings: web::Data<Settings>,
+    programs: web::Data<CJPrograms>,
     statsd: web::Data<StatsD>,
-) -> HttpResponse {
+) -> Result<HttpResponse, CjmsError> {
     info_and_incr!(
         statsd.as_ref(),
         LogKey::CorrectionsReportByDayAccessed,
         day = path.day.to_string().as_str(),
         "Corrections report accessed by day"
     );
-    let results = get_results_for_day(pool.as_ref(), path.day).await;
-    let body =
-        build_body_from_results(settings.as_ref(), results, pool.as_ref(), statsd.as_ref()).await;
-    HttpResponse::Ok().body(body)
+    respond_for_day(
+        DEFAULT_PROGRAM,
+        path.day,
+        pool.as_ref(),
+        programs.as_ref(),
+        statsd.as_ref(),
+    )
+    .await
 }
 
 pub async fn today(
     pool: web::Data<PgPool>,
-    settings: web::Data<Settings>,
+    programs: web::Data<CJPrograms>,
     statsd: web::Data<StatsD>,
-) -> HttpResponse {
+) -> Result<HttpResponse, CjmsError> {
     info_and_incr!(
         statsd.as_ref(),
         LogKey::CorrectionsReportTodayAccessed,
         "Corrections report accessed for today"
     );
     let today = OffsetDateTime::now_utc().date();
-    let results = get_results_for_day(pool.as_ref(), today).await;
-    let body =
-        build_body_from_results(settings.as_ref(), results, pool.as_ref(), statsd.as_ref()).await;
-    HttpResponse::Ok().body(body)
+    respond_for_day(
+        DEFAULT_PROGRAM,
+        today,
+        pool.as_ref(),
+        programs.as_ref(),
+        statsd.as_ref(),
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct ProgramCorrectionsPath {
+    program: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProgramCorrectionsByDayPath {
+    program: String,
+    #[serde(with = "date_parser")]
+    day: Date,
+}
+
+pub async fn program_by_day(
+    path: web::Path<ProgramCorrectionsByDayPath>,
+    pool: web::Data<PgPool>,
+    programs: web::Data<CJPrograms>,
+    statsd: web::Data<StatsD>,
+) -> Result<HttpResponse, CjmsError> {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::CorrectionsReportByDayAccessed,
+        program = path.program.as_str(),
+        day = path.day.to_string().as_str(),
+        "Corrections report accessed by day"
+    );
+    respond_for_day(
+        &path.program,
+        path.day,
+        pool.as_ref(),
+        programs.as_ref(),
+        statsd.as_ref(),
+    )
+    .await
+}
+
+pub async fn program_today(
+    path: web::Path<ProgramCorrectionsPath>,
+    pool: web::Data<PgPool>,
+    programs: web::Data<CJPrograms>,
+    statsd: web::Data<StatsD>,
+) -> Result<HttpResponse, CjmsError> {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::CorrectionsReportTodayAccessed,
+        program = path.program.as_str(),
+        "Corrections report accessed for today"
+    );
+    let today = OffsetDateTime::now_utc().date();
+    respond_for_day(
+        &path.program,
+        today,
+        pool.as_ref(),
+        programs.as_ref(),
+        statsd.as_ref(),
+    )
+    .await
 }
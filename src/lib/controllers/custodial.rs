@@ -1,9 +1,13 @@
 use crate::{
     error, error_and_incr, info, info_and_incr,
+    models::job_runs::JobRunModel,
     telemetry::{LogKey, StatsD},
     version::{read_version, VERSION_FILE},
+    worker::Job,
 };
 use actix_web::{web, Error, HttpResponse};
+use serde_json::{json, Map, Value as JsonValue};
+use sqlx::PgPool;
 use std::time::Duration;
 use time::OffsetDateTime;
 
@@ -29,6 +33,44 @@ pub async fn version() -> Result<HttpResponse, Error> {
     Ok(HttpResponse::Ok().json(version_data))
 }
 
+// Last run and last successful run of every job, null if it has never happened
+pub async fn jobs(pool: web::Data<PgPool>, statsd: web::Data<StatsD>) -> HttpResponse {
+    info_and_incr!(
+        statsd.as_ref(),
+        LogKey::JobsStatusAccessed,
+        "Jobs status accessed"
+    );
+    let model = JobRunModel {
+        db_pool: pool.as_ref(),
+    };
+    let (last_runs, last_successes) = match (
+        model.fetch_last_run_per_job().await,
+        model.fetch_last_success_per_job().await,
+    ) {
+        (Ok(last_runs), Ok(last_successes)) => (last_runs, last_successes),
+        (Err(e), _) | (_, Err(e)) => {
+            error_and_incr!(
+                statsd.as_ref(),
+                LogKey::JobsStatusQueryFailed,
+                error = e,
+                "Jobs status query failed."
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let mut body = Map::new();
+    for job in Job::ALL {
+        let name = job.to_string();
+        let last_run = last_runs.iter().find(|r| r.job == name);
+        let last_success = last_successes.iter().find(|r| r.job == name);
+        body.insert(
+            name,
+            json!({"last_run": last_run, "last_success": last_success}),
+        );
+    }
+    HttpResponse::Ok().json(JsonValue::Object(body))
+}
+
 // Debug endpoints
 
 pub async fn log() -> Result<HttpResponse, Error> {
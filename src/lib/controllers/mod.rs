@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod aic;
 pub mod corrections;
 pub mod custodial;
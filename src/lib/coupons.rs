@@ -0,0 +1,263 @@
+use serde::Deserialize;
+
+use crate::models::subscriptions::{LineItem, Subscription};
+
+// The codes in BigQuery's promotion_codes, e.g. "VPN10, SPRING". Empty and repeated
+// codes are dropped.
+pub fn parse_coupons(promotion_codes: &str) -> Vec<String> {
+    let mut coupons: Vec<String> = vec![];
+    for code in promotion_codes.split(',').map(str::trim) {
+        if !code.is_empty() && !coupons.iter().any(|c| c == code) {
+            coupons.push(code.to_string());
+        }
+    }
+    coupons
+}
+
+// As CJ takes them in COUPON, comma separated without spaces
+pub fn format_coupons(coupons: &[String]) -> String {
+    coupons.join(",")
+}
+
+// What to do with subscriptions that used a promotion code. Codes are matched ignoring
+// case, as Stripe does.
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CouponRule {
+    pub code: String,
+    // Mark WillNotReport instead of reporting, e.g. employee or partner codes
+    #[serde(default)]
+    pub exclude: bool,
+    // Logged with excluded subscriptions. Optional.
+    #[serde(default)]
+    pub reason: Option<String>,
+    // Reported for each of the quantity of the subscription's plan instead of its amount,
+    // in minor units of the plan currency. Optional.
+    #[serde(default)]
+    pub amount: Option<i32>,
+    // Reported as TYPE instead of the program's cj_type. Optional.
+    #[serde(default)]
+    pub cj_type: Option<String>,
+}
+
+// The coupon_rules setting. When a subscription used more than one code with a rule,
+// exclusion wins, then the first code's amount and cj_type.
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct CouponRules(pub Vec<CouponRule>);
+
+impl CouponRules {
+    fn for_coupons<'a>(&'a self, coupons: &'a [String]) -> impl Iterator<Item = &'a CouponRule> {
+        coupons.iter().filter_map(move |coupon| {
+            self.0
+                .iter()
+                .find(|rule| rule.code.eq_ignore_ascii_case(coupon))
+        })
+    }
+
+    // Why the subscription isn't reported, None if it is
+    pub fn excluded_reason(&self, coupons: &[String]) -> Option<String> {
+        self.for_coupons(coupons)
+            .find(|rule| rule.exclude)
+            .map(|rule| {
+                rule.reason
+                    .clone()
+                    .unwrap_or_else(|| format!("Coupon {} is excluded from reporting", rule.code))
+            })
+    }
+
+    pub fn amount(&self, coupons: &[String]) -> Option<i32> {
+        self.for_coupons(coupons).find_map(|rule| rule.amount)
+    }
+
+    // The items we report to CJ. A rule's amount replaces the amount of the
+    // subscription's plan and its discount, as it's what the plan is sold for. Other items
+    // are reported as they were sold.
+    pub fn reported_items(&self, sub: &Subscription) -> Vec<LineItem> {
+        let amount = self.amount(&sub.coupons);
+        sub.items()
+            .into_iter()
+            .map(|item| match amount {
+                Some(amount) if item.plan_id == sub.plan_id => LineItem {
+                    amount,
+                    discount: 0,
+                    ..item
+                },
+                _ => item,
+            })
+            .collect()
+    }
+
+    // What CJ sells the reported items for, after their discounts
+    pub fn reported_total(&self, sub: &Subscription) -> i64 {
+        self.reported_items(sub)
+            .iter()
+            .map(|item| {
+                i64::from(item.amount) * i64::from(item.quantity) - i64::from(item.discount)
+            })
+            .sum()
+    }
+
+    // What CJ reverses for a refund of refund_amount, the same share of reported_total as
+    // the refund is of what was paid. All of reported_total if nothing was paid.
+    pub fn refunded_total(&self, sub: &Subscription, refund_amount: i32) -> i64 {
+        let reported_total = self.reported_total(sub);
+        let paid = CouponRules::default().reported_total(sub);
+        if paid <= 0 {
+            return reported_total;
+        }
+        let share = i128::from(reported_total) * i128::from(refund_amount);
+        let paid = i128::from(paid);
+        // Rounded half away from zero
+        let refunded = (2 * share + share.signum() * paid) / (2 * paid);
+        i64::try_from(refunded).unwrap_or(reported_total)
+    }
+
+    pub fn cj_type<'a>(&'a self, coupons: &'a [String]) -> Option<&'a str> {
+        self.for_coupons(coupons)
+            .find_map(|rule| rule.cj_type.as_deref())
+    }
+
+    // Codes whose rule's amount or cj_type isn't used, as an earlier code's rule has one.
+    // Empty unless the subscription's coupons have conflicting rules.
+    pub fn conflicting_codes<'a>(&'a self, coupons: &'a [String]) -> Vec<&'a str> {
+        let mut has_amount = false;
+        let mut has_cj_type = false;
+        let mut conflicting = vec![];
+        for rule in self.for_coupons(coupons) {
+            if (has_amount && rule.amount.is_some()) || (has_cj_type && rule.cj_type.is_some()) {
+                conflicting.push(rule.code.as_str());
+            }
+            has_amount |= rule.amount.is_some();
+            has_cj_type |= rule.cj_type.is_some();
+        }
+        conflicting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promotion_codes_are_parsed_into_a_list() {
+        assert_eq!(parse_coupons(""), Vec::<String>::new());
+        assert_eq!(parse_coupons("  "), Vec::<String>::new());
+        assert_eq!(parse_coupons(" VPN10 "), vec!["VPN10"]);
+        assert_eq!(
+            parse_coupons("VPN10, SPRING,,VPN10"),
+            vec!["VPN10", "SPRING"]
+        );
+        assert_eq!(
+            format_coupons(&parse_coupons("VPN10, SPRING")),
+            "VPN10,SPRING"
+        );
+    }
+
+    #[test]
+    fn rules_apply_to_any_of_the_coupons() {
+        let rules: CouponRules = serde_yaml::from_str(
+            "
+- code: EMPLOYEE
+  exclude: true
+  reason: employee
+- code: PARTNER
+  exclude: true
+- code: HALF
+  amount: 500
+  cj_type: half-price
+- code: OTHER
+  amount: 100
+",
+        )
+        .unwrap();
+        let coupons = |codes: &str| parse_coupons(codes);
+        assert_eq!(rules.excluded_reason(&coupons("SPRING")), None);
+        assert_eq!(
+            rules.excluded_reason(&coupons("SPRING, employee")),
+            Some("employee".to_string())
+        );
+        assert_eq!(
+            rules.excluded_reason(&coupons("PARTNER")),
+            Some("Coupon PARTNER is excluded from reporting".to_string())
+        );
+        assert_eq!(rules.amount(&coupons("SPRING")), None);
+        assert_eq!(rules.amount(&coupons("OTHER, HALF")), Some(100));
+        assert_eq!(rules.cj_type(&coupons("SPRING")), None);
+        assert_eq!(rules.cj_type(&coupons("OTHER, half")), Some("half-price"));
+        assert_eq!(
+            rules.conflicting_codes(&coupons("HALF, SPRING")),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            rules.conflicting_codes(&coupons("OTHER, half")),
+            vec!["HALF"]
+        );
+    }
+
+    #[test]
+    fn rule_amounts_replace_the_plan_items_amount() {
+        use crate::models::subscriptions::test_subscriptions::make_fake_sub;
+
+        let rules = CouponRules(vec![CouponRule {
+            code: "HALF".to_string(),
+            exclude: false,
+            reason: None,
+            amount: Some(500),
+            cj_type: None,
+        }]);
+        let mut sub = make_fake_sub();
+        sub.plan_id = "bundle".to_string();
+        sub.plan_amount = 1000;
+        sub.quantity = 2;
+        sub.coupons = vec![];
+        assert_eq!(rules.reported_total(&sub), 2000);
+        sub.coupons = vec!["HALF".to_string()];
+        assert_eq!(rules.reported_total(&sub), 1000);
+
+        sub.set_line_items(&[
+            LineItem {
+                plan_id: "bundle".to_string(),
+                quantity: 1,
+                amount: 1000,
+                discount: 100,
+            },
+            LineItem {
+                plan_id: "add-on".to_string(),
+                quantity: 3,
+                amount: 200,
+                discount: 0,
+            },
+        ]);
+        let items = rules.reported_items(&sub);
+        assert_eq!(items[0].amount, 500);
+        assert_eq!(items[0].discount, 0);
+        assert_eq!(items[1].amount, 200);
+        assert_eq!(rules.reported_total(&sub), 500 + 600);
+    }
+
+    #[test]
+    fn refunds_reverse_their_share_of_the_reported_total() {
+        use crate::models::subscriptions::test_subscriptions::make_fake_sub;
+
+        let rules = CouponRules(vec![CouponRule {
+            code: "PARTNER".to_string(),
+            exclude: false,
+            reason: None,
+            amount: Some(100),
+            cj_type: None,
+        }]);
+        let mut sub = make_fake_sub();
+        sub.plan_amount = 999;
+        sub.quantity = 2;
+        sub.coupons = vec!["PARTNER".to_string()];
+        assert_eq!(rules.refunded_total(&sub, 1998), 200);
+        assert_eq!(rules.refunded_total(&sub, 999), 100);
+        assert_eq!(rules.refunded_total(&sub, 500), 50);
+        assert_eq!(rules.refunded_total(&sub, 0), 0);
+        sub.coupons = vec![];
+        assert_eq!(rules.refunded_total(&sub, 500), 500);
+        sub.coupons = vec!["PARTNER".to_string()];
+        sub.plan_amount = 0;
+        assert_eq!(rules.refunded_total(&sub, 0), 200);
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{
+    bigquery::client::BQError, cj::client::CJError, error,
+    models::status_overrides::StatusOverrideError, telemetry::LogKey,
+};
+
+#[derive(Error, Debug)]
+pub enum CjmsError {
+    #[error("Configuration error: {0}")]
+    Configuration(String),
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("BigQuery error: {0}")]
+    BigQuery(#[from] BQError),
+
+    #[error("CJ error: {0}")]
+    CJ(#[from] CJError),
+
+    #[error("Data integrity error: {0}")]
+    DataIntegrity(String),
+
+    // Bad arguments from an operator or client
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("Job panicked: {0}")]
+    JobPanicked(String),
+
+    #[error("Too many records failed: {0}")]
+    TooManyFailures(String),
+}
+
+impl From<sqlx::migrate::MigrateError> for CjmsError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        CjmsError::Database(sqlx::Error::Migrate(Box::new(e)))
+    }
+}
+
+// A missing reason or operator is the caller's mistake
+impl From<StatusOverrideError> for CjmsError {
+    fn from(e: StatusOverrideError) -> Self {
+        match e {
+            StatusOverrideError::Database(e) => CjmsError::Database(e),
+            e => CjmsError::InvalidInput(e.to_string()),
+        }
+    }
+}
+
+impl CjmsError {
+    // Process exit code for binaries that stop on this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CjmsError::JobPanicked(_) | CjmsError::TooManyFailures(_) => 1,
+            CjmsError::Configuration(_) => 2,
+            CjmsError::Database(_) => 3,
+            CjmsError::BigQuery(_) => 4,
+            CjmsError::CJ(_) => 5,
+            CjmsError::DataIntegrity(_) => 6,
+            CjmsError::InvalidInput(_) => 7,
+        }
+    }
+}
+
+// For binaries that can't go on. Logs the error and exits with its exit code.
+// Generic so it can be passed to unwrap_or_else.
+pub fn exit_on_error<T>(e: CjmsError) -> T {
+    error!(LogKey::ExitOnError, error = e, "Exiting on error");
+    eprintln!("Error: {}", e);
+    std::process::exit(e.exit_code())
+}
+
+// Details are logged by the controller. Clients only get the kind of failure.
+impl ResponseError for CjmsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            CjmsError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            CjmsError::BigQuery(_) | CjmsError::CJ(_) => StatusCode::BAD_GATEWAY,
+            CjmsError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            CjmsError::Configuration(_) => "Configuration error.",
+            CjmsError::Database(sqlx::Error::RowNotFound) => "Not found.",
+            CjmsError::Database(_) => "Database error.",
+            CjmsError::BigQuery(_) => "BigQuery error.",
+            CjmsError::CJ(_) => "CJ error.",
+            CjmsError::DataIntegrity(_) => "Data integrity error.",
+            CjmsError::InvalidInput(_) => "Invalid input.",
+            CjmsError::JobPanicked(_) | CjmsError::TooManyFailures(_) => "Job failed.",
+        };
+        HttpResponse::build(self.status_code()).json(json!({ "error": message }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    #[tokio::test]
+    async fn error_response_is_json_without_details() {
+        let e = CjmsError::Database(sqlx::Error::PoolTimedOut);
+        let response = e.error_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"error":"Database error."}"#);
+        assert_eq!(
+            CjmsError::Database(sqlx::Error::RowNotFound).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            CjmsError::CJ(CJError::GraphQL("down".to_string())).status_code(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn exit_codes_differ_by_kind() {
+        assert_eq!(CjmsError::Configuration("x".to_string()).exit_code(), 2);
+        assert_eq!(
+            CjmsError::Database(sqlx::Error::PoolTimedOut).exit_code(),
+            3
+        );
+        assert_eq!(CjmsError::BigQuery(BQError::NoJobId).exit_code(), 4);
+        assert_eq!(
+            CjmsError::CJ(CJError::UnknownProgram("x".to_string())).exit_code(),
+            5
+        );
+        assert_eq!(CjmsError::DataIntegrity("x".to_string()).exit_code(), 6);
+        assert_eq!(CjmsError::InvalidInput("x".to_string()).exit_code(), 7);
+        assert_eq!(CjmsError::JobPanicked("x".to_string()).exit_code(), 1);
+    }
+}
@@ -2,21 +2,29 @@ use sqlx::{Pool, Postgres};
 use time::OffsetDateTime;
 
 use crate::{
-    error_and_incr, info_and_incr,
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
     models::{
         refunds::RefundModel,
         status_history::{Status, UpdateStatus},
     },
+    network::AffiliateNetwork,
     telemetry::{LogKey, StatsD},
 };
 
-pub async fn batch_refunds_by_day(db_pool: &Pool<Postgres>, statsd: &StatsD) {
+// Each refund that's reported is submitted to the network as a correction first, and
+// left NotReported for the next run if that fails. With dry_run, logs the status each
+// refund would move to and neither submits nor writes anything.
+pub async fn batch_refunds_by_day<N: AffiliateNetwork>(
+    db_pool: &Pool<Postgres>,
+    network: &N,
+    statsd: &StatsD,
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
     let refunds = RefundModel { db_pool };
-    // Intentional panic. Cannot continue if we can't retrieve refunds.
-    let not_reported_refunds = refunds
-        .fetch_all_by_status(Status::NotReported)
-        .await
-        .expect("Could not retrieve refunds from DB.");
+    let not_reported_refunds = refunds.fetch_all_by_status(Status::NotReported).await?;
     statsd.gauge(
         &LogKey::BatchRefundsNNotReported,
         not_reported_refunds.len(),
@@ -35,6 +43,29 @@ pub async fn batch_refunds_by_day(db_pool: &Pool<Postgres>, statsd: &StatsD) {
         if next_state == Status::Reported {
             refund.correction_file_date = Some(OffsetDateTime::now_utc().date());
         }
+        if dry_run {
+            info!(
+                LogKey::BatchRefundsDryRun,
+                refund_id = &refund.refund_id.as_str(),
+                status = &next_state.to_string().as_str(),
+                "Dry run. Would update refund status"
+            );
+            outcome.succeeded();
+            continue;
+        }
+        if next_state == Status::Reported {
+            if let Err(e) = network.submit_correction(&refund).await {
+                error_and_incr!(
+                    statsd,
+                    LogKey::BatchRefundsSubmitCorrectionFailed,
+                    error = e,
+                    refund_id = &refund.refund_id.as_str(),
+                    "Could not submit refund as a correction. Will try again next run."
+                );
+                outcome.failed();
+                continue;
+            }
+        }
         refund.update_status(next_state);
         match refunds.update_refund(&refund).await {
             Ok(r) => {
@@ -44,6 +75,7 @@ pub async fn batch_refunds_by_day(db_pool: &Pool<Postgres>, statsd: &StatsD) {
                     refund_id = &r.refund_id.as_str(),
                     "Success updating refund"
                 );
+                outcome.succeeded();
             }
             Err(e) => {
                 error_and_incr!(
@@ -53,7 +85,9 @@ pub async fn batch_refunds_by_day(db_pool: &Pool<Postgres>, statsd: &StatsD) {
                     refund_id = &refund.refund_id.as_str(),
                     "Could not update refund to be reported"
                 );
+                outcome.failed();
             }
         };
     }
+    Ok(outcome)
 }
@@ -3,7 +3,9 @@ use uuid::Uuid;
 
 use crate::{
     bigquery::client::{BQClient, BQError, ResultSet},
-    error_and_incr, info_and_incr,
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
     models::{
         refunds::{PartialRefund, Refund, RefundModel},
         status_history::{Status, UpdateStatus},
@@ -26,13 +28,21 @@ fn make_refund_from_bq_row(rs: &ResultSet) -> Result<Refund, BQError> {
     Ok(refund)
 }
 
-pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>, statsd: &StatsD) {
+// With dry_run, reads from BigQuery and the database as usual but only logs the refunds
+// it would create or update.
+pub async fn fetch_and_process_refunds(
+    bq: &BQClient,
+    db_pool: &Pool<Postgres>,
+    statsd: &StatsD,
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
     let subscriptions = SubscriptionModel { db_pool };
     let refunds = RefundModel { db_pool };
 
     // Get all results from bigquery table that stores refunds reports
     let query = "SELECT * FROM `cjms_bigquery.refunds_v1`;";
-    let mut rs = bq.get_bq_results(query).await;
+    let mut rs = bq.get_bq_results(query).await?;
     rs.report_stats(statsd, &LogKey::CheckRefunds);
     while rs.next_row() {
         // If can't deserialize e.g. required fields are not available log and move on.
@@ -53,6 +63,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                     error = e,
                     "Failed to make refund for BigQuery result row. Continuing ...",
                 );
+                outcome.failed();
                 continue;
             }
         };
@@ -69,6 +80,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                 refund_id = r.refund_id.as_str(),
                 "Subscription related to refund missing from database",
             );
+            outcome.failed();
             continue;
         }
         // Do we already have it in the refunds table
@@ -87,6 +99,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                         refund_id = refund.refund_id.as_str(),
                         "Data for refund is unchanged. Continuing..."
                     );
+                    outcome.skipped();
                     continue;
                 }
 
@@ -96,6 +109,16 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                     refund_id = refund.refund_id.as_str(),
                     "Data for refund is changed. Updating..."
                 );
+                if dry_run {
+                    info!(
+                        LogKey::CheckRefundsDryRun,
+                        refund_id = refund.refund_id.as_str(),
+                        status = &Status::NotReported.to_string().as_str(),
+                        "Dry run. Would update refund"
+                    );
+                    outcome.succeeded();
+                    continue;
+                }
                 refund.subscription_id = r.subscription_id;
                 refund.refund_created = r.refund_created;
                 refund.refund_amount = r.refund_amount;
@@ -111,6 +134,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                             refund_id = refund.refund_id.as_str(),
                             "Refund updated. Continuing..."
                         );
+                        outcome.succeeded();
                     }
                     Err(e) => {
                         error_and_incr!(
@@ -120,12 +144,22 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                             refund_id = r.refund_id.as_str(),
                             "Error updating refund. Continuing..."
                         );
+                        outcome.failed();
                     }
                 };
             }
             Err(e) => {
                 match e {
                     sqlx::Error::RowNotFound => {
+                        if dry_run {
+                            info!(
+                                LogKey::CheckRefundsDryRun,
+                                refund_id = r.refund_id.as_str(),
+                                "Dry run. Would create refund"
+                            );
+                            outcome.succeeded();
+                            continue;
+                        }
                         match refunds.create_from_refund(&r).await {
                             Ok(r) => {
                                 info_and_incr!(
@@ -134,6 +168,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                                     refund_id = r.refund_id.as_str(),
                                     "Successfully created refund"
                                 );
+                                outcome.succeeded();
                             }
                             Err(e) => match e {
                                 sqlx::Error::Database(e) => {
@@ -155,6 +190,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                                             "Database error while creating refund. Continuing..."
                                         );
                                     }
+                                    outcome.failed();
                                     continue;
                                 }
                                 _ => {
@@ -165,6 +201,7 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                                         refund_id = &r.refund_id.as_str(),
                                         "Unexpected error while creating refund. Continuing..."
                                     );
+                                    outcome.failed();
                                     continue;
                                 }
                             },
@@ -178,9 +215,11 @@ pub async fn fetch_and_process_refunds(bq: &BQClient, db_pool: &Pool<Postgres>,
                             refund_id = r.refund_id.as_str(),
                             "Error while trying to retrieve refund. Continuing..."
                         );
+                        outcome.failed();
                     }
                 }
             }
         };
     }
+    Ok(outcome)
 }
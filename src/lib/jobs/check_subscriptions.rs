@@ -4,16 +4,44 @@ use uuid::Uuid;
 
 use crate::{
     bigquery::client::{BQClient, BQError, ResultSet},
-    error_and_incr, info_and_incr,
+    coupons::parse_coupons,
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
     models::{
         aic::AICModel,
-        subscriptions::{PartialSubscription, Subscription, SubscriptionModel},
+        subscriptions::{LineItem, PartialSubscription, Subscription, SubscriptionModel},
     },
     telemetry::{LogKey, StatsD},
 };
 
+// line_items is an optional REPEATED RECORD of plan_id, quantity, amount and discount.
+// Tables without it have subscriptions that are just their plan.
+fn make_line_items_from_bq_row(rs: &ResultSet) -> Result<Vec<LineItem>, BQError> {
+    let mut records = match rs.get_records_by_name("line_items") {
+        Ok(records) => records,
+        Err(BQError::InvalidColumnName { .. }) => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut line_items = vec![];
+    while records.next_row() {
+        line_items.push(LineItem {
+            plan_id: records.require_string_by_name("plan_id")?,
+            quantity: records.require_i32_by_name("quantity")?,
+            amount: records.require_i32_by_name("amount")?,
+            discount: match records.get_i64_by_name("discount") {
+                Ok(discount) => i32::try_from(discount.unwrap_or_default())
+                    .map_err(|_| BQError::IntegerCastUnsuccessful)?,
+                Err(BQError::InvalidColumnName { .. }) => 0,
+                Err(e) => return Err(e),
+            },
+        });
+    }
+    Ok(line_items)
+}
+
 fn make_subscription_from_bq_row(rs: &ResultSet) -> Result<Subscription, BQError> {
-    let sub = Subscription::new(PartialSubscription {
+    let mut sub = Subscription::new(PartialSubscription {
         id: Uuid::new_v4(),
         flow_id: rs.require_string_by_name("flow_id")?,
         subscription_id: rs.require_string_by_name("subscription_id")?,
@@ -25,26 +53,33 @@ fn make_subscription_from_bq_row(rs: &ResultSet) -> Result<Subscription, BQError
         plan_currency: rs.require_string_by_name("plan_currency")?,
         plan_amount: rs.require_i32_by_name("plan_amount")?,
         country: rs.get_string_by_name("country")?,
-        coupons: rs
-            .get_string_by_name("promotion_codes")?
-            .map(|x| x.trim().to_string()),
+        coupons: parse_coupons(
+            rs.get_string_by_name("promotion_codes")?
+                .as_deref()
+                .unwrap_or_default(),
+        ),
         aic_id: None,
         aic_expires: None,
         cj_event_value: None,
     });
+    sub.set_line_items(&make_line_items_from_bq_row(rs)?);
     Ok(sub)
 }
 
+// With dry_run, reads from BigQuery and the database as usual but only logs the AICs
+// it would archive and the subscriptions it would create.
 pub async fn fetch_and_process_new_subscriptions(
     bq: &BQClient,
     db_pool: &Pool<Postgres>,
     statsd: &StatsD,
-) {
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
     let subscriptions = SubscriptionModel { db_pool };
     let aics = AICModel { db_pool };
     // Get all results from bigquery table that stores new subscription reports
     let query = "SELECT * FROM `cjms_bigquery.subscriptions_v1`;";
-    let mut rs = bq.get_bq_results(query).await;
+    let mut rs = bq.get_bq_results(query).await?;
     rs.report_stats(statsd, &LogKey::CheckSubscriptions);
     while rs.next_row() {
         // If can't deserialize e.g. required fields are not available log and move on.
@@ -65,6 +100,7 @@ pub async fn fetch_and_process_new_subscriptions(
                     error = e,
                     "Failed to make subscription for BigQuery result row. Continuing...",
                 );
+                outcome.failed();
                 continue;
             }
         };
@@ -95,6 +131,7 @@ pub async fn fetch_and_process_new_subscriptions(
                         error = e,
                         "Error getting aic for subscription. Continuing...",
                     );
+                    outcome.failed();
                     continue;
                 }
             },
@@ -103,6 +140,28 @@ pub async fn fetch_and_process_new_subscriptions(
         sub.cj_event_value = Some(aic.cj_event_value.clone());
         sub.aic_expires = Some(aic.expires);
 
+        if dry_run {
+            // Creating would fail with a duplicate key violation
+            let duplicate = subscriptions
+                .fetch_one_by_flow_id(&sub.flow_id)
+                .await
+                .is_ok();
+            info!(
+                LogKey::CheckSubscriptionsDryRun,
+                sub_id = sub.id.to_string().as_str(),
+                flow_id = sub.flow_id.as_str(),
+                aic_id = aic.id.to_string().as_str(),
+                archive_aic = !aic_found_in_archive,
+                duplicate = duplicate,
+                "Dry run. Would create subscription"
+            );
+            match duplicate {
+                true => outcome.skipped(),
+                false => outcome.succeeded(),
+            }
+            continue;
+        }
+
         // Archive the AIC
         if !aic_found_in_archive {
             match aics.archive_aic(&aic).await {
@@ -122,6 +181,7 @@ pub async fn fetch_and_process_new_subscriptions(
                         aic_id = aic.id.to_string().as_str(),
                         "Failed to archive aic entry. Continuing...",
                     );
+                    outcome.failed();
                     continue;
                 }
             };
@@ -135,17 +195,22 @@ pub async fn fetch_and_process_new_subscriptions(
                     sub_id = sub.id.to_string().as_str(),
                     "Successfully created subscription"
                 );
+                outcome.succeeded();
             }
             Err(e) => match e {
                 sqlx::Error::Database(e) => {
-                    // 23505 is the code for unique constraints e.g. duplicate flow id issues
+                    // 23505 is the code for unique constraints e.g. duplicate flow id issues.
+                    // Every run reads the whole table, so rows already made into
+                    // subscriptions are skipped rather than failed.
                     if e.code() == Some(std::borrow::Cow::Borrowed("23505")) {
-                        error_and_incr!(
+                        info_and_incr!(
                             statsd,
                             LogKey::CheckSubscriptionsSubscriptionCreateDuplicateKeyViolation,
-                            error = e,
-                            "Duplicate key violation"
+                            error = e.to_string().as_str(),
+                            "Duplicate key violation. Subscription already exists, skipping."
                         );
+                        outcome.skipped();
+                        continue;
                     }
                     error_and_incr!(
                         statsd,
@@ -153,6 +218,7 @@ pub async fn fetch_and_process_new_subscriptions(
                         error = e,
                         "Database error while creating subscription. Continuing..."
                     );
+                    outcome.failed();
                     continue;
                 }
                 _ => {
@@ -162,9 +228,11 @@ pub async fn fetch_and_process_new_subscriptions(
                         error = e,
                         "Unexpected error while creating subscription. Continuing...",
                     );
+                    outcome.failed();
                     continue;
                 }
             },
         };
     }
+    Ok(outcome)
 }
@@ -1,19 +1,32 @@
 use sqlx::PgPool;
 
 use crate::{
-    error_and_incr, info_and_incr,
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
     models::aic::AICModel,
     telemetry::{LogKey, StatsD},
 };
 
-pub async fn archive_expired_aics(db_pool: &PgPool, statsd: &StatsD) {
+// With dry_run, logs the AICs that would be archived and writes nothing.
+pub async fn archive_expired_aics(
+    db_pool: &PgPool,
+    statsd: &StatsD,
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
     let aic_model = AICModel { db_pool };
-    // Intentional expect. Cannot continue without
-    let expired = aic_model
-        .fetch_expired()
-        .await
-        .expect("Could not get expired AICs");
+    let expired = aic_model.fetch_expired().await?;
     for aic in expired {
+        if dry_run {
+            info!(
+                LogKey::CleanupDryRun,
+                aic_id = &aic.id.to_string().as_str(),
+                "Dry run. Would archive aic"
+            );
+            outcome.succeeded();
+            continue;
+        }
         match aic_model.archive_aic(&aic).await {
             Ok(_) => {
                 info_and_incr!(
@@ -22,6 +35,7 @@ pub async fn archive_expired_aics(db_pool: &PgPool, statsd: &StatsD) {
                     aic_id = &aic.id.to_string().as_str(),
                     "Successfully archived aic"
                 );
+                outcome.succeeded();
             }
             Err(e) => {
                 error_and_incr!(
@@ -31,8 +45,10 @@ pub async fn archive_expired_aics(db_pool: &PgPool, statsd: &StatsD) {
                     aic_id = &aic.id.to_string().as_str(),
                     "Could not archive aic. Continuing..."
                 );
+                outcome.failed();
                 continue;
             }
         }
     }
+    Ok(outcome)
 }
@@ -0,0 +1,196 @@
+use std::time::Duration as StdDuration;
+
+use sqlx::PgPool;
+use time::{Date, OffsetDateTime};
+
+use crate::{
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
+    models::exchange_rates::{ExchangeRate, ExchangeRateModel},
+    settings::Settings,
+    telemetry::{LogKey, StatsD},
+};
+
+// A rates file has a header line, date,currency,rate, then a rate per line, e.g.
+// 2022-06-01,eur,1.0713 for one euro being worth 1.0713 of publisher_currency.
+// Returns each line's rate or why it couldn't be read.
+pub fn parse_rates(contents: &str, loaded: OffsetDateTime) -> Vec<Result<ExchangeRate, String>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("date,"))
+        .map(|line| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (date, currency, rate) = match fields[..] {
+                [date, currency, rate] => (date, currency, rate),
+                _ => return Err(format!("Expected date,currency,rate. Got {}", line)),
+            };
+            let date =
+                Date::parse(date, "%F").map_err(|e| format!("Invalid date {}. {}", date, e))?;
+            let rate: f64 = rate
+                .parse()
+                .map_err(|e| format!("Invalid rate {}. {}", rate, e))?;
+            if currency.is_empty() || !rate.is_finite() || rate <= 0.0 {
+                return Err(format!(
+                    "Expected a currency and a positive, finite rate. Got {}",
+                    line
+                ));
+            }
+            Ok(ExchangeRate {
+                date,
+                currency: currency.to_lowercase(),
+                rate,
+                loaded,
+            })
+        })
+        .collect()
+}
+
+// A path, or the url of a feed that serves a rates file
+async fn read_source(source: &str, timeout_seconds: u64) -> Result<String, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let client = reqwest::Client::builder()
+            .timeout(StdDuration::from_secs(timeout_seconds))
+            .build()
+            .map_err(|e| e.to_string())?;
+        let resp = client
+            .get(source)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| e.to_string())?;
+        resp.text().await.map_err(|e| e.to_string())
+    } else {
+        std::fs::read_to_string(source).map_err(|e| e.to_string())
+    }
+}
+
+// Loads settings.exchange_rates_source into the exchange_rates table, for
+// verify_reports to convert amounts to publisher_currency. A line that can't be
+// read fails and the rest are loaded. With dry_run, logs the rates it would load.
+pub async fn load_exchange_rates(
+    db_pool: &PgPool,
+    settings: &Settings,
+    statsd: &StatsD,
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
+    let rates = ExchangeRateModel { db_pool };
+    let source = match &settings.exchange_rates_source {
+        Some(source) => source,
+        None => {
+            info!(
+                LogKey::LoadExchangeRatesNoSource,
+                "No exchange_rates_source is set. Nothing to load."
+            );
+            return Ok(outcome);
+        }
+    };
+    let contents = read_source(source, settings.exchange_rates_timeout_seconds)
+        .await
+        .map_err(|e| {
+            CjmsError::Configuration(format!(
+                "Could not read exchange rates from {}. {}",
+                source, e
+            ))
+        })?;
+    for parsed in parse_rates(&contents, OffsetDateTime::now_utc()) {
+        let rate = match parsed {
+            Ok(rate) => rate,
+            Err(e) => {
+                error_and_incr!(
+                    statsd,
+                    LogKey::LoadExchangeRatesParseFailed,
+                    error = e,
+                    "Could not read exchange rate. Continuing..."
+                );
+                outcome.failed();
+                continue;
+            }
+        };
+        if dry_run {
+            info!(
+                LogKey::LoadExchangeRatesDryRun,
+                date = rate.date.to_string().as_str(),
+                currency = rate.currency.as_str(),
+                rate = rate.rate,
+                "Dry run. Would load exchange rate"
+            );
+            outcome.succeeded();
+            continue;
+        }
+        match rates.upsert(&rate).await {
+            Ok(_) => {
+                info_and_incr!(
+                    statsd,
+                    LogKey::LoadExchangeRatesSaved,
+                    date = rate.date.to_string().as_str(),
+                    currency = rate.currency.as_str(),
+                    "Saved exchange rate."
+                );
+                outcome.succeeded();
+            }
+            Err(e) => {
+                error_and_incr!(
+                    statsd,
+                    LogKey::LoadExchangeRatesSaveFailed,
+                    error = e,
+                    date = rate.date.to_string().as_str(),
+                    currency = rate.currency.as_str(),
+                    "Could not save exchange rate. Continuing..."
+                );
+                outcome.failed();
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::date;
+
+    use super::*;
+
+    #[test]
+    fn rates_are_parsed_line_by_line() {
+        let loaded = OffsetDateTime::now_utc();
+        let parsed = parse_rates(
+            "date,currency,rate
+2022-06-01,EUR,1.0713
+
+2022-06-01,chf,notarate
+2022-06-01,chf
+",
+            loaded,
+        );
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(
+            parsed[0],
+            Ok(ExchangeRate {
+                date: date!(2022 - 06 - 01),
+                currency: "eur".to_string(),
+                rate: 1.0713,
+                loaded,
+            })
+        );
+        assert!(parsed[1].is_err());
+        assert!(parsed[2].is_err());
+    }
+
+    #[test]
+    fn rates_must_be_positive_and_finite() {
+        let parsed = parse_rates(
+            "2022-06-01,eur,0
+2022-06-01,eur,-1.07
+2022-06-01,eur,NaN
+2022-06-01,eur,inf
+2022-06-01,eur,-infinity
+",
+            OffsetDateTime::now_utc(),
+        );
+        assert_eq!(parsed.len(), 5);
+        assert!(parsed.iter().all(Result::is_err));
+    }
+}
@@ -2,5 +2,8 @@ pub mod batch_refunds;
 pub mod check_refunds;
 pub mod check_subscriptions;
 pub mod cleanup;
+pub mod load_exchange_rates;
+pub mod outcome;
+pub mod reconcile;
 pub mod report_subscriptions;
 pub mod verify_reports;
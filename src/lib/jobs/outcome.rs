@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+use crate::telemetry::{LogKey, StatsD};
+
+// What happened to each record a job looked at. Skipped records needed no change or
+// will be retried on a later run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct JobOutcome {
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl JobOutcome {
+    pub fn succeeded(&mut self) {
+        self.succeeded += 1;
+    }
+
+    pub fn skipped(&mut self) {
+        self.skipped += 1;
+    }
+
+    pub fn failed(&mut self) {
+        self.failed += 1;
+    }
+
+    pub fn processed(&self) -> usize {
+        self.succeeded + self.skipped + self.failed
+    }
+
+    // Percentage of processed records that failed. 0 if nothing was processed.
+    pub fn failure_percent(&self) -> u64 {
+        match self.processed() {
+            0 => 0,
+            processed => (self.failed * 100 / processed) as u64,
+        }
+    }
+
+    // Emits <job>-outcome-* gauges. Called once per run.
+    pub fn report(&self, statsd: &StatsD, key: &LogKey) {
+        statsd.gauge(&key.add_suffix("outcome-processed"), self.processed());
+        statsd.gauge(&key.add_suffix("outcome-succeeded"), self.succeeded);
+        statsd.gauge(&key.add_suffix("outcome-skipped"), self.skipped);
+        statsd.gauge(&key.add_suffix("outcome-failed"), self.failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_percent_is_rounded_down_and_zero_when_empty() {
+        let mut outcome = JobOutcome::default();
+        assert_eq!(outcome.failure_percent(), 0);
+        outcome.succeeded();
+        outcome.skipped();
+        outcome.failed();
+        assert_eq!(outcome.processed(), 3);
+        assert_eq!(outcome.failure_percent(), 33);
+    }
+}
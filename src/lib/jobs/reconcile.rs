@@ -0,0 +1,360 @@
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use strum_macros::Display as EnumToString;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    error::CjmsError,
+    error_and_incr,
+    jobs::outcome::JobOutcome,
+    models::{
+        commissions::{Commission, CommissionModel},
+        refunds::{Refund, RefundModel},
+        status_history::{Status, UpdateStatus},
+        subscriptions::{Subscription, SubscriptionModel},
+    },
+    settings::Settings,
+    telemetry::{LogKey, StatsD},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumToString, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DiscrepancyKind {
+    // CJ has records for an order we have no subscription or refund for
+    Orphan,
+    // CJ has more than one original, or more than one correction, for an order
+    Duplicate,
+    // verify_reports found the record didn't match what we reported
+    Mismatch,
+    // CJ has a correction for a subscription with more than one refund, so it can't
+    // be told which refund it is for
+    Ambiguous,
+    // Reported, but not verified in time
+    Stale,
+}
+
+impl DiscrepancyKind {
+    fn log_key(&self) -> LogKey {
+        match self {
+            DiscrepancyKind::Orphan => LogKey::ReconcileOrphan,
+            DiscrepancyKind::Duplicate => LogKey::ReconcileDuplicate,
+            DiscrepancyKind::Mismatch => LogKey::ReconcileMismatch,
+            DiscrepancyKind::Ambiguous => LogKey::ReconcileAmbiguous,
+            DiscrepancyKind::Stale => LogKey::ReconcileStale,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Discrepancy {
+    pub kind: DiscrepancyKind,
+    // CJ's orderId, which is our subscription id
+    pub order_id: Option<String>,
+    pub commission_ids: Vec<String>,
+    pub refund_id: Option<String>,
+    pub cause: String,
+}
+
+impl Discrepancy {
+    fn for_commissions(kind: DiscrepancyKind, commissions: &[&Commission], cause: String) -> Self {
+        Discrepancy {
+            kind,
+            order_id: commissions.first().map(|c| c.order_id.clone()),
+            commission_ids: commissions
+                .iter()
+                .map(|c| c.commission_id.clone())
+                .collect(),
+            refund_id: None,
+            cause,
+        }
+    }
+}
+
+pub struct Reconciliation {
+    // Orders CJ has records for, plus our records waiting to be verified
+    pub checked: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+fn csv_field(value: &str) -> String {
+    match value.contains(&[',', '"', '\n'][..]) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+// One line per discrepancy. Commission ids are separated by spaces.
+pub fn discrepancies_to_csv(discrepancies: &[Discrepancy]) -> String {
+    let mut csv = String::from("kind,order_id,commission_ids,refund_id,cause\n");
+    for d in discrepancies {
+        let fields = [
+            d.kind.to_string(),
+            d.order_id.clone().unwrap_or_default(),
+            d.commission_ids.join(" "),
+            d.refund_id.clone().unwrap_or_default(),
+            d.cause.clone(),
+        ];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&line.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// The mismatch_reasons verify_reports kept with the commission, if it found any. A
+// commission it never checked isn't reported.
+fn mismatch(commission: &Commission, refund_id: Option<&str>) -> Option<Discrepancy> {
+    let reasons = commission.mismatch_reasons.as_ref()?;
+    if reasons.as_array().is_none_or(|r| r.is_empty()) {
+        return None;
+    }
+    let mut d = Discrepancy::for_commissions(
+        DiscrepancyKind::Mismatch,
+        &[commission],
+        format!("verify_reports found: {}", reasons),
+    );
+    d.refund_id = refund_id.map(String::from);
+    Some(d)
+}
+
+// The records CJ has for one order, with the order's subscription if we have one and
+// that subscription's refunds
+fn check_order(
+    commissions: &[&Commission],
+    sub: Option<&Subscription>,
+    sub_refunds: &[&Refund],
+) -> Vec<Discrepancy> {
+    if sub.is_none() {
+        return vec![Discrepancy::for_commissions(
+            DiscrepancyKind::Orphan,
+            commissions,
+            "No subscription has this order id.".to_string(),
+        )];
+    }
+    let (originals, corrections): (Vec<&Commission>, Vec<&Commission>) =
+        commissions.iter().copied().partition(|c| c.original);
+    let mut discrepancies = vec![];
+    match originals.len() {
+        0 => {}
+        1 => discrepancies.extend(mismatch(originals[0], None)),
+        n => discrepancies.push(Discrepancy::for_commissions(
+            DiscrepancyKind::Duplicate,
+            &originals,
+            format!("CJ has {} original records for the subscription.", n),
+        )),
+    }
+    if corrections.is_empty() {
+        return discrepancies;
+    }
+    match (corrections.len(), sub_refunds.len()) {
+        (_, 0) => discrepancies.push(Discrepancy::for_commissions(
+            DiscrepancyKind::Orphan,
+            &corrections,
+            "CJ has corrections for a subscription with no refund.".to_string(),
+        )),
+        (1, 1) => discrepancies.extend(mismatch(corrections[0], Some(&sub_refunds[0].refund_id))),
+        (1, n) => discrepancies.push(Discrepancy::for_commissions(
+            DiscrepancyKind::Ambiguous,
+            &corrections,
+            format!(
+                "CJ has 1 correction for the subscription's {} refunds ({}), so it can't be matched to one.",
+                n,
+                sub_refunds
+                    .iter()
+                    .map(|r| r.refund_id.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ),
+        )),
+        (n, _) => discrepancies.push(Discrepancy::for_commissions(
+            DiscrepancyKind::Duplicate,
+            &corrections,
+            format!("CJ has {} corrections for the subscription.", n),
+        )),
+    }
+    discrepancies
+}
+
+fn is_stale(status_t: Option<OffsetDateTime>, now: OffsetDateTime, window_hours: u64) -> bool {
+    status_t.is_some_and(|t| now - t > Duration::hours(window_hours as i64))
+}
+
+// Reports what verify_reports found about the CJ records it kept for the orders CJ
+// posted a commission for in [since, before), and CJ records that don't fit our
+// subscriptions and refunds. verify_reports stops looking for a record
+// settings.verify_window_hours after it was reported, so a record still Reported after
+// that hasn't been verified.
+pub async fn find_discrepancies(
+    db_pool: &PgPool,
+    settings: &Settings,
+    since: &OffsetDateTime,
+    before: &OffsetDateTime,
+) -> Result<Reconciliation, sqlx::Error> {
+    let window_hours = settings.verify_window_hours;
+    let commissions = CommissionModel { db_pool };
+    let subscriptions = SubscriptionModel { db_pool };
+    let refunds = RefundModel { db_pool };
+    let mut discrepancies = vec![];
+
+    // fetch_all_for_orders_posted_between returns records grouped by order
+    let order_commissions = commissions
+        .fetch_all_for_orders_posted_between(since, before)
+        .await?;
+    let mut orders: Vec<Vec<&Commission>> = vec![];
+    for commission in &order_commissions {
+        match orders.last_mut() {
+            Some(order) if order[0].order_id == commission.order_id => order.push(commission),
+            _ => orders.push(vec![commission]),
+        }
+    }
+    // Order ids that aren't a uuid can't be ours
+    let order_ids: Vec<Uuid> = orders
+        .iter()
+        .filter_map(|order| Uuid::parse_str(&order[0].order_id).ok())
+        .collect();
+    let order_subs = subscriptions.fetch_all_by_ids(&order_ids).await?;
+    let subscription_ids: Vec<String> = order_subs
+        .iter()
+        .map(|sub| sub.subscription_id.clone())
+        .collect();
+    let order_refunds = refunds
+        .fetch_all_by_subscription_ids(&subscription_ids)
+        .await?;
+    let subs_by_order_id: HashMap<String, &Subscription> = order_subs
+        .iter()
+        .map(|sub| (sub.id.to_string(), sub))
+        .collect();
+    let mut refunds_by_subscription_id: HashMap<&str, Vec<&Refund>> = HashMap::new();
+    for refund in &order_refunds {
+        refunds_by_subscription_id
+            .entry(refund.subscription_id.as_str())
+            .or_default()
+            .push(refund);
+    }
+    let mut checked = orders.len();
+    for order in &orders {
+        let sub = subs_by_order_id.get(&order[0].order_id).copied();
+        let sub_refunds = sub
+            .and_then(|sub| refunds_by_subscription_id.get(sub.subscription_id.as_str()))
+            .map_or(&[][..], Vec::as_slice);
+        discrepancies.extend(check_order(order, sub, sub_refunds));
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let reported_subscriptions = subscriptions.fetch_all_by_status(Status::Reported).await?;
+    checked += reported_subscriptions.len();
+    for sub in reported_subscriptions {
+        if is_stale(sub.get_status_t(), now, window_hours) {
+            discrepancies.push(Discrepancy {
+                kind: DiscrepancyKind::Stale,
+                order_id: Some(sub.id.to_string()),
+                commission_ids: vec![],
+                refund_id: None,
+                cause: format!(
+                    "Subscription reported more than {} hours ago and not verified.",
+                    window_hours
+                ),
+            });
+        }
+    }
+    let reported_refunds = refunds.fetch_all_by_status(Status::Reported).await?;
+    checked += reported_refunds.len();
+    let stale_refunds: Vec<Refund> = reported_refunds
+        .into_iter()
+        .filter(|refund| is_stale(refund.get_status_t(), now, window_hours))
+        .collect();
+    let stale_refund_subscription_ids: Vec<String> = stale_refunds
+        .iter()
+        .map(|refund| refund.subscription_id.clone())
+        .collect();
+    let order_ids_by_subscription_id: HashMap<String, String> = subscriptions
+        .fetch_all_by_subscription_ids(&stale_refund_subscription_ids)
+        .await?
+        .into_iter()
+        .map(|sub| (sub.subscription_id, sub.id.to_string()))
+        .collect();
+    for refund in stale_refunds {
+        discrepancies.push(Discrepancy {
+            kind: DiscrepancyKind::Stale,
+            order_id: order_ids_by_subscription_id
+                .get(&refund.subscription_id)
+                .cloned(),
+            commission_ids: vec![],
+            refund_id: Some(refund.refund_id.clone()),
+            cause: format!(
+                "Refund reported more than {} hours ago and not verified.",
+                window_hours
+            ),
+        });
+    }
+    Ok(Reconciliation {
+        checked,
+        discrepancies,
+    })
+}
+
+// Checks the orders CJ posted a commission for in the last
+// settings.reconcile_window_days. Logs and counts each discrepancy as a failure. The job
+// only reads, so a dry run is the same as a run.
+pub async fn reconcile(
+    db_pool: &PgPool,
+    settings: &Settings,
+    statsd: &StatsD,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
+    let before = OffsetDateTime::now_utc();
+    let since = before - Duration::days(i64::from(settings.reconcile_window_days));
+    let reconciliation = find_discrepancies(db_pool, settings, &since, &before).await?;
+    for d in &reconciliation.discrepancies {
+        let key = d.kind.log_key();
+        error_and_incr!(
+            statsd,
+            key,
+            order_id = d.order_id.as_deref().unwrap_or_default(),
+            commission_ids = d.commission_ids.join(" ").as_str(),
+            refund_id = d.refund_id.as_deref().unwrap_or_default(),
+            cause = d.cause.as_str(),
+            "Discrepancy with CJ found."
+        );
+        outcome.failed();
+    }
+    outcome.succeeded = reconciliation
+        .checked
+        .saturating_sub(reconciliation.discrepancies.len());
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discrepancies_are_written_as_csv() {
+        let discrepancies = vec![
+            Discrepancy {
+                kind: DiscrepancyKind::Duplicate,
+                order_id: Some("order".to_string()),
+                commission_ids: vec!["1".to_string(), "2".to_string()],
+                refund_id: None,
+                cause: "CJ has 2 original records, \"really\".".to_string(),
+            },
+            Discrepancy {
+                kind: DiscrepancyKind::Stale,
+                order_id: None,
+                commission_ids: vec![],
+                refund_id: Some("re_1".to_string()),
+                cause: "Not verified.".to_string(),
+            },
+        ];
+        assert_eq!(
+            discrepancies_to_csv(&discrepancies),
+            "kind,order_id,commission_ids,refund_id,cause
+duplicate,order,1 2,,\"CJ has 2 original records, \"\"really\"\".\"
+stale,,,re_1,Not verified.
+"
+        );
+    }
+}
@@ -1,31 +1,73 @@
 use sqlx::{Pool, Postgres};
 
 use crate::{
-    cj::client::CJClient,
-    error_and_incr, info_and_incr,
+    coupons::format_coupons,
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
     models::{status_history::Status, subscriptions::SubscriptionModel},
+    network::AffiliateNetwork,
+    settings::Settings,
     telemetry::{LogKey, StatsD},
 };
 
-pub async fn report_subscriptions_to_cj(
+// Subscriptions to plans settings.plans has as not eligible, or that used a coupon
+// settings.coupon_rules excludes, are marked WillNotReport. The reason is kept on the
+// status history entry.
+// With dry_run, logs the subscriptions it would report to the network or mark
+// WillNotReport. Nothing is sent to the network and nothing is written.
+pub async fn report_subscriptions<N: AffiliateNetwork>(
     db_pool: &Pool<Postgres>,
-    cj_client: &CJClient,
+    network: &N,
+    settings: &Settings,
     statsd: &StatsD,
-) {
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
     let subscriptions = SubscriptionModel { db_pool };
-    // Intentional panic. Cannot continue if we can't retrieve subs.
     let not_reported_subscriptions = subscriptions
         .fetch_all_by_status(Status::NotReported)
-        .await
-        .expect("Could not retrieve subscriptions from DB.");
+        .await?;
     statsd.gauge(
         &LogKey::ReportSubscriptionsNNotReported,
         not_reported_subscriptions.len(),
     );
 
     for sub in not_reported_subscriptions {
-        let next_status = match sub.aic_expires {
-            Some(aic_expires) => {
+        let (next_status, reason) = match (
+            settings.plans.ineligible_reason(&sub.plan_id),
+            settings.coupon_rules.excluded_reason(&sub.coupons),
+            sub.aic_expires,
+        ) {
+            (Some(reason), _, _) => {
+                info_and_incr!(
+                    statsd,
+                    LogKey::ReportSubscriptionsPlanNotEligible,
+                    sub_id = &sub.id.to_string().as_str(),
+                    plan_id = sub.plan_id.as_str(),
+                    reason = reason.as_str(),
+                    "Plan is not eligible for commission. Will not report."
+                );
+                (
+                    Status::WillNotReport,
+                    Some(format!("Plan not eligible: {}", reason)),
+                )
+            }
+            (None, Some(reason), _) => {
+                info_and_incr!(
+                    statsd,
+                    LogKey::ReportSubscriptionsCouponExcluded,
+                    sub_id = &sub.id.to_string().as_str(),
+                    coupons = format_coupons(&sub.coupons).as_str(),
+                    reason = reason.as_str(),
+                    "Coupon is excluded from reporting. Will not report."
+                );
+                (
+                    Status::WillNotReport,
+                    Some(format!("Coupon excluded: {}", reason)),
+                )
+            }
+            (None, None, Some(aic_expires)) => {
                 if aic_expires < sub.subscription_created {
                     info_and_incr!(
                         statsd,
@@ -33,24 +75,62 @@ pub async fn report_subscriptions_to_cj(
                         sub_id = &sub.id.to_string().as_str(),
                         "AIC expired before subscription created. Will not report."
                     );
-                    Status::WillNotReport
+                    (
+                        Status::WillNotReport,
+                        Some("AIC expired before subscription created".to_string()),
+                    )
                 } else {
-                    Status::Reported
+                    (Status::Reported, None)
                 }
             }
-            None => {
+            (None, None, None) => {
                 error_and_incr!(
                     statsd,
                     LogKey::ReportSubscriptionsSubscriptionHasNoAicExpiry,
                     sub_id = &sub.id.to_string().as_str(),
                     "Subscription does not have an AIC expiry. Will not report."
                 );
-                Status::WillNotReport
+                (Status::WillNotReport, Some("No AIC expiry".to_string()))
             }
         };
+        let conflicting_codes = settings.coupon_rules.conflicting_codes(&sub.coupons);
+        if next_status == Status::Reported && !conflicting_codes.is_empty() {
+            info_and_incr!(
+                statsd,
+                LogKey::ReportSubscriptionsCouponRulesConflict,
+                sub_id = &sub.id.to_string().as_str(),
+                coupons = format_coupons(&sub.coupons).as_str(),
+                ignored = conflicting_codes.join(",").as_str(),
+                "More than one coupon rule sets an amount or a cj_type. Using the first code's."
+            );
+        }
+        if dry_run {
+            info!(
+                LogKey::ReportSubscriptionsDryRun,
+                sub_id = &sub.id.to_string().as_str(),
+                status = &next_status.to_string().as_str(),
+                reason = reason.as_deref().unwrap_or_default(),
+                report_to_cj = next_status == Status::Reported,
+                plan_id = sub.plan_id.as_str(),
+                cj_sku = settings.plans.cj_sku(&sub.plan_id),
+                plan_amount = sub.plan_amount,
+                reported_total = settings.coupon_rules.reported_total(&sub),
+                n_items = sub.items().len(),
+                coupons = format_coupons(&sub.coupons).as_str(),
+                plan_currency = sub.plan_currency.as_str(),
+                request = match next_status {
+                    Status::Reported => network.describe_conversion(&sub),
+                    _ => String::new(),
+                }
+                .as_str(),
+                "Dry run. Would update subscription status"
+            );
+            outcome.succeeded();
+            continue;
+        }
         if next_status == Status::WillNotReport {
             match subscriptions
-                .update_sub_status(&sub.id, Status::WillNotReport)
+                .update_sub_status_with_reason(&sub.id, Status::WillNotReport, reason)
                 .await
             {
                 Ok(_) => {
@@ -60,6 +140,7 @@ pub async fn report_subscriptions_to_cj(
                         sub_id = &sub.id.to_string().as_str(),
                         "Successfully marked as WillNotReport"
                     );
+                    outcome.succeeded();
                 }
                 Err(e) => {
                     error_and_incr!(
@@ -69,46 +150,39 @@ pub async fn report_subscriptions_to_cj(
                         sub_id = &sub.id.to_string().as_str(),
                         "Could not mark subscription as WillNotReport."
                     );
+                    outcome.failed();
                 }
             };
             continue;
         }
 
-        let mark_not_reported = match cj_client.report_subscription(&sub).await {
-            Ok(r) => {
-                if r.status() == 200 {
-                    match subscriptions
-                        .update_sub_status(&sub.id, Status::Reported)
-                        .await
-                    {
-                        Ok(_) => {
-                            info_and_incr!(
-                                statsd,
-                                LogKey::ReportSubscriptionReportToCj,
-                                sub_id = &sub.id.to_string().as_str(),
-                                "Successfully reported sub to CJ; received 200 status"
-                            );
-                        }
-                        Err(e) => {
-                            error_and_incr!(
-                                statsd,
-                                LogKey::ReportSubscriptionReportToCjButCouldNotMarkReported,
-                                error = e,
-                                sub_id = &sub.id.to_string().as_str(),
-                                "Successfully reported sub to CJ; received 200 status, but could not mark the sub as reported locally."
-                            );
-                        }
-                    };
-                    false
-                } else {
-                    error_and_incr!(
-                        statsd,
-                        LogKey::ReportSubscriptionReportToCjFailed,
-                        sub_id = &sub.id.to_string().as_str(),
-                        "Could not report sub to CJ; received non-200 status."
-                    );
-                    true
-                }
+        let mark_not_reported = match network.report_conversion(&sub).await {
+            Ok(_) => {
+                match subscriptions
+                    .update_sub_status(&sub.id, Status::Reported)
+                    .await
+                {
+                    Ok(_) => {
+                        info_and_incr!(
+                            statsd,
+                            LogKey::ReportSubscriptionReportToCj,
+                            sub_id = &sub.id.to_string().as_str(),
+                            "Successfully reported sub to CJ; received 200 status"
+                        );
+                        outcome.succeeded();
+                    }
+                    Err(e) => {
+                        error_and_incr!(
+                            statsd,
+                            LogKey::ReportSubscriptionReportToCjButCouldNotMarkReported,
+                            error = e,
+                            sub_id = &sub.id.to_string().as_str(),
+                            "Successfully reported sub to CJ; received 200 status, but could not mark the sub as reported locally."
+                        );
+                        outcome.failed();
+                    }
+                };
+                false
             }
             Err(e) => {
                 error_and_incr!(
@@ -116,12 +190,14 @@ pub async fn report_subscriptions_to_cj(
                     LogKey::ReportSubscriptionReportToCjFailed,
                     error = e,
                     sub_id = &sub.id.to_string().as_str(),
-                    "Could not report sub to CJ; unknown application failure."
+                    "Could not report sub to CJ."
                 );
                 true
             }
         };
         if mark_not_reported {
+            // Reporting failed, whether or not the sub could be put back
+            outcome.failed();
             match subscriptions
                 .update_sub_status(&sub.id, Status::NotReported)
                 .await
@@ -146,4 +222,5 @@ pub async fn report_subscriptions_to_cj(
             }
         }
     }
+    Ok(outcome)
 }
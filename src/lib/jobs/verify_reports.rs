@@ -1,72 +1,125 @@
+use serde_json::json;
 use sqlx::{Pool, Postgres};
-use time::OffsetDateTime;
+use std::collections::HashMap;
+use time::{Duration, OffsetDateTime};
 
 use crate::{
-    cj::client::{convert_amount_to_decimal, CJClient, CommissionDetailRecord},
-    error_and_incr, info_and_incr,
+    cj::matching::{find_mismatches, AmountCheck, ExpectedSale, MismatchReason},
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::outcome::JobOutcome,
     models::{
+        commissions::{Commission, CommissionModel},
+        exchange_rates::ExchangeRateModel,
         refunds::RefundModel,
         status_history::{Status, UpdateStatus},
         subscriptions::SubscriptionModel,
     },
+    network::{AffiliateNetwork, NetworkCommission},
+    settings::Settings,
     telemetry::{LogKey, StatsD},
 };
 
-pub async fn verify_reports_with_cj(
+// How to compare an amount in currency with the network's, converted at the rate on its
+// posting date if it's not the publisher currency. None if there's no rate, or it's
+// older than settings.max_exchange_rate_age_days.
+async fn get_amount_check(
+    rates: &ExchangeRateModel<'_>,
+    settings: &Settings,
+    statsd: &StatsD,
+    currency: &str,
+    record: &NetworkCommission,
+) -> Result<Option<AmountCheck>, sqlx::Error> {
+    let tolerance = i64::from(settings.verify_amount_tolerance_cents);
+    if currency.eq_ignore_ascii_case(&record.sale_amount.currency) {
+        return Ok(Some(AmountCheck::same_currency(tolerance)));
+    }
+    let date = record.posting_date.date();
+    let max_age = Duration::days(i64::from(settings.max_exchange_rate_age_days));
+    match rates.fetch_one_on_date(currency, &date).await? {
+        Some(rate) if date - rate.date > max_age => {
+            info!(
+                LogKey::VerifyReportsExchangeRateStale,
+                currency = currency,
+                date = date.to_string().as_str(),
+                rate_date = rate.date.to_string().as_str(),
+                commission_id = record.id.as_str(),
+                "Latest exchange rate is too old for the posting date. Amount not checked."
+            );
+            statsd.incr(&LogKey::VerifyReportsExchangeRateStale);
+            Ok(None)
+        }
+        Some(rate) => Ok(Some(AmountCheck {
+            rate: rate.rate,
+            tolerance,
+            tolerance_percent: settings.verify_exchange_rate_tolerance_percent,
+        })),
+        None => {
+            info!(
+                LogKey::VerifyReportsExchangeRateMissing,
+                currency = currency,
+                date = date.to_string().as_str(),
+                commission_id = record.id.as_str(),
+                "No exchange rate for the currency on the posting date. Amount not checked."
+            );
+            statsd.incr(&LogKey::VerifyReportsExchangeRateMissing);
+            Ok(None)
+        }
+    }
+}
+
+// Records are looked for in CJ for settings.verify_window_hours after they're reported.
+// A subscription still not found after that is re-reported, with the same order id so
+// CJ deduplicates it, up to settings.max_re_reports times before it's left
+// CJNotReceived. Records found are checked with cj::matching, converting amounts in
+// other currencies with the exchange_rates table, and why one didn't match is kept with
+// its commission. Each of the network's accounts, e.g. CJ programs, is queried for its
+// own subscriptions and refunds. With dry_run, queries the network as usual but only
+// logs the status each subscription and refund would move to.
+pub async fn verify_reports<N: AffiliateNetwork>(
     db_pool: &Pool<Postgres>,
-    cj_client: &CJClient,
+    network: &N,
+    settings: &Settings,
     statsd: &StatsD,
-) {
+    dry_run: bool,
+) -> Result<JobOutcome, CjmsError> {
+    let mut outcome = JobOutcome::default();
+    let window_hours = settings.verify_window_hours;
+    let window = Duration::hours(window_hours as i64);
+    // By commission id, kept with the commissions at the end
+    let mut mismatch_reasons: HashMap<String, Vec<MismatchReason>> = HashMap::new();
     let subscriptions = SubscriptionModel { db_pool };
     let refunds = RefundModel { db_pool };
+    let commissions = CommissionModel { db_pool };
+    let rates = ExchangeRateModel { db_pool };
 
     // Get the list of subscriptions and the list of refunds we're looking for
-    let reported_subscriptions = subscriptions
-        .fetch_all_by_status(Status::Reported)
-        .await
-        .expect("Could not retrieve subscriptions from DB.");
-    let reported_refunds = refunds
-        .fetch_all_by_status(Status::Reported)
-        .await
-        .expect("Could not retrieve refunds from DB.");
+    let reported_subscriptions = subscriptions.fetch_all_by_status(Status::Reported).await?;
+    let reported_refunds = refunds.fetch_all_by_status(Status::Reported).await?;
 
-    // Get the date range with which to query cj
+    // Get the date range with which to query the network
     let mut min_sub = None;
     let mut max_sub = None;
     let mut min_refund = None;
     let mut max_refund = None;
 
     if !reported_subscriptions.is_empty() {
-        let subscription_date_range = subscriptions
-            .get_reported_date_range()
-            .await
-            .expect("Could not retrieve date range.");
-        min_sub = Some(
-            subscription_date_range
-                .min
-                .expect("No minimum date was returned."),
-        );
-        max_sub = Some(
-            subscription_date_range
-                .max
-                .expect("No maximum date was returned."),
-        );
+        let subscription_date_range = subscriptions.get_reported_date_range().await?;
+        min_sub = Some(subscription_date_range.min.ok_or_else(|| {
+            CjmsError::DataIntegrity("No minimum subscription date was returned.".to_string())
+        })?);
+        max_sub = Some(subscription_date_range.max.ok_or_else(|| {
+            CjmsError::DataIntegrity("No maximum subscription date was returned.".to_string())
+        })?);
     }
     if !reported_refunds.is_empty() {
-        let refund_date_range = refunds
-            .get_reported_date_range()
-            .await
-            .expect("Could not retrieve date range.");
-        min_refund = Some(
-            refund_date_range
-                .min
-                .expect("No minimum date was returned."),
-        );
-        max_refund = Some(
-            refund_date_range
-                .max
-                .expect("No maximum date was returned."),
-        );
+        let refund_date_range = refunds.get_reported_date_range().await?;
+        min_refund = Some(refund_date_range.min.ok_or_else(|| {
+            CjmsError::DataIntegrity("No minimum refund date was returned.".to_string())
+        })?);
+        max_refund = Some(refund_date_range.max.ok_or_else(|| {
+            CjmsError::DataIntegrity("No maximum refund date was returned.".to_string())
+        })?);
     }
     let mins: Vec<OffsetDateTime> = [min_sub, min_refund].iter().cloned().flatten().collect();
     let maxs: Vec<OffsetDateTime> = [max_sub, max_refund].iter().cloned().flatten().collect();
@@ -80,7 +133,7 @@ pub async fn verify_reports_with_cj(
                 n_refunds = reported_refunds.len(),
                 "No maximum date. So nothing to check. Aborting..."
             );
-            return;
+            return Ok(outcome);
         }
     };
     let max = match maxs.iter().cloned().max() {
@@ -93,49 +146,95 @@ pub async fn verify_reports_with_cj(
                 n_refunds = reported_refunds.len(),
                 "No maximum date. So nothing to check. Aborting..."
             );
-            return;
+            return Ok(outcome);
         }
     };
 
-    // Query CJ
-    let cj_query_result = cj_client
-        .query_commission_detail_api_between_dates(min, max)
-        .await;
-    statsd.gauge(&LogKey::VerifyReportsCount, cj_query_result.count);
+    // Query each account's records. If an account can't be queried, its subscriptions
+    // and refunds stay Reported to be checked on the next run.
+    let mut records_by_account: HashMap<&str, Vec<NetworkCommission>> = HashMap::new();
+    for account in network.accounts() {
+        match network.query_commissions(account, min, max).await {
+            Ok(records) => {
+                records_by_account.insert(account, records);
+            }
+            Err(e) => {
+                error_and_incr!(
+                    statsd,
+                    LogKey::VerifyReportsQueryFailed,
+                    error = e,
+                    account = account,
+                    "Could not query the account. Will check its records again next run."
+                );
+            }
+        }
+    }
+    statsd.gauge(
+        &LogKey::VerifyReportsCount,
+        records_by_account.values().map(Vec::len).sum(),
+    );
 
     // Iterate through the subscriptions updating as we go
     for sub in reported_subscriptions {
         let sub_id = sub.id.to_string();
+        let account_records = match records_by_account.get(network.account_for_plan(&sub.plan_id)) {
+            Some(records) => records,
+            None => {
+                outcome.skipped();
+                continue;
+            }
+        };
         // A subscription record (as opposed to a refund) has "original: true"
         // We pull out the matching order id and original: true
-        let sub_record: Vec<CommissionDetailRecord> = cj_query_result
-            .records
+        let sub_record: Vec<NetworkCommission> = account_records
             .iter()
+            .filter(|&r| (r.order_id == sub_id) && r.original)
             .cloned()
-            .filter(|r| (r.order_id == sub_id) && r.original)
             .collect();
+        let mut re_report = false;
         let next_status = match sub_record.len() {
             0 => {
                 let time_since_subscription_reported =
                     // It's ok to use unwrap, because the select does not return null status_t
                     OffsetDateTime::now_utc() - sub.get_status_t().unwrap();
-                match time_since_subscription_reported.whole_hours() > 36 {
+                match time_since_subscription_reported > window {
                     true => {
-                        error_and_incr!(
-                            statsd,
-                            LogKey::VerifyReportsSubscriptionNotFound,
-                            subscription_id = sub_id.as_str(),
-                            "No susbscription match found 36 hours after report."
-                        );
-                        Status::CJNotReceived
+                        let n_re_reports = sub.re_report_count;
+                        match i64::from(n_re_reports) < i64::from(settings.max_re_reports) {
+                            true => {
+                                info_and_incr!(
+                                    statsd,
+                                    LogKey::VerifyReportsSubscriptionReReport,
+                                    subscription_id = sub_id.as_str(),
+                                    n_re_reports = n_re_reports,
+                                    window_hours = window_hours,
+                                    "No subscription match found in the window. Will report again."
+                                );
+                                re_report = true;
+                                Status::NotReported
+                            }
+                            false => {
+                                error_and_incr!(
+                                    statsd,
+                                    LogKey::VerifyReportsSubscriptionNotFound,
+                                    subscription_id = sub_id.as_str(),
+                                    n_re_reports = n_re_reports,
+                                    window_hours = window_hours,
+                                    "No subscription match found in the window."
+                                );
+                                Status::CJNotReceived
+                            }
+                        }
                     }
                     false => {
                         info_and_incr!(
                             statsd,
                             LogKey::VerifyReportsSubscriptionNotFound,
                             subscription_id = sub_id.as_str(),
-                            "No susbscription match found. Continue trying for up to 36 hours. Continuing..."
+                            window_hours = window_hours,
+                            "No subscription match found. Will keep trying for the window. Continuing..."
                         );
+                        outcome.skipped();
                         continue;
                     }
                 }
@@ -149,19 +248,32 @@ pub async fn verify_reports_with_cj(
                 );
                 // Verify the details are correct.
                 let record = &sub_record[0];
-                let plan_id_correct = record.items[0].sku == sub.plan_id;
-                let amount_correct = match sub.plan_currency.to_lowercase().as_str() {
-                    "usd" => {
-                        record.sale_amount_pub_currency
-                            == convert_amount_to_decimal(sub.plan_amount)
-                    }
-                    _ => {
-                        // We do not check in non-USD cases because CJ sends us back an amount that
-                        // they've converted from our amount to USD at an unknown exchange rate.
-                        true
-                    }
-                };
-                let correct = plan_id_correct && amount_correct;
+                let amount_check =
+                    match get_amount_check(&rates, settings, statsd, &sub.plan_currency, record)
+                        .await
+                    {
+                        Ok(amount_check) => amount_check,
+                        Err(e) => {
+                            error_and_incr!(
+                                statsd,
+                                LogKey::VerifyReportsExchangeRateFailed,
+                                error = e,
+                                subscription_id = sub_id.as_str(),
+                                commission_id = record.id.as_str(),
+                                "Could not get the exchange rate. Continuing..."
+                            );
+                            outcome.failed();
+                            continue;
+                        }
+                    };
+                let mismatches = find_mismatches(
+                    record,
+                    &ExpectedSale::for_subscription(&sub, &settings.plans, &settings.coupon_rules),
+                    amount_check.as_ref(),
+                );
+                let correct = mismatches.is_empty();
+                let mismatches_json = json!(mismatches).to_string();
+                mismatch_reasons.insert(record.id.clone(), mismatches);
                 match correct {
                     true => {
                         info_and_incr!(
@@ -177,6 +289,7 @@ pub async fn verify_reports_with_cj(
                             statsd,
                             LogKey::VerifyReportsSubscriptionNotMatched,
                             subscription_id = sub_id.as_str(),
+                            mismatch_reasons = mismatches_json.as_str(),
                             "Subscription found but not matched."
                         );
                         Status::CJNotReceived
@@ -190,13 +303,29 @@ pub async fn verify_reports_with_cj(
                     subscription_id = sub_id.as_str(),
                     "Too many records were found for the subscription. Continuing..."
                 );
+                outcome.failed();
                 continue;
             }
         };
-        match subscriptions
-            .update_sub_status(&sub.id, next_status.clone())
-            .await
-        {
+        if dry_run {
+            info!(
+                LogKey::VerifyReportsDryRun,
+                sub_id = &sub.id.to_string().as_str(),
+                status = &next_status.to_string().as_str(),
+                "Dry run. Would update subscription status"
+            );
+            outcome.succeeded();
+            continue;
+        }
+        let updated = match re_report {
+            true => subscriptions.re_report_sub(&sub.id).await,
+            false => {
+                subscriptions
+                    .update_sub_status(&sub.id, next_status.clone())
+                    .await
+            }
+        };
+        match updated {
             Ok(_) => {
                 info_and_incr!(
                     statsd,
@@ -205,6 +334,7 @@ pub async fn verify_reports_with_cj(
                     status = &next_status.to_string().as_str(),
                     "Successfully updated subscription with new status."
                 );
+                outcome.succeeded();
             }
             Err(e) => {
                 error_and_incr!(
@@ -215,6 +345,7 @@ pub async fn verify_reports_with_cj(
                     sub_id = &sub.id.to_string().as_str(),
                     "Subscription update with new status failed."
                 );
+                outcome.failed();
             }
         };
     }
@@ -234,30 +365,39 @@ pub async fn verify_reports_with_cj(
                     stripe_subscription_id = refund.subscription_id.as_str(),
                     "Could not find subscription that pairs with refund. Continuing..."
                 );
+                outcome.failed();
                 continue;
             }
         };
         let related_sub_id = related_sub.id.to_string();
+        let account_records =
+            match records_by_account.get(network.account_for_plan(&related_sub.plan_id)) {
+                Some(records) => records,
+                None => {
+                    outcome.skipped();
+                    continue;
+                }
+            };
         // A refund record (as opposed to a subscription) has "original: false"
         // We pull out the matching order id and original: false
-        let refund_record: Vec<CommissionDetailRecord> = cj_query_result
-            .records
+        let refund_record: Vec<NetworkCommission> = account_records
             .iter()
+            .filter(|&r| (r.order_id == related_sub_id) && !r.original)
             .cloned()
-            .filter(|r| (r.order_id == related_sub_id) && !r.original)
             .collect();
         let next_status = match refund_record.len() {
             0 => {
                 let time_since_refund_reported =
                     // It's ok to use unwrap, because the select does not return null status_t
                     OffsetDateTime::now_utc() - refund.get_status_t().unwrap();
-                match time_since_refund_reported.whole_hours() > 36 {
+                match time_since_refund_reported > window {
                     true => {
                         error_and_incr!(
                             statsd,
                             LogKey::VerifyReportsRefundNotFound,
                             refund_id = refund.id.to_string().as_str(),
-                            "No refund match found 36 hours after report."
+                            window_hours = window_hours,
+                            "No refund match found in the window."
                         );
                         Status::CJNotReceived
                     }
@@ -266,8 +406,10 @@ pub async fn verify_reports_with_cj(
                             statsd,
                             LogKey::VerifyReportsRefundNotFound,
                             refund_id = refund.id.to_string().as_str(),
-                            "No refund match found. Continue trying for up to 36 hours. Continuing..."
+                            window_hours = window_hours,
+                            "No refund match found. Will keep trying for the window. Continuing..."
                         );
+                        outcome.skipped();
                         continue;
                     }
                 }
@@ -281,21 +423,42 @@ pub async fn verify_reports_with_cj(
                 );
                 // Verify the details are correct.
                 let record = &refund_record[0];
-                let reason_correct =
-                    record.correction_reason == Some(String::from("RETURNED_MERCHANDISE"));
-                let plan_id_correct = record.items[0].sku == related_sub.plan_id;
-                let amount_correct = match related_sub.plan_currency.to_lowercase().as_str() {
-                    "usd" => {
-                        record.sale_amount_pub_currency
-                            == convert_amount_to_decimal(-refund.refund_amount)
-                    }
-                    _ => {
-                        // We do not check in non-USD cases because CJ sends us back an amount that
-                        // they've converted from our amount to USD at an unknown exchange rate.
-                        true
+                let amount_check = match get_amount_check(
+                    &rates,
+                    settings,
+                    statsd,
+                    &related_sub.plan_currency,
+                    record,
+                )
+                .await
+                {
+                    Ok(amount_check) => amount_check,
+                    Err(e) => {
+                        error_and_incr!(
+                            statsd,
+                            LogKey::VerifyReportsExchangeRateFailed,
+                            error = e,
+                            refund_id = refund.id.to_string().as_str(),
+                            commission_id = record.id.as_str(),
+                            "Could not get the exchange rate. Continuing..."
+                        );
+                        outcome.failed();
+                        continue;
                     }
                 };
-                let correct = reason_correct && plan_id_correct && amount_correct;
+                let mismatches = find_mismatches(
+                    record,
+                    &ExpectedSale::for_refund(
+                        &refund,
+                        &related_sub,
+                        &settings.plans,
+                        &settings.coupon_rules,
+                    ),
+                    amount_check.as_ref(),
+                );
+                let correct = mismatches.is_empty();
+                let mismatches_json = json!(mismatches).to_string();
+                mismatch_reasons.insert(record.id.clone(), mismatches);
                 match correct {
                     true => {
                         info_and_incr!(
@@ -311,6 +474,7 @@ pub async fn verify_reports_with_cj(
                             statsd,
                             LogKey::VerifyReportsRefundNotMatched,
                             refund_id = refund.id.to_string().as_str(),
+                            mismatch_reasons = mismatches_json.as_str(),
                             "Refund found but not matched."
                         );
                         Status::CJNotReceived
@@ -324,9 +488,20 @@ pub async fn verify_reports_with_cj(
                     refund_id = refund.id.to_string().as_str(),
                     "Too many records were found for the refund. Continuing..."
                 );
+                outcome.failed();
                 continue;
             }
         };
+        if dry_run {
+            info!(
+                LogKey::VerifyReportsDryRun,
+                refund_id = &refund.id.to_string().as_str(),
+                status = &next_status.to_string().as_str(),
+                "Dry run. Would update refund status"
+            );
+            outcome.succeeded();
+            continue;
+        }
         match refunds
             .update_refund_status(&refund.refund_id, next_status.clone())
             .await
@@ -339,6 +514,7 @@ pub async fn verify_reports_with_cj(
                     status = &next_status.to_string().as_str(),
                     "Successfully updated refund with new status."
                 );
+                outcome.succeeded();
             }
             Err(e) => {
                 error_and_incr!(
@@ -349,7 +525,38 @@ pub async fn verify_reports_with_cj(
                     refund_id = &refund.id.to_string().as_str(),
                     "Refund update with new status failed."
                 );
+                outcome.failed();
             }
         };
     }
+
+    // Keep every record CJ returned. One that can't be saved doesn't fail the run,
+    // it's saved when CJ returns it again.
+    if !dry_run {
+        let mut n_saved = 0;
+        for record in records_by_account.values().flatten() {
+            let mut commission = Commission::new(record);
+            commission.mismatch_reasons = mismatch_reasons
+                .get(&record.id)
+                .map(|reasons| json!(reasons));
+            match commissions.upsert(&commission).await {
+                Ok(_) => n_saved += 1,
+                Err(e) => {
+                    error_and_incr!(
+                        statsd,
+                        LogKey::VerifyReportsCommissionSaveFailed,
+                        error = e,
+                        commission_id = record.id.as_str(),
+                        "Could not save CJ commission. Continuing..."
+                    );
+                }
+            }
+        }
+        info!(
+            LogKey::VerifyReportsCommissionsSaved,
+            n_saved = n_saved,
+            "Saved CJ commissions."
+        );
+    }
+    Ok(outcome)
 }
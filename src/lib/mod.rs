@@ -1,12 +1,19 @@
 pub mod appconfig;
 pub mod bigquery;
 pub mod cj;
+pub mod cjmsctl;
 pub mod controllers;
+pub mod coupons;
+pub mod error;
 pub mod jobs;
 pub mod models;
+pub mod money;
+pub mod network;
+pub mod plans;
 pub mod settings;
 pub mod telemetry;
 pub mod version;
+pub mod worker;
 
 #[cfg(test)]
 pub mod test_utils {
@@ -41,22 +48,42 @@ pub mod test_utils {
         Settings {
             aic_expiration_days: 2,
             authentication: "_".to_string(),
+            bigquery_domain: None,
             cj_api_access_token: "_".to_string(),
             cj_cid: "_".to_string(),
+            cj_commission_detail_endpoint: None,
+            cj_programs: vec![],
+            cj_max_retries: 0,
+            cj_retry_base_delay_ms: 1,
+            cj_s2s_endpoint: None,
             cj_sftp_user: "_".to_string(),
             cj_signature: "_".to_string(),
             cj_subid: "_".to_string(),
+            cj_timeout_seconds: 5,
             cj_type: "_".to_string(),
+            coupon_rules: crate::coupons::CouponRules::default(),
             database_url: "_".to_string(),
             environment: "_".to_string(),
+            exchange_rates_source: None,
+            exchange_rates_timeout_seconds: 5,
             gcp_project: "_".to_string(),
             host: "_".to_string(),
             log_level: "_".to_string(),
+            max_exchange_rate_age_days: 7,
+            max_job_failure_percent: 50,
+            max_re_reports: 0,
+            plans: crate::plans::PlanCatalog::default(),
             port: 1111,
+            publisher_currency: "usd".to_string(),
+            reconcile_window_days: 30,
+            schedules: std::collections::BTreeMap::new(),
             sentry_dsn: "_".to_string(),
             sentry_environment: "_".to_string(),
             statsd_host: "_".to_string(),
             statsd_port: 2222,
+            verify_amount_tolerance_cents: 0,
+            verify_exchange_rate_tolerance_percent: 2,
+            verify_window_hours: 36,
         }
     }
 }
@@ -4,6 +4,8 @@ use uuid::Uuid;
 
 use crate::{error, settings::Settings, telemetry::LogKey};
 
+use super::filters::RecordFilter;
+
 #[derive(Debug)]
 pub struct AIC {
     pub id: Uuid,
@@ -147,29 +149,102 @@ impl AICModel<'_> {
             .await
     }
 
+    // aic_archive has an extra archived column so archive queries list their columns
     pub async fn fetch_one_by_id_from_archive(&self, id: &Uuid) -> Result<AIC, Error> {
-        query_as!(AIC, "SELECT * FROM aic_archive WHERE id = $1", id)
-            .fetch_one(self.db_pool)
-            .await
+        query_as!(
+            AIC,
+            "SELECT id, cj_event_value, flow_id, created, expires FROM aic_archive WHERE id = $1",
+            id
+        )
+        .fetch_one(self.db_pool)
+        .await
     }
 
     pub async fn fetch_one_by_flow_id_from_archive(&self, flow_id: &str) -> Result<AIC, Error> {
-        query_as!(AIC, "SELECT * FROM aic_archive WHERE flow_id = $1", flow_id)
+        query_as!(
+            AIC,
+            "SELECT id, cj_event_value, flow_id, created, expires FROM aic_archive WHERE flow_id = $1",
+            flow_id
+        )
+        .fetch_one(self.db_pool)
+        .await
+    }
+
+    // None for AICs archived before the archived column was added
+    pub async fn fetch_archived_t(&self, id: &Uuid) -> Result<Option<OffsetDateTime>, Error> {
+        let result = query!("SELECT archived FROM aic_archive WHERE id = $1", id)
             .fetch_one(self.db_pool)
-            .await
+            .await?;
+        Ok(result.archived)
+    }
+
+    // AICs have no status, plan or country so only the flow_id, cj_event_value,
+    // date range and cursor filters apply.
+    pub async fn fetch_filtered(&self, filter: &RecordFilter) -> Result<Vec<AIC>, Error> {
+        query_as!(
+            AIC,
+            r#"
+            SELECT *
+            FROM aic
+            WHERE ($1::TIMESTAMPTZ IS NULL OR created >= $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR created < $2)
+            AND ($3::TEXT IS NULL OR flow_id = $3)
+            AND ($4::TEXT IS NULL OR cj_event_value = $4)
+            AND ($5::TIMESTAMPTZ IS NULL OR (created, id) > ($5, $6::UUID))
+            ORDER BY created, id
+            LIMIT $7"#,
+            filter.since,
+            filter.before,
+            filter.flow_id,
+            filter.cj_event_value,
+            filter.after_t(),
+            filter.after_id(),
+            filter.page_size(),
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
+    pub async fn fetch_filtered_from_archive(
+        &self,
+        filter: &RecordFilter,
+    ) -> Result<Vec<AIC>, Error> {
+        query_as!(
+            AIC,
+            r#"
+            SELECT id, cj_event_value, flow_id, created, expires
+            FROM aic_archive
+            WHERE ($1::TIMESTAMPTZ IS NULL OR created >= $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR created < $2)
+            AND ($3::TEXT IS NULL OR flow_id = $3)
+            AND ($4::TEXT IS NULL OR cj_event_value = $4)
+            AND ($5::TIMESTAMPTZ IS NULL OR (created, id) > ($5, $6::UUID))
+            ORDER BY created, id
+            LIMIT $7"#,
+            filter.since,
+            filter.before,
+            filter.flow_id,
+            filter.cj_event_value,
+            filter.after_t(),
+            filter.after_id(),
+            filter.page_size(),
+        )
+        .fetch_all(self.db_pool)
+        .await
     }
 
     pub async fn create_archive_from_aic(&self, aic: &AIC) -> Result<AIC, Error> {
         query_as!(
             AIC,
-            "INSERT INTO aic_archive (id, cj_event_value, flow_id, created, expires)
-			VALUES ($1, $2, $3, $4, $5)
-			RETURNING *",
+            "INSERT INTO aic_archive (id, cj_event_value, flow_id, created, expires, archived)
+			VALUES ($1, $2, $3, $4, $5, $6)
+			RETURNING id, cj_event_value, flow_id, created, expires",
             aic.id,
             aic.cj_event_value,
             aic.flow_id,
             aic.created,
-            aic.expires
+            aic.expires,
+            OffsetDateTime::now_utc(),
         )
         .fetch_one(self.db_pool)
         .await
@@ -179,26 +254,56 @@ impl AICModel<'_> {
         &self,
         transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
         aic: &AIC,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), Error> {
         query!("DELETE FROM aic WHERE id = $1", aic.id)
             .execute(&mut *transaction)
             .await?;
         query!(
-            "INSERT INTO aic_archive (id, cj_event_value, flow_id, created, expires)
-			VALUES ($1, $2, $3, $4, $5)
+            "INSERT INTO aic_archive (id, cj_event_value, flow_id, created, expires, archived)
+			VALUES ($1, $2, $3, $4, $5, $6)
 			RETURNING *",
             aic.id,
             aic.cj_event_value,
             aic.flow_id,
             aic.created,
-            aic.expires
+            aic.expires,
+            OffsetDateTime::now_utc(),
         )
         .fetch_one(&mut *transaction)
         .await?;
         Ok(())
     }
 
-    pub async fn archive_aic(&self, aic: &AIC) -> Result<(), Box<dyn std::error::Error>> {
+    // Note that an expired AIC will be archived again by the next cleanup run.
+    pub async fn unarchive_aic(&self, id: &Uuid) -> Result<AIC, Error> {
+        // Wrap deleting archive row and creating aic row into one transaction
+        let mut transaction = self.db_pool.begin().await?;
+        let aic = query_as!(
+            AIC,
+            "DELETE FROM aic_archive WHERE id = $1
+			RETURNING id, cj_event_value, flow_id, created, expires",
+            id
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+        let aic = query_as!(
+            AIC,
+            "INSERT INTO aic (id, cj_event_value, flow_id, created, expires)
+			VALUES ($1, $2, $3, $4, $5)
+			RETURNING *",
+            aic.id,
+            aic.cj_event_value,
+            aic.flow_id,
+            aic.created,
+            aic.expires
+        )
+        .fetch_one(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(aic)
+    }
+
+    pub async fn archive_aic(&self, aic: &AIC) -> Result<(), Error> {
         // Wrap creating archive row and deleting aic row into one transaction
         let mut transaction = self.db_pool.begin().await?;
         self.create_archive_delete_aic(&mut transaction, aic)
@@ -0,0 +1,164 @@
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::{query_as, Error, PgPool};
+use time::OffsetDateTime;
+
+use crate::network::NetworkCommission;
+
+// A commission from an affiliate network, e.g. a CJ Commission Detail API record, kept
+// so payouts can be looked into without querying the network again. Amounts are in
+// minor units of the publisher's currency.
+#[derive(Debug, Serialize)]
+pub struct Commission {
+    pub commission_id: String,
+    pub order_id: String,
+    pub original: bool,
+    pub correction_reason: Option<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub posting_date: OffsetDateTime,
+    pub sale_amount: i64,
+    pub commission_amount: Option<i64>,
+    pub action_status: Option<String>,
+    pub validation_status: Option<String>,
+    pub publisher_id: Option<String>,
+    pub skus: Vec<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub first_seen: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub last_seen: OffsetDateTime,
+    // What verify_reports found didn't match what we reported, see cj::matching. An
+    // empty list if it matched, null if it wasn't checked.
+    pub mismatch_reasons: Option<JsonValue>,
+}
+
+impl Commission {
+    pub fn new(record: &NetworkCommission) -> Self {
+        let now = OffsetDateTime::now_utc();
+        Commission {
+            commission_id: record.id.clone(),
+            order_id: record.order_id.clone(),
+            original: record.original,
+            correction_reason: record.correction_reason.clone(),
+            posting_date: record.posting_date,
+            sale_amount: record.sale_amount.minor_units,
+            commission_amount: record.commission_amount.as_ref().map(|a| a.minor_units),
+            action_status: record.status.clone(),
+            validation_status: record.validation_status.clone(),
+            publisher_id: record.publisher_id.clone(),
+            skus: record.items.iter().map(|item| item.sku.clone()).collect(),
+            first_seen: now,
+            last_seen: now,
+            mismatch_reasons: None,
+        }
+    }
+}
+
+pub struct CommissionModel<'a> {
+    pub db_pool: &'a PgPool,
+}
+
+impl CommissionModel<'_> {
+    // CJ updates a commission as it moves through locking and validation, so a
+    // commission we've seen before is overwritten. first_seen is kept, and so are
+    // mismatch_reasons unless the commission was checked again.
+    pub async fn upsert(&self, commission: &Commission) -> Result<Commission, Error> {
+        query_as!(
+            Commission,
+            "INSERT INTO commissions (
+                commission_id,
+                order_id,
+                original,
+                correction_reason,
+                posting_date,
+                sale_amount,
+                commission_amount,
+                action_status,
+                validation_status,
+                publisher_id,
+                skus,
+                first_seen,
+                last_seen,
+                mismatch_reasons
+            )
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            ON CONFLICT (commission_id) DO UPDATE
+            SET
+                order_id = EXCLUDED.order_id,
+                original = EXCLUDED.original,
+                correction_reason = EXCLUDED.correction_reason,
+                posting_date = EXCLUDED.posting_date,
+                sale_amount = EXCLUDED.sale_amount,
+                commission_amount = EXCLUDED.commission_amount,
+                action_status = EXCLUDED.action_status,
+                validation_status = EXCLUDED.validation_status,
+                publisher_id = EXCLUDED.publisher_id,
+                skus = EXCLUDED.skus,
+                last_seen = EXCLUDED.last_seen,
+                mismatch_reasons = COALESCE(EXCLUDED.mismatch_reasons, commissions.mismatch_reasons)
+			RETURNING *",
+            commission.commission_id,
+            commission.order_id,
+            commission.original,
+            commission.correction_reason,
+            commission.posting_date,
+            commission.sale_amount,
+            commission.commission_amount,
+            commission.action_status,
+            commission.validation_status,
+            commission.publisher_id,
+            &commission.skus,
+            commission.first_seen,
+            commission.last_seen,
+            commission.mismatch_reasons,
+        )
+        .fetch_one(self.db_pool)
+        .await
+    }
+
+    pub async fn fetch_one_by_commission_id(
+        &self,
+        commission_id: &str,
+    ) -> Result<Commission, Error> {
+        query_as!(
+            Commission,
+            "SELECT * FROM commissions WHERE commission_id = $1",
+            commission_id
+        )
+        .fetch_one(self.db_pool)
+        .await
+    }
+
+    // Oldest first, so the original comes before its corrections
+    pub async fn fetch_all_by_order_id(&self, order_id: &str) -> Result<Vec<Commission>, Error> {
+        query_as!(
+            Commission,
+            "SELECT * FROM commissions WHERE order_id = $1 ORDER BY posting_date, commission_id",
+            order_id
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
+    // Every commission for the orders with one posted in [since, before), so an order's
+    // original and corrections are seen together. Grouped by order, oldest first.
+    pub async fn fetch_all_for_orders_posted_between(
+        &self,
+        since: &OffsetDateTime,
+        before: &OffsetDateTime,
+    ) -> Result<Vec<Commission>, Error> {
+        query_as!(
+            Commission,
+            r#"
+            SELECT *
+            FROM commissions
+            WHERE order_id IN (
+                SELECT order_id FROM commissions WHERE posting_date >= $1 AND posting_date < $2
+            )
+            ORDER BY order_id, posting_date, commission_id"#,
+            since,
+            before
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+}
@@ -0,0 +1,55 @@
+use sqlx::{query_as, Error, PgPool};
+use time::{Date, OffsetDateTime};
+
+// The value of one unit of currency in settings.publisher_currency on date
+#[derive(Debug, PartialEq)]
+pub struct ExchangeRate {
+    pub date: Date,
+    // Lowercase, like plan_currency
+    pub currency: String,
+    pub rate: f64,
+    pub loaded: OffsetDateTime,
+}
+
+pub struct ExchangeRateModel<'a> {
+    pub db_pool: &'a PgPool,
+}
+
+impl ExchangeRateModel<'_> {
+    // A rate loaded again for the same day replaces the one before
+    pub async fn upsert(&self, rate: &ExchangeRate) -> Result<ExchangeRate, Error> {
+        query_as!(
+            ExchangeRate,
+            "INSERT INTO exchange_rates (date, currency, rate, loaded)
+			VALUES ($1, $2, $3, $4)
+            ON CONFLICT (date, currency) DO UPDATE
+            SET rate = EXCLUDED.rate, loaded = EXCLUDED.loaded
+			RETURNING *",
+            rate.date,
+            rate.currency,
+            rate.rate,
+            rate.loaded,
+        )
+        .fetch_one(self.db_pool)
+        .await
+    }
+
+    // The latest rate on or before date, as rates files skip weekends and holidays
+    pub async fn fetch_one_on_date(
+        &self,
+        currency: &str,
+        date: &Date,
+    ) -> Result<Option<ExchangeRate>, Error> {
+        query_as!(
+            ExchangeRate,
+            "SELECT * FROM exchange_rates
+            WHERE currency = $1 AND date <= $2
+            ORDER BY date DESC
+            LIMIT 1",
+            currency.to_lowercase(),
+            date
+        )
+        .fetch_optional(self.db_pool)
+        .await
+    }
+}
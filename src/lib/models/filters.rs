@@ -0,0 +1,131 @@
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::status_history::Status;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+// Cursor timestamps are limited to years 1 to 9999. time panics on timestamps outside the
+// dates it can represent, and a cursor comes from the client.
+const MIN_CURSOR_SECONDS: i128 = -62_135_596_800;
+const MAX_CURSOR_SECONDS: i128 = 253_402_300_799;
+
+// Keyset pagination cursor. Records are ordered by their creation timestamp and then by id,
+// so the pair of the last record seen is enough to resume from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub t: OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        format!("{}_{}", self.t.unix_timestamp_nanos(), self.id)
+    }
+
+    pub fn decode(value: &str) -> Option<Cursor> {
+        let (t, id) = value.split_once('_')?;
+        let t = t.parse::<i128>().ok()?;
+        if !(MIN_CURSOR_SECONDS..=MAX_CURSOR_SECONDS).contains(&t.div_euclid(1_000_000_000)) {
+            return None;
+        }
+        let id = Uuid::parse_str(id).ok()?;
+        Some(Cursor {
+            t: OffsetDateTime::from_unix_timestamp_nanos(t),
+            id,
+        })
+    }
+}
+
+// Filters shared by the admin listings. Not every filter applies to every table, see the
+// individual models for which columns they use.
+#[derive(Debug, Clone)]
+pub struct RecordFilter {
+    pub status: Option<Status>,
+    pub since: Option<OffsetDateTime>,
+    pub before: Option<OffsetDateTime>,
+    pub plan_id: Option<String>,
+    pub country: Option<String>,
+    pub flow_id: Option<String>,
+    pub cj_event_value: Option<String>,
+    pub after: Option<Cursor>,
+    pub limit: i64,
+}
+
+impl Default for RecordFilter {
+    fn default() -> Self {
+        RecordFilter {
+            status: None,
+            since: None,
+            before: None,
+            plan_id: None,
+            country: None,
+            flow_id: None,
+            cj_event_value: None,
+            after: None,
+            limit: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+impl RecordFilter {
+    pub fn status_string(&self) -> Option<String> {
+        self.status.as_ref().map(|s| s.to_string())
+    }
+
+    pub fn after_t(&self) -> Option<OffsetDateTime> {
+        self.after.as_ref().map(|c| c.t)
+    }
+
+    pub fn after_id(&self) -> Option<Uuid> {
+        self.after.as_ref().map(|c| c.id)
+    }
+
+    pub fn page_size(&self) -> i64 {
+        self.limit.clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            t: OffsetDateTime::now_utc(),
+            id: Uuid::new_v4(),
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded, cursor);
+    }
+
+    #[test]
+    fn cursor_decode_rejects_garbage() {
+        assert!(Cursor::decode("").is_none());
+        assert!(Cursor::decode("abc_def").is_none());
+        assert!(Cursor::decode(&format!("123_{}", "not-a-uuid")).is_none());
+    }
+
+    #[test]
+    fn cursor_decode_rejects_timestamps_out_of_range() {
+        let id = Uuid::new_v4();
+        assert!(Cursor::decode(&format!("{}_{}", i128::MAX, id)).is_none());
+        assert!(Cursor::decode(&format!("{}_{}", i128::MIN, id)).is_none());
+        assert!(Cursor::decode(&format!("{}_{}", 253_402_300_800_000_000_000i128, id)).is_none());
+        assert!(Cursor::decode(&format!("{}_{}", -62_135_596_801_000_000_000i128, id)).is_none());
+        assert!(Cursor::decode(&format!("{}_{}", 253_402_300_799_999_999_999i128, id)).is_some());
+        assert!(Cursor::decode(&format!("{}_{}", -62_135_596_800_000_000_000i128, id)).is_some());
+    }
+
+    #[test]
+    fn page_size_is_clamped() {
+        let mut filter = RecordFilter::default();
+        assert_eq!(filter.page_size(), DEFAULT_PAGE_SIZE);
+        filter.limit = 0;
+        assert_eq!(filter.page_size(), 1);
+        filter.limit = 5000;
+        assert_eq!(filter.page_size(), MAX_PAGE_SIZE);
+    }
+}
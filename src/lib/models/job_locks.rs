@@ -0,0 +1,44 @@
+use sqlx::{query, Connection, Error, PgConnection, PgPool};
+
+// Session level advisory locks, keyed by a hash of the name. The lock is held by the
+// connection, so it is taken off the pool and closed on release. If the process dies
+// the connection closes and Postgres releases the lock.
+pub struct JobLock {
+    name: String,
+    conn: PgConnection,
+}
+
+impl JobLock {
+    pub async fn release(mut self) -> Result<(), Error> {
+        query!("SELECT pg_advisory_unlock(hashtext($1))", self.name)
+            .fetch_one(&mut self.conn)
+            .await?;
+        self.conn.close().await
+    }
+}
+
+pub struct JobLockModel<'a> {
+    pub db_pool: &'a PgPool,
+}
+
+impl JobLockModel<'_> {
+    // Returns None if another session holds the lock
+    pub async fn try_lock(&self, name: &str) -> Result<Option<JobLock>, Error> {
+        let mut conn = self.db_pool.acquire().await?.detach();
+        let result = query!(
+            r#"SELECT pg_try_advisory_lock(hashtext($1)) AS "locked!""#,
+            name
+        )
+        .fetch_one(&mut conn)
+        .await?;
+        if result.locked {
+            Ok(Some(JobLock {
+                name: name.to_string(),
+                conn,
+            }))
+        } else {
+            conn.close().await?;
+            Ok(None)
+        }
+    }
+}
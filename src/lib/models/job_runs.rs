@@ -0,0 +1,102 @@
+use serde::Serialize;
+use sqlx::{query_as, Error, PgPool};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::jobs::outcome::JobOutcome;
+
+#[derive(Debug, Serialize)]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub started: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub ended: Option<OffsetDateTime>,
+    pub processed: i64,
+    pub succeeded: i64,
+    pub skipped: i64,
+    pub failed: i64,
+    // The panic message, or why the run failed
+    pub error: Option<String>,
+}
+
+pub struct JobRunModel<'a> {
+    pub db_pool: &'a PgPool,
+}
+
+impl JobRunModel<'_> {
+    pub async fn start(&self, job: &str) -> Result<JobRun, Error> {
+        query_as!(
+            JobRun,
+            "INSERT INTO job_runs (id, job, started)
+			VALUES ($1, $2, $3)
+			RETURNING *",
+            Uuid::new_v4(),
+            job,
+            OffsetDateTime::now_utc(),
+        )
+        .fetch_one(self.db_pool)
+        .await
+    }
+
+    pub async fn finish(
+        &self,
+        id: &Uuid,
+        outcome: &JobOutcome,
+        error: Option<String>,
+    ) -> Result<JobRun, Error> {
+        query_as!(
+            JobRun,
+            "UPDATE job_runs
+            SET
+                ended = $1,
+                processed = $2,
+                succeeded = $3,
+                skipped = $4,
+                failed = $5,
+                error = $6
+            WHERE id = $7
+			RETURNING *",
+            OffsetDateTime::now_utc(),
+            outcome.processed() as i64,
+            outcome.succeeded as i64,
+            outcome.skipped as i64,
+            outcome.failed as i64,
+            error,
+            id,
+        )
+        .fetch_one(self.db_pool)
+        .await
+    }
+
+    pub async fn fetch_one_by_id(&self, id: &Uuid) -> Result<JobRun, Error> {
+        query_as!(JobRun, "SELECT * FROM job_runs WHERE id = $1", id)
+            .fetch_one(self.db_pool)
+            .await
+    }
+
+    // Includes runs that are still going
+    pub async fn fetch_last_run_per_job(&self) -> Result<Vec<JobRun>, Error> {
+        query_as!(
+            JobRun,
+            "SELECT DISTINCT ON (job) * FROM job_runs ORDER BY job, started DESC"
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
+    // A run is successful if it ended without panicking or failing
+    pub async fn fetch_last_success_per_job(&self) -> Result<Vec<JobRun>, Error> {
+        query_as!(
+            JobRun,
+            "SELECT DISTINCT ON (job) *
+            FROM job_runs
+            WHERE ended IS NOT NULL
+            AND error IS NULL
+            ORDER BY job, started DESC"
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+}
@@ -1,4 +1,11 @@
 pub mod aic;
+pub mod commissions;
+pub mod exchange_rates;
+pub mod filters;
+pub mod job_locks;
+pub mod job_runs;
 pub mod refunds;
 pub mod status_history;
+pub mod status_overrides;
 pub mod subscriptions;
+pub mod timeline;
@@ -1,9 +1,12 @@
 use serde_json::Value as JsonValue;
-use sqlx::{query, query_as, Error, PgPool};
+use sqlx::{query, query_as, Error, PgPool, Postgres, Transaction};
 use time::{Date, OffsetDateTime};
 use uuid::Uuid;
 
-use super::status_history::{DateRange, Status, UpdateStatus};
+use super::{
+    filters::RecordFilter,
+    status_history::{DateRange, Status, StatusCount, UpdateStatus},
+};
 
 pub struct PartialRefund {
     pub id: Uuid,
@@ -196,12 +199,76 @@ impl RefundModel<'_> {
         .await
     }
 
+    pub async fn fetch_one_by_refund_id_for_update(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        refund_id: &str,
+    ) -> Result<Refund, Error> {
+        query_as!(
+            Refund,
+            "SELECT * FROM refunds WHERE refund_id = $1 FOR UPDATE",
+            refund_id
+        )
+        .fetch_one(&mut *transaction)
+        .await
+    }
+
+    pub async fn update_status_in_transaction(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        refund: &Refund,
+    ) -> Result<Refund, Error> {
+        query_as!(
+            Refund,
+            r#"UPDATE refunds
+            SET
+                status = $1,
+                status_t = $2,
+                status_history = $3
+            WHERE refund_id = $4
+			RETURNING *"#,
+            refund.status,
+            refund.status_t,
+            refund.status_history,
+            refund.refund_id,
+        )
+        .fetch_one(&mut *transaction)
+        .await
+    }
+
     pub async fn fetch_all(&self) -> Result<Vec<Refund>, Error> {
         query_as!(Refund, "SELECT * FROM refunds")
             .fetch_all(self.db_pool)
             .await
     }
 
+    pub async fn fetch_all_by_subscription_id(
+        &self,
+        subscription_id: &str,
+    ) -> Result<Vec<Refund>, Error> {
+        query_as!(
+            Refund,
+            "SELECT * FROM refunds WHERE subscription_id = $1 ORDER BY refund_created",
+            subscription_id
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
+    // Oldest first
+    pub async fn fetch_all_by_subscription_ids(
+        &self,
+        subscription_ids: &[String],
+    ) -> Result<Vec<Refund>, Error> {
+        query_as!(
+            Refund,
+            "SELECT * FROM refunds WHERE subscription_id = ANY($1) ORDER BY refund_created",
+            subscription_ids
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
     pub async fn fetch_all_by_status(&self, status: Status) -> Result<Vec<Refund>, Error> {
         // Note that users of this function rely on status_t being available
         query_as!(
@@ -218,6 +285,40 @@ impl RefundModel<'_> {
         .await
     }
 
+    pub async fn fetch_filtered(&self, filter: &RecordFilter) -> Result<Vec<Refund>, Error> {
+        // Refunds don't carry plan, country, flow_id or cj_event_value themselves,
+        // so those filters apply to the subscription the refund belongs to.
+        query_as!(
+            Refund,
+            r#"
+            SELECT refunds.*
+            FROM refunds
+            LEFT JOIN subscriptions ON refunds.subscription_id = subscriptions.subscription_id
+            WHERE ($1::TEXT IS NULL OR refunds.status = $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR refunds.refund_created >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR refunds.refund_created < $3)
+            AND ($4::TEXT IS NULL OR subscriptions.plan_id = $4)
+            AND ($5::TEXT IS NULL OR subscriptions.country = $5)
+            AND ($6::TEXT IS NULL OR subscriptions.flow_id = $6)
+            AND ($7::TEXT IS NULL OR subscriptions.cj_event_value = $7)
+            AND ($8::TIMESTAMPTZ IS NULL OR (refunds.refund_created, refunds.id) > ($8, $9::UUID))
+            ORDER BY refunds.refund_created, refunds.id
+            LIMIT $10"#,
+            filter.status_string(),
+            filter.since,
+            filter.before,
+            filter.plan_id,
+            filter.country,
+            filter.flow_id,
+            filter.cj_event_value,
+            filter.after_t(),
+            filter.after_id(),
+            filter.page_size(),
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
     pub async fn fetch_by_correction_file_day(&self, day: &Date) -> Result<Vec<Refund>, Error> {
         query_as!(
             Refund,
@@ -228,6 +329,15 @@ impl RefundModel<'_> {
         .await
     }
 
+    pub async fn count_by_status(&self) -> Result<Vec<StatusCount>, Error> {
+        query_as!(
+            StatusCount,
+            r#"SELECT status, COUNT(*) AS "count!" FROM refunds GROUP BY status ORDER BY status"#
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
     pub async fn get_reported_date_range(&self) -> Result<DateRange, Error> {
         let result = query!(
             "SELECT MIN(status_t), MAX(status_t) FROM refunds WHERE status = 'Reported' AND status_t IS NOT NULL",
@@ -12,6 +12,11 @@ pub struct DateRange {
     pub max: Option<OffsetDateTime>,
 }
 
+pub struct StatusCount {
+    pub status: Option<String>,
+    pub count: i64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, EnumToString, EnumString)]
 pub enum Status {
     NotReported,
@@ -25,10 +30,16 @@ pub enum Status {
 pub struct StatusHistoryEntry {
     pub t: OffsetDateTime,
     pub status: Status,
+    // Why the record moved to status, e.g. why a subscription won't be reported. Only
+    // kept when a job gives one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 impl PartialEq for StatusHistoryEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.status == other.status && self.t.unix_timestamp() == other.t.unix_timestamp()
+        self.status == other.status
+            && self.t.unix_timestamp() == other.t.unix_timestamp()
+            && self.reason == other.reason
     }
 }
 impl Eq for StatusHistoryEntry {}
@@ -65,11 +76,8 @@ pub trait UpdateStatus {
     fn set_raw_status_history(&mut self, v: Option<JsonValue>);
 
     fn get_status(&self) -> Option<Status> {
-        let status_value = self.get_raw_status().unwrap_or_else(|| "".to_string());
-        match Status::from_str(&status_value) {
-            Ok(status) => Some(status),
-            Err(_) => None,
-        }
+        let status_value = self.get_raw_status().unwrap_or_default();
+        Status::from_str(&status_value).ok()
     }
 
     fn get_status_history(&self) -> Option<StatusHistory> {
@@ -78,6 +86,10 @@ pub trait UpdateStatus {
     }
 
     fn update_status(&mut self, new_status: Status) {
+        self.update_status_with_reason(new_status, None)
+    }
+
+    fn update_status_with_reason(&mut self, new_status: Status, reason: Option<String>) {
         let t = OffsetDateTime::now_utc();
         self.set_status_t(Some(t));
         self.set_raw_status(Some(new_status.to_string()));
@@ -88,6 +100,7 @@ pub trait UpdateStatus {
         status_history.entries.push(StatusHistoryEntry {
             status: new_status,
             t,
+            reason,
         });
         self.set_raw_status_history(Some(json!(status_history)));
     }
@@ -0,0 +1,204 @@
+use serde::Serialize;
+use sqlx::{query_as, PgPool, Postgres, Transaction};
+use strum_macros::{Display as EnumToString, EnumString};
+use thiserror::Error;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{
+    refunds::{Refund, RefundModel},
+    status_history::{Status, UpdateStatus},
+    subscriptions::{Subscription, SubscriptionModel},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, EnumToString, EnumString)]
+pub enum RecordType {
+    Subscription,
+    Refund,
+}
+
+#[derive(Error, Debug)]
+pub enum StatusOverrideError {
+    #[error("A reason is required to override a status")]
+    MissingReason,
+
+    #[error("An operator is required to override a status")]
+    MissingOperator,
+
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+// Audit trail entry for a status that was changed by hand rather than by a job.
+#[derive(Debug, Serialize)]
+pub struct StatusOverride {
+    pub id: Uuid,
+    pub record_type: String,
+    pub record_id: Uuid,
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub reason: String,
+    pub operator: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub created: OffsetDateTime,
+}
+
+impl StatusOverride {
+    pub fn new(
+        record_type: RecordType,
+        record_id: Uuid,
+        from_status: Option<String>,
+        to_status: Status,
+        reason: &str,
+        operator: &str,
+    ) -> Self {
+        StatusOverride {
+            id: Uuid::new_v4(),
+            record_type: record_type.to_string(),
+            record_id,
+            from_status,
+            to_status: to_status.to_string(),
+            reason: reason.trim().to_string(),
+            operator: operator.trim().to_string(),
+            created: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
+fn validate(reason: &str, operator: &str) -> Result<(), StatusOverrideError> {
+    if reason.trim().is_empty() {
+        return Err(StatusOverrideError::MissingReason);
+    }
+    if operator.trim().is_empty() {
+        return Err(StatusOverrideError::MissingOperator);
+    }
+    Ok(())
+}
+
+pub struct StatusOverrideModel<'a> {
+    pub db_pool: &'a PgPool,
+}
+
+impl StatusOverrideModel<'_> {
+    async fn create_from_override(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        entry: &StatusOverride,
+    ) -> Result<StatusOverride, sqlx::Error> {
+        query_as!(
+            StatusOverride,
+            "INSERT INTO status_overrides (id, record_type, record_id, from_status, to_status, reason, operator, created)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+			RETURNING *",
+            entry.id,
+            entry.record_type,
+            entry.record_id,
+            entry.from_status,
+            entry.to_status,
+            entry.reason,
+            entry.operator,
+            entry.created,
+        )
+        .fetch_one(&mut *transaction)
+        .await
+    }
+
+    pub async fn override_subscription_status(
+        &self,
+        id: &Uuid,
+        new_status: Status,
+        reason: &str,
+        operator: &str,
+    ) -> Result<Subscription, StatusOverrideError> {
+        validate(reason, operator)?;
+        let subscriptions = SubscriptionModel {
+            db_pool: self.db_pool,
+        };
+        // Wrap the status change and the audit entry into one transaction
+        let mut transaction = self.db_pool.begin().await?;
+        let mut sub = subscriptions
+            .fetch_one_by_id_for_update(&mut transaction, id)
+            .await?;
+        let from_status = sub.get_raw_status();
+        sub.update_status(new_status.clone());
+        let sub = subscriptions
+            .update_status_in_transaction(&mut transaction, &sub)
+            .await?;
+        let entry = StatusOverride::new(
+            RecordType::Subscription,
+            sub.id,
+            from_status,
+            new_status,
+            reason,
+            operator,
+        );
+        self.create_from_override(&mut transaction, &entry).await?;
+        transaction.commit().await?;
+        Ok(sub)
+    }
+
+    pub async fn override_refund_status(
+        &self,
+        refund_id: &str,
+        new_status: Status,
+        reason: &str,
+        operator: &str,
+    ) -> Result<Refund, StatusOverrideError> {
+        validate(reason, operator)?;
+        let refunds = RefundModel {
+            db_pool: self.db_pool,
+        };
+        // Wrap the status change and the audit entry into one transaction
+        let mut transaction = self.db_pool.begin().await?;
+        let mut refund = refunds
+            .fetch_one_by_refund_id_for_update(&mut transaction, refund_id)
+            .await?;
+        let from_status = refund.get_raw_status();
+        refund.update_status(new_status.clone());
+        let refund = refunds
+            .update_status_in_transaction(&mut transaction, &refund)
+            .await?;
+        let entry = StatusOverride::new(
+            RecordType::Refund,
+            refund.id,
+            from_status,
+            new_status,
+            reason,
+            operator,
+        );
+        self.create_from_override(&mut transaction, &entry).await?;
+        transaction.commit().await?;
+        Ok(refund)
+    }
+
+    pub async fn fetch_all_by_record_id(
+        &self,
+        record_id: &Uuid,
+    ) -> Result<Vec<StatusOverride>, sqlx::Error> {
+        query_as!(
+            StatusOverride,
+            "SELECT * FROM status_overrides WHERE record_id = $1 ORDER BY created",
+            record_id
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_requires_reason_and_operator() {
+        assert!(matches!(
+            validate(" ", "ops"),
+            Err(StatusOverrideError::MissingReason)
+        ));
+        assert!(matches!(
+            validate("CJ fixed it", ""),
+            Err(StatusOverrideError::MissingOperator)
+        ));
+        assert!(validate("CJ fixed it", "ops").is_ok());
+    }
+}
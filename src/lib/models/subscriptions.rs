@@ -1,11 +1,28 @@
-use serde_json::Value as JsonValue;
-use sqlx::{query, query_as, Error, PgPool};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use sqlx::{query, query_as, Error, PgPool, Postgres, Transaction};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
 use crate::models::status_history::{Status, UpdateStatus};
 
-use super::status_history::DateRange;
+use super::{
+    filters::RecordFilter,
+    status_history::{DateRange, StatusCount},
+};
+
+// One of the items a subscription was sold as. Amounts are in minor units of the plan
+// currency.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct LineItem {
+    pub plan_id: String,
+    pub quantity: i32,
+    // For each of the quantity
+    pub amount: i32,
+    // Taken off the whole line
+    #[serde(default)]
+    pub discount: i32,
+}
 
 // All the public fields of Subscription for clean construction
 pub struct PartialSubscription {
@@ -21,7 +38,7 @@ pub struct PartialSubscription {
     pub plan_currency: String,
     pub plan_amount: i32,
     pub country: Option<String>,
-    pub coupons: Option<String>,
+    pub coupons: Vec<String>,
     pub aic_id: Option<Uuid>,
     pub aic_expires: Option<OffsetDateTime>,
     pub cj_event_value: Option<String>,
@@ -41,7 +58,7 @@ pub struct Subscription {
     pub plan_currency: String,
     pub plan_amount: i32,
     pub country: Option<String>,
-    pub coupons: Option<String>,
+    pub coupons: Vec<String>,
     pub aic_id: Option<Uuid>,
     pub aic_expires: Option<OffsetDateTime>,
     pub cj_event_value: Option<String>,
@@ -49,6 +66,10 @@ pub struct Subscription {
     status: Option<String>,
     status_t: Option<OffsetDateTime>,
     status_history: Option<JsonValue>,
+    // A list of LineItem, see line_items()
+    line_items: JsonValue,
+    // How many times verify_reports re-reported the subscription, see re_report_sub
+    pub re_report_count: i32,
 }
 impl PartialEq for Subscription {
     fn eq(&self, other: &Self) -> bool {
@@ -67,7 +88,9 @@ impl PartialEq for Subscription {
         self.coupons == other.coupons &&
         self.aic_id == other.aic_id &&
         self.cj_event_value == other.cj_event_value &&
-        self.status == other.status
+        self.status == other.status &&
+        self.line_items == other.line_items &&
+        self.re_report_count == other.re_report_count
         // Compare manually if needed
         // self.status_history == other.status_history
         ;
@@ -137,10 +160,35 @@ impl Subscription {
             status: None,
             status_t: None,
             status_history: None,
+            line_items: json!([]),
+            re_report_count: 0,
         };
         sub.update_status(Status::NotReported);
         sub
     }
+
+    // The line items BigQuery had for the subscription, empty if it was just its plan
+    pub fn line_items(&self) -> Vec<LineItem> {
+        serde_json::from_value(self.line_items.clone()).unwrap_or_default()
+    }
+
+    pub fn set_line_items(&mut self, line_items: &[LineItem]) {
+        self.line_items = json!(line_items);
+    }
+
+    // What the subscription was sold as, one item for the plan if it has no line items
+    pub fn items(&self) -> Vec<LineItem> {
+        let line_items = self.line_items();
+        if !line_items.is_empty() {
+            return line_items;
+        }
+        vec![LineItem {
+            plan_id: self.plan_id.clone(),
+            quantity: self.quantity,
+            amount: self.plan_amount,
+            discount: 0,
+        }]
+    }
 }
 
 pub struct SubscriptionModel<'a> {
@@ -169,9 +217,11 @@ impl SubscriptionModel<'_> {
                 cj_event_value,
                 status,
                 status_t,
-                status_history
+                status_history,
+                line_items,
+                re_report_count
              )
-			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)
 			RETURNING *",
             sub.id,
             sub.flow_id,
@@ -184,13 +234,15 @@ impl SubscriptionModel<'_> {
             sub.plan_currency,
             sub.plan_amount,
             sub.country,
-            sub.coupons,
+            &sub.coupons[..],
             sub.aic_id,
             sub.aic_expires,
             sub.cj_event_value,
             sub.status,
             sub.status_t,
             sub.status_history,
+            sub.line_items,
+            sub.re_report_count,
         )
         .fetch_one(self.db_pool)
         .await
@@ -229,6 +281,29 @@ impl SubscriptionModel<'_> {
         .await
     }
 
+    pub async fn fetch_all_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Subscription>, Error> {
+        query_as!(
+            Subscription,
+            "SELECT * FROM subscriptions WHERE id = ANY($1)",
+            ids
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
+    pub async fn fetch_all_by_subscription_ids(
+        &self,
+        subscription_ids: &[String],
+    ) -> Result<Vec<Subscription>, Error> {
+        query_as!(
+            Subscription,
+            "SELECT * FROM subscriptions WHERE subscription_id = ANY($1)",
+            subscription_ids
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
     pub async fn fetch_all(&self) -> Result<Vec<Subscription>, Error> {
         query_as!(Subscription, "SELECT * FROM subscriptions")
             .fetch_all(self.db_pool)
@@ -250,13 +325,55 @@ impl SubscriptionModel<'_> {
         .await
     }
 
+    pub async fn fetch_filtered(&self, filter: &RecordFilter) -> Result<Vec<Subscription>, Error> {
+        query_as!(
+            Subscription,
+            r#"
+            SELECT *
+            FROM subscriptions
+            WHERE ($1::TEXT IS NULL OR status = $1)
+            AND ($2::TIMESTAMPTZ IS NULL OR subscription_created >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR subscription_created < $3)
+            AND ($4::TEXT IS NULL OR plan_id = $4)
+            AND ($5::TEXT IS NULL OR country = $5)
+            AND ($6::TEXT IS NULL OR flow_id = $6)
+            AND ($7::TEXT IS NULL OR cj_event_value = $7)
+            AND ($8::TIMESTAMPTZ IS NULL OR (subscription_created, id) > ($8, $9::UUID))
+            ORDER BY subscription_created, id
+            LIMIT $10"#,
+            filter.status_string(),
+            filter.since,
+            filter.before,
+            filter.plan_id,
+            filter.country,
+            filter.flow_id,
+            filter.cj_event_value,
+            filter.after_t(),
+            filter.after_id(),
+            filter.page_size(),
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
     pub async fn update_sub_status(
         &self,
         id: &Uuid,
         new_status: Status,
+    ) -> Result<Subscription, Error> {
+        self.update_sub_status_with_reason(id, new_status, None)
+            .await
+    }
+
+    // The reason is kept with the status in status_history
+    pub async fn update_sub_status_with_reason(
+        &self,
+        id: &Uuid,
+        new_status: Status,
+        reason: Option<String>,
     ) -> Result<Subscription, Error> {
         let mut sub = self.fetch_one_by_id(id).await?;
-        sub.update_status(new_status);
+        sub.update_status_with_reason(new_status, reason);
         query_as!(
             Subscription,
             r#"UPDATE subscriptions
@@ -275,6 +392,82 @@ impl SubscriptionModel<'_> {
         .await
     }
 
+    pub async fn fetch_one_by_id_for_update(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        id: &Uuid,
+    ) -> Result<Subscription, Error> {
+        query_as!(
+            Subscription,
+            "SELECT * FROM subscriptions WHERE id = $1 FOR UPDATE",
+            id
+        )
+        .fetch_one(&mut *transaction)
+        .await
+    }
+
+    pub async fn update_status_in_transaction(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        sub: &Subscription,
+    ) -> Result<Subscription, Error> {
+        query_as!(
+            Subscription,
+            r#"UPDATE subscriptions
+            SET
+                status = $1,
+                status_t = $2,
+                status_history = $3
+            WHERE id = $4
+			RETURNING *"#,
+            sub.status,
+            sub.status_t,
+            sub.status_history,
+            sub.id,
+        )
+        .fetch_one(&mut *transaction)
+        .await
+    }
+
+    // Records that CJ didn't receive the subscription, puts it back to be reported again
+    // and counts the re-report, in one transaction so it can't be left CJNotReceived.
+    pub async fn re_report_sub(&self, id: &Uuid) -> Result<Subscription, Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        let mut sub = self
+            .fetch_one_by_id_for_update(&mut transaction, id)
+            .await?;
+        sub.update_status(Status::CJNotReceived);
+        sub.update_status(Status::NotReported);
+        let sub = query_as!(
+            Subscription,
+            r#"UPDATE subscriptions
+            SET
+                status = $1,
+                status_t = $2,
+                status_history = $3,
+                re_report_count = re_report_count + 1
+            WHERE id = $4
+			RETURNING *"#,
+            sub.status,
+            sub.status_t,
+            sub.status_history,
+            sub.id,
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(sub)
+    }
+
+    pub async fn count_by_status(&self) -> Result<Vec<StatusCount>, Error> {
+        query_as!(
+            StatusCount,
+            r#"SELECT status, COUNT(*) AS "count!" FROM subscriptions GROUP BY status ORDER BY status"#
+        )
+        .fetch_all(self.db_pool)
+        .await
+    }
+
     pub async fn get_reported_date_range(&self) -> Result<DateRange, Error> {
         let result = query!(
             "SELECT MIN(status_t), MAX(status_t) FROM subscriptions WHERE status = 'Reported' AND status_t IS NOT NULL",
@@ -310,7 +503,7 @@ pub mod test_subscriptions {
             plan_currency: random_currency_or_country(),
             plan_amount: random_price(),
             country: Some(random_currency_or_country()),
-            coupons: Some(random_ascii_string()),
+            coupons: vec![random_simple_ascii_string()],
             aic_id: Some(Uuid::new_v4()),
             aic_expires: Some(OffsetDateTime::now_utc()),
             cj_event_value: Some(random_ascii_string()),
@@ -331,7 +524,7 @@ pub mod test_subscriptions {
             plan_currency: random_simple_ascii_string(),
             plan_amount: 1,
             country: None,
-            coupons: None,
+            coupons: vec![],
             aic_id: None,
             aic_expires: None,
             cj_event_value: None,
@@ -350,4 +543,36 @@ pub mod test_subscriptions {
             now.unix_timestamp()
         );
     }
+
+    #[test]
+    fn items_are_the_line_items_or_the_plan() {
+        let mut sub = make_fake_sub();
+        sub.quantity = 2;
+        assert_eq!(
+            sub.items(),
+            vec![LineItem {
+                plan_id: sub.plan_id.clone(),
+                quantity: 2,
+                amount: sub.plan_amount,
+                discount: 0,
+            }]
+        );
+        let line_items = vec![
+            LineItem {
+                plan_id: "bundle".to_string(),
+                quantity: 1,
+                amount: 1000,
+                discount: 100,
+            },
+            LineItem {
+                plan_id: "add-on".to_string(),
+                quantity: 3,
+                amount: 250,
+                discount: 0,
+            },
+        ];
+        sub.set_line_items(&line_items);
+        assert_eq!(sub.line_items(), line_items);
+        assert_eq!(sub.items(), line_items);
+    }
 }
@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, PgPool};
+use strum_macros::Display as EnumToString;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use super::{
+    aic::AICModel,
+    refunds::{Refund, RefundModel},
+    status_history::{Status, StatusHistory, UpdateStatus},
+    status_overrides::StatusOverrideModel,
+    subscriptions::{Subscription, SubscriptionModel},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, EnumToString)]
+pub enum TimelineEventType {
+    // The aic table only keeps the latest cookie. created is reset when
+    // a new cj_event_value comes in, so this is the creation or the last update.
+    AICCreatedOrUpdated,
+    AICArchived,
+    SubscriptionCreated,
+    SubscriptionIngested,
+    SubscriptionStatusChanged,
+    SubscriptionVerified,
+    RefundCreated,
+    RefundIngested,
+    RefundStatusChanged,
+    RefundVerified,
+    CorrectionFile,
+    StatusOverridden,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    #[serde(with = "time::serde::timestamp")]
+    pub t: OffsetDateTime,
+    pub event: TimelineEventType,
+    // aic or subscription uuid, or refund_id
+    pub record_id: String,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Timeline {
+    pub flow_id: String,
+    pub events: Vec<TimelineEvent>,
+}
+
+pub enum TimelineLookup<'a> {
+    FlowId(&'a str),
+    SubscriptionId(&'a str),
+    AICId(&'a Uuid),
+}
+
+fn optional<T>(result: Result<T, Error>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(Error::RowNotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_verification(status: &Status) -> bool {
+    matches!(status, Status::CJReceived | Status::CJNotReceived)
+}
+
+// The first status history entry is written when the record is ingested, every later entry
+// is a transition.
+fn status_history_events(
+    status_history: Option<StatusHistory>,
+    record_id: &str,
+    ingested: TimelineEventType,
+    changed: TimelineEventType,
+    verified: TimelineEventType,
+) -> Vec<TimelineEvent> {
+    let entries = status_history.map(|h| h.entries).unwrap_or_default();
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let event = if i == 0 {
+                ingested.clone()
+            } else if is_verification(&entry.status) {
+                verified.clone()
+            } else {
+                changed.clone()
+            };
+            TimelineEvent {
+                t: entry.t,
+                event,
+                record_id: record_id.to_string(),
+                detail: match entry.reason {
+                    Some(reason) => format!("{}: {}", entry.status, reason),
+                    None => entry.status.to_string(),
+                },
+            }
+        })
+        .collect()
+}
+
+fn subscription_events(sub: &Subscription) -> Vec<TimelineEvent> {
+    let record_id = sub.id.to_string();
+    let mut events = vec![TimelineEvent {
+        t: sub.subscription_created,
+        event: TimelineEventType::SubscriptionCreated,
+        record_id: record_id.clone(),
+        detail: format!(
+            "subscription_id={} plan_id={} aic_id={}",
+            sub.subscription_id,
+            sub.plan_id,
+            sub.aic_id.map(|id| id.to_string()).unwrap_or_default()
+        ),
+    }];
+    events.extend(status_history_events(
+        sub.get_status_history(),
+        &record_id,
+        TimelineEventType::SubscriptionIngested,
+        TimelineEventType::SubscriptionStatusChanged,
+        TimelineEventType::SubscriptionVerified,
+    ));
+    events
+}
+
+fn refund_events(refund: &Refund) -> Vec<TimelineEvent> {
+    let mut events = vec![TimelineEvent {
+        t: refund.refund_created,
+        event: TimelineEventType::RefundCreated,
+        record_id: refund.refund_id.clone(),
+        detail: format!("refund_amount={}", refund.refund_amount),
+    }];
+    events.extend(status_history_events(
+        refund.get_status_history(),
+        &refund.refund_id,
+        TimelineEventType::RefundIngested,
+        TimelineEventType::RefundStatusChanged,
+        TimelineEventType::RefundVerified,
+    ));
+    if let Some(day) = refund.correction_file_date {
+        events.push(TimelineEvent {
+            t: day.midnight().assume_utc(),
+            event: TimelineEventType::CorrectionFile,
+            record_id: refund.refund_id.clone(),
+            detail: format!("/corrections/{}.csv", day.format("%F")),
+        });
+    }
+    events
+}
+
+pub struct TimelineModel<'a> {
+    pub db_pool: &'a PgPool,
+}
+
+impl TimelineModel<'_> {
+    async fn resolve_flow_id(&self, lookup: TimelineLookup<'_>) -> Result<String, Error> {
+        match lookup {
+            TimelineLookup::FlowId(flow_id) => Ok(flow_id.to_string()),
+            TimelineLookup::SubscriptionId(subscription_id) => {
+                let subscriptions = SubscriptionModel {
+                    db_pool: self.db_pool,
+                };
+                let sub = subscriptions
+                    .fetch_one_by_subscription_id(subscription_id)
+                    .await?;
+                Ok(sub.flow_id)
+            }
+            TimelineLookup::AICId(id) => {
+                let aics = AICModel {
+                    db_pool: self.db_pool,
+                };
+                let aic = match optional(aics.fetch_one_by_id(id).await)? {
+                    Some(aic) => aic,
+                    None => aics.fetch_one_by_id_from_archive(id).await?,
+                };
+                Ok(aic.flow_id)
+            }
+        }
+    }
+
+    // Returns RowNotFound if nothing is known about the flow.
+    pub async fn fetch(&self, lookup: TimelineLookup<'_>) -> Result<Timeline, Error> {
+        let flow_id = self.resolve_flow_id(lookup).await?;
+        let aics = AICModel {
+            db_pool: self.db_pool,
+        };
+        let subscriptions = SubscriptionModel {
+            db_pool: self.db_pool,
+        };
+        let refunds = RefundModel {
+            db_pool: self.db_pool,
+        };
+        let overrides = StatusOverrideModel {
+            db_pool: self.db_pool,
+        };
+
+        let live_aic = optional(aics.fetch_one_by_flow_id(&flow_id).await)?;
+        let archived_aic = optional(aics.fetch_one_by_flow_id_from_archive(&flow_id).await)?;
+        let sub = optional(subscriptions.fetch_one_by_flow_id(&flow_id).await)?;
+        if live_aic.is_none() && archived_aic.is_none() && sub.is_none() {
+            return Err(Error::RowNotFound);
+        }
+
+        let mut events = vec![];
+        for aic in live_aic.iter().chain(archived_aic.iter()) {
+            events.push(TimelineEvent {
+                t: aic.created,
+                event: TimelineEventType::AICCreatedOrUpdated,
+                record_id: aic.id.to_string(),
+                detail: format!("cj_event_value={}", aic.cj_event_value),
+            });
+        }
+        if let Some(aic) = &archived_aic {
+            if let Some(t) = aics.fetch_archived_t(&aic.id).await? {
+                events.push(TimelineEvent {
+                    t,
+                    event: TimelineEventType::AICArchived,
+                    record_id: aic.id.to_string(),
+                    detail: String::new(),
+                });
+            }
+        }
+        if let Some(sub) = &sub {
+            events.extend(subscription_events(sub));
+            let mut record_ids = vec![sub.id];
+            for refund in refunds
+                .fetch_all_by_subscription_id(&sub.subscription_id)
+                .await?
+            {
+                events.extend(refund_events(&refund));
+                record_ids.push(refund.id);
+            }
+            for record_id in record_ids {
+                for entry in overrides.fetch_all_by_record_id(&record_id).await? {
+                    events.push(TimelineEvent {
+                        t: entry.created,
+                        event: TimelineEventType::StatusOverridden,
+                        record_id: entry.record_id.to_string(),
+                        detail: format!(
+                            "{} -> {} by {}: {}",
+                            entry.from_status.unwrap_or_default(),
+                            entry.to_status,
+                            entry.operator,
+                            entry.reason
+                        ),
+                    });
+                }
+            }
+        }
+        // Stable, so events with the same timestamp keep the order they were added in
+        events.sort_by_key(|e| e.t);
+        Ok(Timeline { flow_id, events })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::status_history::StatusHistoryEntry;
+    use time::Duration;
+
+    #[test]
+    fn status_history_events_classifies_entries() {
+        let t = OffsetDateTime::now_utc();
+        let history = StatusHistory {
+            entries: vec![
+                StatusHistoryEntry {
+                    t,
+                    status: Status::NotReported,
+                    reason: None,
+                },
+                StatusHistoryEntry {
+                    t: t + Duration::hours(1),
+                    status: Status::Reported,
+                    reason: None,
+                },
+                StatusHistoryEntry {
+                    t: t + Duration::days(2),
+                    status: Status::CJReceived,
+                    reason: None,
+                },
+            ],
+        };
+        let events = status_history_events(
+            Some(history),
+            "a",
+            TimelineEventType::SubscriptionIngested,
+            TimelineEventType::SubscriptionStatusChanged,
+            TimelineEventType::SubscriptionVerified,
+        );
+        let types: Vec<TimelineEventType> = events.into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            types,
+            vec![
+                TimelineEventType::SubscriptionIngested,
+                TimelineEventType::SubscriptionStatusChanged,
+                TimelineEventType::SubscriptionVerified,
+            ]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, str::FromStr};
+
+// ISO 4217 currencies that don't have 2 digits after the decimal point
+const MINOR_UNIT_EXPONENTS: [(&str, u32); 26] = [
+    ("bhd", 3),
+    ("bif", 0),
+    ("clf", 4),
+    ("clp", 0),
+    ("djf", 0),
+    ("gnf", 0),
+    ("iqd", 3),
+    ("isk", 0),
+    ("jod", 3),
+    ("jpy", 0),
+    ("kmf", 0),
+    ("krw", 0),
+    ("kwd", 3),
+    ("lyd", 3),
+    ("omr", 3),
+    ("pyg", 0),
+    ("rwf", 0),
+    ("tnd", 3),
+    ("ugx", 0),
+    ("uyi", 0),
+    ("uyw", 4),
+    ("vnd", 0),
+    ("vuv", 0),
+    ("xaf", 0),
+    ("xof", 0),
+    ("xpf", 0),
+];
+
+// Digits after the decimal point, e.g. 2 for usd, 0 for jpy, 3 for kwd
+pub fn minor_unit_exponent(currency: &str) -> u32 {
+    let currency = currency.to_lowercase();
+    MINOR_UNIT_EXPONENTS
+        .iter()
+        .find(|(c, _)| *c == currency)
+        .map_or(2, |(_, exponent)| *exponent)
+}
+
+// An exact decimal, as CJ sends amounts, e.g. "59.88" is 5988 at scale 2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u32,
+}
+
+impl Decimal {
+    // Rounded half away from zero if there are more digits than exponent
+    pub fn to_minor_units(&self, exponent: u32) -> i64 {
+        if self.scale <= exponent {
+            return self.mantissa * 10i64.pow(exponent - self.scale);
+        }
+        let divisor = 10i64.pow(self.scale - exponent);
+        let (quotient, remainder) = (self.mantissa / divisor, self.mantissa % divisor);
+        match remainder.abs() * 2 >= divisor {
+            true => quotient + self.mantissa.signum(),
+            false => quotient,
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid decimal: {}", s);
+        let (negative, digits) = match s.trim().strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.trim()),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let mantissa: i64 = format!("{}{}", whole, fraction)
+            .parse()
+            .map_err(|_| invalid())?;
+        Ok(Decimal {
+            mantissa: if negative { -mantissa } else { mantissa },
+            scale: fraction.len() as u32,
+        })
+    }
+}
+
+// CJ sends amounts as strings
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+// An amount in the minor units of its currency, e.g. cents for usd and yen for jpy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Money {
+    pub minor_units: i64,
+    // Lowercase, like plan_currency
+    pub currency: String,
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: &str) -> Self {
+        Money {
+            minor_units,
+            currency: currency.to_lowercase(),
+        }
+    }
+
+    pub fn from_decimal(decimal: &Decimal, currency: &str) -> Self {
+        Money::new(
+            decimal.to_minor_units(minor_unit_exponent(currency)),
+            currency,
+        )
+    }
+
+    pub fn exponent(&self) -> u32 {
+        minor_unit_exponent(&self.currency)
+    }
+
+    // rate is the value of one unit of this currency in currency
+    pub fn convert(&self, rate: f64, currency: &str) -> Money {
+        let scale = 10f64.powi(minor_unit_exponent(currency) as i32 - self.exponent() as i32);
+        Money::new(
+            (self.minor_units as f64 * rate * scale).round() as i64,
+            currency,
+        )
+    }
+
+    // Exact, with as many decimal places as the currency has, e.g. 59.88, 500 or 1.234
+    pub fn to_decimal_string(&self) -> String {
+        let exponent = self.exponent();
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+        match exponent {
+            0 => format!("{}{}", sign, units),
+            _ => {
+                let divisor = 10u64.pow(exponent);
+                format!(
+                    "{}{}.{:0width$}",
+                    sign,
+                    units / divisor,
+                    units % divisor,
+                    width = exponent as usize
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.to_decimal_string(), self.currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn amounts_are_formatted_with_the_currency_exponent() {
+        assert_eq!(Money::new(5988, "usd").to_decimal_string(), "59.88");
+        assert_eq!(Money::new(1000, "USD").to_decimal_string(), "10.00");
+        assert_eq!(Money::new(5, "eur").to_decimal_string(), "0.05");
+        assert_eq!(Money::new(-999, "usd").to_decimal_string(), "-9.99");
+        assert_eq!(Money::new(500, "jpy").to_decimal_string(), "500");
+        assert_eq!(Money::new(1234, "kwd").to_decimal_string(), "1.234");
+        assert_eq!(Money::new(1234, "kwd").to_string(), "1.234 kwd");
+    }
+
+    #[test]
+    fn decimals_are_parsed_exactly() {
+        assert_eq!(
+            Money::from_decimal(&decimal("59.88"), "usd").minor_units,
+            5988
+        );
+        assert_eq!(
+            Money::from_decimal(&decimal("-9.99"), "usd").minor_units,
+            -999
+        );
+        assert_eq!(Money::from_decimal(&decimal("10"), "usd").minor_units, 1000);
+        assert_eq!(
+            Money::from_decimal(&decimal("500.00"), "jpy").minor_units,
+            500
+        );
+        assert_eq!(
+            Money::from_decimal(&decimal("1.234"), "kwd").minor_units,
+            1234
+        );
+        // Rounded half away from zero
+        assert_eq!(
+            Money::from_decimal(&decimal("0.125"), "usd").minor_units,
+            13
+        );
+        assert_eq!(
+            Money::from_decimal(&decimal("-0.125"), "usd").minor_units,
+            -13
+        );
+        assert_eq!(
+            Money::from_decimal(&decimal("0.124"), "usd").minor_units,
+            12
+        );
+        // Where f32 would lose the cent
+        assert_eq!(
+            Money::from_decimal(&decimal("167772.17"), "usd").minor_units,
+            16777217
+        );
+        for invalid in ["", "-", ".", "1.2.3", "1e3", "notgood", "+1"] {
+            assert!(invalid.parse::<Decimal>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn amounts_are_converted_between_exponents() {
+        assert_eq!(
+            Money::new(5000, "eur").convert(1.07, "usd"),
+            Money::new(5350, "usd")
+        );
+        assert_eq!(
+            Money::new(1000, "jpy").convert(0.0074, "usd"),
+            Money::new(740, "usd")
+        );
+        assert_eq!(
+            Money::new(1000, "usd").convert(0.307, "kwd"),
+            Money::new(3070, "kwd")
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use std::fmt::Debug;
+use time::OffsetDateTime;
+
+use crate::{
+    models::{refunds::Refund, subscriptions::Subscription},
+    money::Money,
+};
+
+// A line of a commission. Networks that don't break sales down have one for the sale.
+#[derive(Clone, Debug)]
+pub struct NetworkCommissionItem {
+    pub sku: String,
+    // None if the network didn't say, counted as one
+    pub quantity: Option<i32>,
+    // Taken off the line by the network, for the coupon on the conversion
+    pub discount: Option<Money>,
+}
+
+// A commission as a network reports it, for a conversion or a correction of one.
+// Amounts are in the network's publisher currency.
+#[derive(Clone, Debug)]
+pub struct NetworkCommission {
+    pub id: String,
+    // The id we reported the conversion with, the subscription's id
+    pub order_id: String,
+    pub posting_date: OffsetDateTime,
+    // False for a correction
+    pub original: bool,
+    pub correction_reason: Option<String>,
+    pub sale_amount: Money,
+    pub commission_amount: Option<Money>,
+    // Where the commission is in the network's process, e.g. CJ's action status
+    pub status: Option<String>,
+    pub validation_status: Option<String>,
+    pub publisher_id: Option<String>,
+    pub items: Vec<NetworkCommissionItem>,
+}
+
+// An affiliate network the jobs report conversions and corrections to, then check
+// commissions in. CJClient is the CJ one.
+//
+// A network has one or more accounts, e.g. CJ programs, each queried separately.
+// Networks turn their commissions into NetworkCommissions, so verify_reports matches and
+// keeps every network's the same way.
+#[async_trait]
+pub trait AffiliateNetwork: Sync {
+    // Logged when a request fails
+    type Error: Debug + Send;
+
+    fn accounts(&self) -> Vec<&str>;
+
+    // The click id sub is reported with. The AIC endpoint only stores CJ's cjevent, which
+    // ends up in the subscription's cj_event_value, so that's the only value there is to
+    // return. A network with its own click id would need somewhere to keep it first.
+    fn click_id<'a>(&self, sub: &'a Subscription) -> Option<&'a str>;
+
+    // The account a subscription to plan_id is reported to and checked in
+    fn account_for_plan(&self, plan_id: &str) -> &str;
+
+    async fn report_conversion(&self, sub: &Subscription) -> Result<(), Self::Error>;
+
+    // What report_conversion would send for sub, logged by dry runs. Leaves out secrets.
+    fn describe_conversion(&self, sub: &Subscription) -> String;
+
+    // Called by batch_refunds for each refund that's reported. Networks that collect
+    // corrections themselves, like CJ with the corrections file, needn't send anything.
+    async fn submit_correction(&self, refund: &Refund) -> Result<(), Self::Error>;
+
+    // The account's commissions posted between min and max, corrections included
+    async fn query_commissions(
+        &self,
+        account: &str,
+        min: OffsetDateTime,
+        max: OffsetDateTime,
+    ) -> Result<Vec<NetworkCommission>, Self::Error>;
+}
@@ -0,0 +1,91 @@
+use serde::Deserialize;
+
+// A Stripe plan, as CJ knows it
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Plan {
+    pub plan_id: String,
+    // Sent to CJ as ITEMn. Optional, defaults to plan_id.
+    #[serde(default)]
+    pub cj_sku: Option<String>,
+    #[serde(default)]
+    pub product_name: Option<String>,
+    // Whether subscriptions to the plan earn commission. Optional, defaults to true.
+    #[serde(default = "default_eligible")]
+    pub eligible: bool,
+    // Logged when a subscription isn't reported, e.g. internal, bundle or partner. Optional.
+    #[serde(default)]
+    pub ineligible_reason: Option<String>,
+    // The name of the CJ program subscriptions to the plan are reported to. Optional,
+    // see cj::programs.
+    #[serde(default)]
+    pub cj_program: Option<String>,
+}
+
+fn default_eligible() -> bool {
+    true
+}
+
+// The plans setting. A list rather than a map, as settings keys are lowercased and
+// plan ids aren't. Plans that aren't in it are eligible and sent as their plan id.
+#[derive(Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct PlanCatalog(pub Vec<Plan>);
+
+impl PlanCatalog {
+    pub fn get(&self, plan_id: &str) -> Option<&Plan> {
+        self.0.iter().find(|p| p.plan_id == plan_id)
+    }
+
+    pub fn cj_sku<'a>(&'a self, plan_id: &'a str) -> &'a str {
+        self.get(plan_id)
+            .and_then(|p| p.cj_sku.as_deref())
+            .unwrap_or(plan_id)
+    }
+
+    // Why subscriptions to the plan aren't reported, None if they are
+    pub fn ineligible_reason(&self, plan_id: &str) -> Option<String> {
+        match self.get(plan_id) {
+            Some(plan) if !plan.eligible => Some(
+                plan.ineligible_reason
+                    .clone()
+                    .unwrap_or_else(|| "Plan is not eligible for commission".to_string()),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_not_in_the_catalog_are_eligible_and_sent_as_is() {
+        let plans: PlanCatalog = serde_yaml::from_str(
+            "
+- plan_id: price_Monthly
+  cj_sku: vpn-monthly
+  product_name: VPN monthly
+- plan_id: price_internal
+  eligible: false
+  ineligible_reason: internal
+- plan_id: price_bundle
+  eligible: false
+",
+        )
+        .unwrap();
+        assert_eq!(plans.cj_sku("price_Monthly"), "vpn-monthly");
+        assert_eq!(plans.cj_sku("price_internal"), "price_internal");
+        assert_eq!(plans.cj_sku("price_other"), "price_other");
+        assert_eq!(plans.ineligible_reason("price_Monthly"), None);
+        assert_eq!(plans.ineligible_reason("price_other"), None);
+        assert_eq!(
+            plans.ineligible_reason("price_internal"),
+            Some("internal".to_string())
+        );
+        assert_eq!(
+            plans.ineligible_reason("price_bundle"),
+            Some("Plan is not eligible for commission".to_string())
+        );
+    }
+}
@@ -21,15 +21,32 @@ use crate::version::{read_version, VERSION_FILE};
 #[derive(Debug, EnumToString, EnumString, PartialEq, Eq, Clone, Copy)]
 #[strum(serialize_all = "kebab_case")]
 pub enum LogKey {
+    AdminAicsAccessed,
+    AdminQueryFailed,
+    AdminQueryInvalid,
+    AdminReconciliationAccessed,
+    AdminRefundsAccessed,
+    AdminStatusOverride,
+    AdminStatusOverrideFailed,
+    AdminStatusOverrideNotFound,
+    AdminSubscriptionsAccessed,
+    AdminTimelineAccessed,
     AicRecordCreate,
     AicRecordCreateFailed,
     AicRecordUpdate,
     AicRecordUpdateFailed,
     AicRecordUpdateFailedNotFound,
     BatchRefunds,
+    BatchRefundsDryRun,
     BatchRefundsEnding,
+    BatchRefundsLocked,
     BatchRefundsNNotReported,
+    BatchRefundsOutcomeFailed,
+    BatchRefundsOutcomeProcessed,
+    BatchRefundsOutcomeSkipped,
+    BatchRefundsOutcomeSucceeded,
     BatchRefundsStarting,
+    BatchRefundsSubmitCorrectionFailed,
     BatchRefundsTimer,
     BatchRefundsUpdate,
     BatchRefundsUpdateFailed,
@@ -38,8 +55,14 @@ pub enum LogKey {
     CheckRefundsBytesFromBq,
     CheckRefundsDeserializeBigQuery,
     CheckRefundsDeserializeBigQueryFailed,
+    CheckRefundsDryRun,
     CheckRefundsEnding,
+    CheckRefundsLocked,
     CheckRefundsNFromBq,
+    CheckRefundsOutcomeFailed,
+    CheckRefundsOutcomeProcessed,
+    CheckRefundsOutcomeSkipped,
+    CheckRefundsOutcomeSucceeded,
     CheckRefundsRefundCreate,
     CheckRefundsRefundCreateDatabaseError,
     CheckRefundsRefundCreateDuplicateKeyViolation,
@@ -62,8 +85,14 @@ pub enum LogKey {
     CheckSubscriptionsBytesFromBq,
     CheckSubscriptionsDeserializeBigQuery,
     CheckSubscriptionsDeserializeBigQueryFailed,
+    CheckSubscriptionsDryRun,
     CheckSubscriptionsEnding,
+    CheckSubscriptionsLocked,
     CheckSubscriptionsNFromBq,
+    CheckSubscriptionsOutcomeFailed,
+    CheckSubscriptionsOutcomeProcessed,
+    CheckSubscriptionsOutcomeSkipped,
+    CheckSubscriptionsOutcomeSucceeded,
     CheckSubscriptionsStarting,
     CheckSubscriptionsSubscriptionCreate,
     CheckSubscriptionsSubscriptionCreateDatabaseError,
@@ -71,17 +100,70 @@ pub enum LogKey {
     CheckSubscriptionsSubscriptionCreateFailed,
     CheckSubscriptionsTimer,
     CheckSubscriptionsTotalNFromBq,
+    Cjmsctl,
+    CjmsctlAicArchived,
+    CjmsctlAicUnarchived,
+    CjmsctlEnding,
+    CjmsctlFailed,
+    CjmsctlRequeueFailed,
+    CjmsctlStarting,
+    CjmsctlStatusOverride,
+    CjmsctlTimer,
     Cleanup,
     CleanupAicArchive,
     CleanupAicArchiveFailed,
+    CleanupDryRun,
     CleanupEnding,
+    CleanupLocked,
+    CleanupOutcomeFailed,
+    CleanupOutcomeProcessed,
+    CleanupOutcomeSkipped,
+    CleanupOutcomeSucceeded,
     CleanupStarting,
     CleanupTimer,
+    CjRequestRetry,
     CorrectionsReport,
     CorrectionsReportByDayAccessed,
+    CorrectionsReportFailed,
     CorrectionsReportTodayAccessed,
     CorrectionsSubscriptionFetch,
     CorrectionsSubscriptionFetchFailed,
+    ExitOnError,
+    JobFailed,
+    JobFailureThresholdExceeded,
+    JobLockFailed,
+    JobPanicked,
+    JobRunRecordFailed,
+    JobsStatusAccessed,
+    JobsStatusQueryFailed,
+    LoadExchangeRates,
+    LoadExchangeRatesDryRun,
+    LoadExchangeRatesEnding,
+    LoadExchangeRatesLocked,
+    LoadExchangeRatesNoSource,
+    LoadExchangeRatesOutcomeFailed,
+    LoadExchangeRatesOutcomeProcessed,
+    LoadExchangeRatesOutcomeSkipped,
+    LoadExchangeRatesOutcomeSucceeded,
+    LoadExchangeRatesParseFailed,
+    LoadExchangeRatesSaveFailed,
+    LoadExchangeRatesSaved,
+    LoadExchangeRatesStarting,
+    LoadExchangeRatesTimer,
+    Reconcile,
+    ReconcileAmbiguous,
+    ReconcileDuplicate,
+    ReconcileEnding,
+    ReconcileLocked,
+    ReconcileMismatch,
+    ReconcileOrphan,
+    ReconcileOutcomeFailed,
+    ReconcileOutcomeProcessed,
+    ReconcileOutcomeSkipped,
+    ReconcileOutcomeSucceeded,
+    ReconcileStale,
+    ReconcileStarting,
+    ReconcileTimer,
     ReportSubscriptionMarkNotReported,
     ReportSubscriptionMarkNotReportedFailed,
     ReportSubscriptionMarkWillNotReport,
@@ -91,8 +173,17 @@ pub enum LogKey {
     ReportSubscriptionReportToCjFailed,
     ReportSubscriptions,
     ReportSubscriptionsAicExpiredBeforeSubscriptionCreated,
+    ReportSubscriptionsCouponExcluded,
+    ReportSubscriptionsCouponRulesConflict,
+    ReportSubscriptionsDryRun,
     ReportSubscriptionsEnding,
+    ReportSubscriptionsLocked,
     ReportSubscriptionsNNotReported,
+    ReportSubscriptionsOutcomeFailed,
+    ReportSubscriptionsOutcomeProcessed,
+    ReportSubscriptionsOutcomeSkipped,
+    ReportSubscriptionsOutcomeSucceeded,
+    ReportSubscriptionsPlanNotEligible,
     ReportSubscriptionsStarting,
     ReportSubscriptionsSubscriptionHasNoAicExpiry,
     ReportSubscriptionsTimer,
@@ -100,9 +191,21 @@ pub enum LogKey {
     StatsDError,
     StatusHistoryDeserializeError,
     VerifyReports,
+    VerifyReportsCommissionSaveFailed,
+    VerifyReportsCommissionsSaved,
     VerifyReportsCount,
+    VerifyReportsDryRun,
+    VerifyReportsExchangeRateFailed,
+    VerifyReportsExchangeRateMissing,
+    VerifyReportsExchangeRateStale,
+    VerifyReportsLocked,
     VerifyReportsNoCount,
+    VerifyReportsOutcomeFailed,
+    VerifyReportsOutcomeProcessed,
+    VerifyReportsOutcomeSkipped,
+    VerifyReportsOutcomeSucceeded,
     VerifyReportsQuery,
+    VerifyReportsQueryFailed,
     VerifyReportsRefundFound,
     VerifyReportsRefundNotFound,
     VerifyReportsRefundMatched,
@@ -113,6 +216,7 @@ pub enum LogKey {
     VerifyRefundsSubscriptionMissingFromDatabase,
     VerifyReportsSubscriptionNotFound,
     VerifyReportsSubscriptionNotMatched,
+    VerifyReportsSubscriptionReReport,
     VerifyReportsSubscriptionFound,
     VerifyReportsSubscriptionUpdateFailed,
     VerifyReportsSubscriptionUpdated,
@@ -121,6 +225,13 @@ pub enum LogKey {
     WebAppEnding,
     WebAppStarting,
     WebAppTimer,
+    Worker,
+    WorkerEnding,
+    WorkerJobScheduled,
+    WorkerNothingScheduled,
+    WorkerShutdownSignal,
+    WorkerStarting,
+    WorkerTimer,
 
     // For test cases
     Test,
@@ -290,7 +401,7 @@ macro_rules! error {
 #[macro_export]
 macro_rules! info_and_incr {
     ( $statsd_client:expr, $trace_type:expr, $($arg:tt)+ ) => {
-        crate::info!($trace_type.to_string().as_str(), $($arg)*);
+        $crate::info!($trace_type.to_string().as_str(), $($arg)*);
         $statsd_client.incr(&$trace_type);
     }
 }
@@ -327,7 +438,7 @@ macro_rules! info_and_incr {
 #[macro_export]
 macro_rules! error_and_incr {
     ( $statsd_client:expr, $trace_type:expr, $($arg:tt)+ ) => {
-        crate::error!($trace_type.to_string().as_str(), $($arg)*);
+        $crate::error!($trace_type.to_string().as_str(), $($arg)*);
         $statsd_client.incr(&$trace_type);
     }
 }
@@ -0,0 +1,259 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use cron::Schedule;
+use strum_macros::{Display as EnumToString, EnumString};
+use time::OffsetDateTime;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{
+    appconfig::CJ,
+    error::CjmsError,
+    error_and_incr, info, info_and_incr,
+    jobs::{
+        batch_refunds::batch_refunds_by_day, check_refunds::fetch_and_process_refunds,
+        check_subscriptions::fetch_and_process_new_subscriptions, cleanup::archive_expired_aics,
+        load_exchange_rates::load_exchange_rates, outcome::JobOutcome, reconcile::reconcile,
+        report_subscriptions::report_subscriptions, verify_reports::verify_reports,
+    },
+    telemetry::LogKey,
+};
+
+// Arguments shared by the standalone job binaries
+#[derive(Parser)]
+pub struct JobArgs {
+    /// Log what the job would do without writing anything or reporting to CJ
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+// Names match the standalone job binaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumToString, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Job {
+    CheckSubscriptions,
+    ReportSubscriptions,
+    CheckRefunds,
+    BatchRefunds,
+    VerifyReports,
+    Cleanup,
+    Reconcile,
+    LoadExchangeRates,
+}
+
+impl Job {
+    pub const ALL: [Job; 8] = [
+        Job::CheckSubscriptions,
+        Job::ReportSubscriptions,
+        Job::CheckRefunds,
+        Job::BatchRefunds,
+        Job::VerifyReports,
+        Job::Cleanup,
+        Job::Reconcile,
+        Job::LoadExchangeRates,
+    ];
+
+    pub fn log_key(&self) -> LogKey {
+        match self {
+            Job::CheckSubscriptions => LogKey::CheckSubscriptions,
+            Job::ReportSubscriptions => LogKey::ReportSubscriptions,
+            Job::CheckRefunds => LogKey::CheckRefunds,
+            Job::BatchRefunds => LogKey::BatchRefunds,
+            Job::VerifyReports => LogKey::VerifyReports,
+            Job::Cleanup => LogKey::Cleanup,
+            Job::Reconcile => LogKey::Reconcile,
+            Job::LoadExchangeRates => LogKey::LoadExchangeRates,
+        }
+    }
+
+    // Held while the job runs, see CJ::run_job
+    pub fn lock_name(&self) -> String {
+        format!("cjms-job-{}", self)
+    }
+
+    async fn work(&self, cj: &CJ, dry_run: bool) -> Result<JobOutcome, CjmsError> {
+        match self {
+            Job::CheckSubscriptions => {
+                fetch_and_process_new_subscriptions(&cj.bq_client, &cj.db_pool, &cj.statsd, dry_run)
+                    .await
+            }
+            Job::ReportSubscriptions => {
+                report_subscriptions(
+                    &cj.db_pool,
+                    &cj.cj_client,
+                    &cj.settings,
+                    &cj.statsd,
+                    dry_run,
+                )
+                .await
+            }
+            Job::CheckRefunds => {
+                fetch_and_process_refunds(&cj.bq_client, &cj.db_pool, &cj.statsd, dry_run).await
+            }
+            Job::BatchRefunds => {
+                batch_refunds_by_day(&cj.db_pool, &cj.cj_client, &cj.statsd, dry_run).await
+            }
+            Job::VerifyReports => {
+                verify_reports(
+                    &cj.db_pool,
+                    &cj.cj_client,
+                    &cj.settings,
+                    &cj.statsd,
+                    dry_run,
+                )
+                .await
+            }
+            Job::Cleanup => archive_expired_aics(&cj.db_pool, &cj.statsd, dry_run).await,
+            // Only reads, so dry runs are the same as runs
+            Job::Reconcile => reconcile(&cj.db_pool, &cj.settings, &cj.statsd).await,
+            Job::LoadExchangeRates => {
+                load_exchange_rates(&cj.db_pool, &cj.settings, &cj.statsd, dry_run).await
+            }
+        }
+    }
+
+    // Emits the same starting, ending and timer metrics as the standalone binaries.
+    // Errors if the job errored, panicked or failed, see CJ::run_job.
+    pub async fn run(&self, cj: &CJ, dry_run: bool) -> Result<JobOutcome, CjmsError> {
+        let key = self.log_key();
+        let start = OffsetDateTime::now_utc();
+        info!(&key.add_suffix("starting"), "Job starting");
+        cj.statsd.incr(&key.add_suffix("starting"));
+        let result = cj.run_job(*self, dry_run, self.work(cj, dry_run)).await;
+        info!(&key.add_suffix("ending"), "Job ending");
+        cj.statsd.incr(&key.add_suffix("ending"));
+        cj.statsd
+            .time(&key.add_suffix("timer"), OffsetDateTime::now_utc() - start);
+        result
+    }
+}
+
+pub struct ScheduledJob {
+    pub job: Job,
+    pub schedule: Schedule,
+    pub next: Option<DateTime<Utc>>,
+}
+
+pub fn parse_schedules(
+    schedules: &BTreeMap<String, String>,
+    now: DateTime<Utc>,
+) -> Result<Vec<ScheduledJob>, String> {
+    schedules
+        .iter()
+        .map(|(name, expression)| {
+            let job = Job::from_str(name).map_err(|_| format!("Unknown job: {}", name))?;
+            let schedule = Schedule::from_str(expression)
+                .map_err(|e| format!("Invalid schedule for {}: {}", name, e))?;
+            let next = schedule.after(&now).next();
+            Ok(ScheduledJob {
+                job,
+                schedule,
+                next,
+            })
+        })
+        .collect()
+}
+
+// Index of the job that is due first. Ties go to the job listed first.
+pub fn next_due(scheduled: &[ScheduledJob]) -> Option<usize> {
+    scheduled
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.next.map(|next| (i, next)))
+        .min_by_key(|(_, next)| *next)
+        .map(|(i, _)| i)
+}
+
+// Runs scheduled jobs one at a time until SIGTERM or SIGINT. A job that is running when
+// the signal arrives is allowed to finish. Runs that were missed while another job was
+// running happen as soon as it finishes, but only once.
+pub async fn run_scheduler(cj: &CJ, mut scheduled: Vec<ScheduledJob>) -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    for s in &scheduled {
+        info!(
+            LogKey::WorkerJobScheduled,
+            job = s.job.to_string().as_str(),
+            next = format!("{:?}", s.next).as_str(),
+            "Job scheduled"
+        );
+    }
+    loop {
+        let i = match next_due(&scheduled) {
+            Some(i) => i,
+            None => {
+                error_and_incr!(
+                    cj.statsd,
+                    LogKey::WorkerNothingScheduled,
+                    "No upcoming jobs. Stopping."
+                );
+                return Ok(());
+            }
+        };
+        // Intentional unwrap, next_due only returns jobs with a next run
+        let next = scheduled[i].next.unwrap();
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = sigterm.recv() => break,
+            _ = sigint.recv() => break,
+            _ = tokio::time::sleep(wait) => {
+                let s = &mut scheduled[i];
+                // A failed or panicking job is logged and recorded in job_runs by run_job, and
+                // runs again on its next schedule
+                s.job.run(cj, false).await.ok();
+                s.next = s.schedule.after(&Utc::now()).next();
+            }
+        }
+    }
+    info_and_incr!(
+        cj.statsd,
+        LogKey::WorkerShutdownSignal,
+        "Shutdown signal received. Stopping."
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn job_names_match_binaries() {
+        assert_eq!(
+            Job::from_str("check_subscriptions").unwrap(),
+            Job::CheckSubscriptions
+        );
+        assert_eq!(Job::ReportSubscriptions.to_string(), "report_subscriptions");
+        assert!(Job::from_str("check-subscriptions").is_err());
+    }
+
+    #[test]
+    fn parse_schedules_rejects_unknown_jobs_and_bad_expressions() {
+        let now = Utc::now();
+        let mut schedules = BTreeMap::new();
+        schedules.insert("nope".to_string(), "0 * * * * *".to_string());
+        assert!(parse_schedules(&schedules, now).is_err());
+        let mut schedules = BTreeMap::new();
+        schedules.insert("cleanup".to_string(), "every minute".to_string());
+        assert!(parse_schedules(&schedules, now).is_err());
+    }
+
+    #[test]
+    fn next_due_picks_the_earliest_job() {
+        let now = Utc.with_ymd_and_hms(2022, 6, 1, 12, 0, 30).unwrap();
+        let mut schedules = BTreeMap::new();
+        // Every hour on the hour
+        schedules.insert("cleanup".to_string(), "0 0 * * * *".to_string());
+        // Every 10 minutes
+        schedules.insert("check_refunds".to_string(), "0 */10 * * * *".to_string());
+        let scheduled = parse_schedules(&schedules, now).unwrap();
+        let i = next_due(&scheduled).unwrap();
+        assert_eq!(scheduled[i].job, Job::CheckRefunds);
+        assert_eq!(
+            scheduled[i].next,
+            Some(Utc.with_ymd_and_hms(2022, 6, 1, 12, 10, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use lib::{
+    controllers::admin::{AICRecordResponse, Page, RefundResponse, SubscriptionResponse},
+    models::{
+        aic::AICModel,
+        commissions::CommissionModel,
+        refunds::RefundModel,
+        status_history::{Status, UpdateStatus},
+        status_overrides::StatusOverrideModel,
+        subscriptions::SubscriptionModel,
+        timeline::{Timeline, TimelineEventType},
+    },
+};
+use reqwest::Response;
+use serde_json::{json, Value};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        aic::make_fake_aic,
+        commissions::make_fake_commission,
+        refunds::{make_fake_refund, save_refund},
+        subscriptions::{make_fake_sub, save_sub},
+    },
+    utils::{spawn_app, TestApp},
+};
+
+async fn get_admin(app: &TestApp, path: &str) -> Response {
+    reqwest::Client::new()
+        .get(app.build_url(path))
+        .basic_auth("user", Some(&app.settings.authentication))
+        .send()
+        .await
+        .expect("Failed to GET")
+}
+
+async fn post_admin(app: &TestApp, path: &str, body: &Value) -> Response {
+    reqwest::Client::new()
+        .post(app.build_url(path))
+        .basic_auth("user", Some(&app.settings.authentication))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to POST")
+}
+
+#[tokio::test]
+async fn test_admin_requires_auth() {
+    let app = spawn_app().await;
+    for path in [
+        "/admin/subscriptions",
+        "/admin/refunds",
+        "/admin/aics",
+        "/admin/reconciliation",
+    ] {
+        let r = reqwest::get(app.build_url(path)).await.unwrap();
+        assert_eq!(r.status(), 401, "Failed on path: {}", path);
+        let r = get_admin(&app, path).await;
+        assert_eq!(r.status(), 200, "Failed on path: {}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_admin_subscriptions_filters_and_paginates() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let model = SubscriptionModel { db_pool: &db_pool };
+    let mut reported = vec![];
+    for _ in 0..3 {
+        let mut sub = make_fake_sub();
+        sub.plan_id = "plan_a".to_string();
+        sub.update_status(Status::Reported);
+        save_sub(&model, &sub).await;
+        reported.push(sub);
+    }
+    let mut other_plan = make_fake_sub();
+    other_plan.update_status(Status::Reported);
+    save_sub(&model, &other_plan).await;
+    let not_reported = make_fake_sub();
+    save_sub(&model, &not_reported).await;
+
+    // Filter by status and plan
+    let r = get_admin(&app, "/admin/subscriptions?status=Reported&plan_id=plan_a").await;
+    assert_eq!(r.status(), 200);
+    let page: Page<SubscriptionResponse> = r.json().await.unwrap();
+    assert_eq!(page.records.len(), 3);
+    assert!(page.next_cursor.is_none());
+    for record in &page.records {
+        assert_eq!(record.status, Some(Status::Reported));
+        assert_eq!(record.status_history.len(), 2);
+        assert_eq!(record.status_history[0].status, Status::NotReported);
+        assert_eq!(record.status_history[1].status, Status::Reported);
+    }
+
+    // Filter by flow_id
+    let path = format!("/admin/subscriptions?flow_id={}", not_reported.flow_id);
+    let page: Page<SubscriptionResponse> = get_admin(&app, &path).await.json().await.unwrap();
+    assert_eq!(page.records.len(), 1);
+    assert_eq!(page.records[0].id, not_reported.id);
+
+    // Walk the pages one at a time
+    let mut seen = vec![];
+    let mut path = "/admin/subscriptions?status=Reported&plan_id=plan_a&limit=2".to_string();
+    loop {
+        let page: Page<SubscriptionResponse> = get_admin(&app, &path).await.json().await.unwrap();
+        seen.extend(page.records.into_iter().map(|r| r.id));
+        match page.next_cursor {
+            Some(cursor) => {
+                path = format!(
+                    "/admin/subscriptions?status=Reported&plan_id=plan_a&limit=2&cursor={}",
+                    cursor
+                );
+            }
+            None => break,
+        }
+    }
+    assert_eq!(seen.len(), 3);
+    for sub in &reported {
+        assert!(seen.contains(&sub.id));
+    }
+}
+
+#[tokio::test]
+async fn test_admin_rejects_invalid_filters() {
+    let app = spawn_app().await;
+    for path in [
+        "/admin/subscriptions?status=Nope",
+        "/admin/refunds?since=yesterday",
+        "/admin/aics?cursor=abc",
+        "/admin/aics?cursor=170141183460469231731687303715884105727_00000000-0000-0000-0000-000000000000",
+    ] {
+        let r = get_admin(&app, path).await;
+        assert_eq!(r.status(), 400, "Failed on path: {}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_admin_refunds_filter_by_subscription_fields() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let refund_model = RefundModel { db_pool: &db_pool };
+    let mut sub = make_fake_sub();
+    sub.country = Some("ca".to_string());
+    save_sub(&sub_model, &sub).await;
+    let mut refund = make_fake_refund();
+    refund.subscription_id = sub.subscription_id.clone();
+    save_refund(&refund_model, &refund).await;
+    save_refund(&refund_model, &make_fake_refund()).await;
+
+    let page: Page<RefundResponse> = get_admin(&app, "/admin/refunds?country=ca")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(page.records.len(), 1);
+    assert_eq!(page.records[0].refund_id, refund.refund_id);
+    assert_eq!(page.records[0].status_history.len(), 1);
+}
+
+#[tokio::test]
+async fn test_admin_aics_live_and_archived() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let model = AICModel { db_pool: &db_pool };
+    let live = make_fake_aic();
+    let archived = make_fake_aic();
+    model.create_from_aic(&live).await.unwrap();
+    model.create_archive_from_aic(&archived).await.unwrap();
+
+    let page: Page<AICRecordResponse> = get_admin(&app, "/admin/aics").await.json().await.unwrap();
+    assert_eq!(page.records.len(), 1);
+    assert_eq!(page.records[0].id, live.id);
+
+    let page: Page<AICRecordResponse> = get_admin(&app, "/admin/aics?archived=true")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(page.records.len(), 1);
+    assert_eq!(page.records[0].id, archived.id);
+}
+
+#[tokio::test]
+async fn test_admin_override_subscription_status() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let model = SubscriptionModel { db_pool: &db_pool };
+    let sub = make_fake_sub();
+    save_sub(&model, &sub).await;
+    let path = format!("/admin/subscriptions/{}/status", sub.id);
+    let body = json!({"status": "WillNotReport", "reason": "Test purchase", "operator": "ops"});
+
+    let r = reqwest::Client::new()
+        .post(app.build_url(&path))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(r.status(), 401);
+
+    let r = post_admin(&app, &path, &body).await;
+    assert_eq!(r.status(), 200);
+    let updated: SubscriptionResponse = r.json().await.unwrap();
+    assert_eq!(updated.status, Some(Status::WillNotReport));
+    let entries = StatusOverrideModel { db_pool: &db_pool }
+        .fetch_all_by_record_id(&sub.id)
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].reason, "Test purchase");
+}
+
+#[tokio::test]
+async fn test_admin_override_refund_status() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let refund = make_fake_refund();
+    save_refund(&RefundModel { db_pool: &db_pool }, &refund).await;
+    let path = format!("/admin/refunds/{}/status", refund.refund_id);
+    let body = json!({"status": "Reported", "reason": "Sent by hand", "operator": "ops"});
+    let r = post_admin(&app, &path, &body).await;
+    assert_eq!(r.status(), 200);
+    let updated: RefundResponse = r.json().await.unwrap();
+    assert_eq!(updated.status, Some(Status::Reported));
+}
+
+#[tokio::test]
+async fn test_admin_override_errors() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let sub = make_fake_sub();
+    save_sub(&SubscriptionModel { db_pool: &db_pool }, &sub).await;
+    let path = format!("/admin/subscriptions/{}/status", sub.id);
+
+    // Missing reason
+    let body = json!({"status": "Reported", "reason": " ", "operator": "ops"});
+    assert_eq!(post_admin(&app, &path, &body).await.status(), 400);
+    // Missing operator
+    let body = json!({"status": "Reported", "reason": "Because", "operator": ""});
+    assert_eq!(post_admin(&app, &path, &body).await.status(), 400);
+    // Unknown record
+    let body = json!({"status": "Reported", "reason": "Because", "operator": "ops"});
+    let r = post_admin(&app, "/admin/refunds/not-a-refund/status", &body).await;
+    assert_eq!(r.status(), 404);
+}
+
+#[tokio::test]
+async fn test_admin_timeline() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let aic_model = AICModel { db_pool: &db_pool };
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let refund_model = RefundModel { db_pool: &db_pool };
+
+    let mut aic = make_fake_aic();
+    // Keep flow_id url safe
+    aic.flow_id = Uuid::new_v4().to_string();
+    aic.created = OffsetDateTime::now_utc() - Duration::days(3);
+    aic_model.create_from_aic(&aic).await.unwrap();
+    aic_model.archive_aic(&aic).await.unwrap();
+    let mut sub = make_fake_sub();
+    sub.flow_id = aic.flow_id.clone();
+    sub.aic_id = Some(aic.id);
+    sub.subscription_created = OffsetDateTime::now_utc() - Duration::days(2);
+    save_sub(&sub_model, &sub).await;
+    sub_model
+        .update_sub_status(&sub.id, Status::Reported)
+        .await
+        .unwrap();
+    let mut refund = make_fake_refund();
+    refund.subscription_id = sub.subscription_id.clone();
+    refund.refund_created = OffsetDateTime::now_utc() - Duration::days(1);
+    save_refund(&refund_model, &refund).await;
+    StatusOverrideModel { db_pool: &db_pool }
+        .override_subscription_status(&sub.id, Status::CJReceived, "Checked with CJ", "ops")
+        .await
+        .unwrap();
+
+    for path in [
+        format!("/admin/timeline?flow_id={}", sub.flow_id),
+        format!("/admin/timeline?subscription_id={}", sub.subscription_id),
+        format!("/admin/timeline?aic_id={}", aic.id),
+    ] {
+        let r = get_admin(&app, &path).await;
+        assert_eq!(r.status(), 200, "Failed on path: {}", path);
+        let timeline: Timeline = r.json().await.unwrap();
+        assert_eq!(timeline.flow_id, sub.flow_id);
+        let events: Vec<TimelineEventType> = timeline.events.into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                TimelineEventType::AICCreatedOrUpdated,
+                TimelineEventType::SubscriptionCreated,
+                TimelineEventType::RefundCreated,
+                TimelineEventType::AICArchived,
+                TimelineEventType::SubscriptionIngested,
+                TimelineEventType::SubscriptionStatusChanged,
+                TimelineEventType::RefundIngested,
+                TimelineEventType::SubscriptionVerified,
+                TimelineEventType::StatusOverridden,
+            ],
+            "Failed on path: {}",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_admin_timeline_errors() {
+    let app = spawn_app().await;
+    for path in [
+        "/admin/timeline",
+        "/admin/timeline?flow_id=a&aic_id=b",
+        "/admin/timeline?aic_id=not-a-uuid",
+    ] {
+        let r = get_admin(&app, path).await;
+        assert_eq!(r.status(), 400, "Failed on path: {}", path);
+    }
+    let r = get_admin(&app, "/admin/timeline?flow_id=unknown").await;
+    assert_eq!(r.status(), 404);
+}
+
+#[tokio::test]
+async fn test_admin_reconciliation_as_json_and_csv() {
+    let app = spawn_app().await;
+    let db_pool = app.db_connection();
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    commission_model
+        .upsert(&make_fake_commission("1", "not-an-order-of-ours"))
+        .await
+        .unwrap();
+
+    let r = get_admin(&app, "/admin/reconciliation").await;
+    assert_eq!(r.status(), 200);
+    let body: Value = r.json().await.unwrap();
+    assert_eq!(body["checked"], 1);
+    assert_eq!(
+        body["discrepancies"],
+        json!([{
+            "kind": "orphan",
+            "order_id": "not-an-order-of-ours",
+            "commission_ids": ["1"],
+            "refund_id": null,
+            "cause": "No subscription has this order id.",
+        }])
+    );
+
+    let r = get_admin(&app, "/admin/reconciliation?format=csv").await;
+    assert_eq!(r.status(), 200);
+    assert_eq!(r.headers()["content-type"], "text/csv");
+    assert_eq!(
+        r.text().await.unwrap(),
+        "kind,order_id,commission_ids,refund_id,cause
+orphan,not-an-order-of-ours,1,,No subscription has this order id.
+"
+    );
+
+    // Before the commission was posted
+    let r = get_admin(
+        &app,
+        "/admin/reconciliation?since=2020-01-01&before=2020-02-01",
+    )
+    .await;
+    assert_eq!(r.status(), 200);
+    let body: Value = r.json().await.unwrap();
+    assert_eq!(body["checked"], 0);
+
+    let r = get_admin(&app, "/admin/reconciliation?format=xml").await;
+    assert_eq!(r.status(), 400);
+    let r = get_admin(&app, "/admin/reconciliation?since=yesterday").await;
+    assert_eq!(r.status(), 400);
+}
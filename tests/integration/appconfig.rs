@@ -1,7 +1,10 @@
 use lib::{
     appconfig::CJ,
+    jobs::outcome::JobOutcome,
+    models::job_locks::JobLockModel,
     telemetry::LogKey,
     version::{write_version, VersionInfo, VERSION_FILE},
+    worker::Job,
 };
 use serial_test::serial;
 use std::env;
@@ -22,7 +25,7 @@ async fn test_creating_and_shutting_down_a_cj_object() {
     write_version(VERSION_FILE, &version_data);
     env::set_var("BQ_ACCESS_TOKEN", "a token");
 
-    let cj = CJ::new(LogKey::Test).await;
+    let cj = CJ::new(LogKey::Test).await.unwrap();
     assert!(!cj.db_pool.is_closed());
     cj.shutdown().await.expect("Failed to complete shutdown");
     assert!(cj.db_pool.is_closed());
@@ -30,3 +33,45 @@ async fn test_creating_and_shutting_down_a_cj_object() {
     env::remove_var("BQ_ACCESS_TOKEN");
     fs::remove_file(VERSION_FILE).unwrap();
 }
+
+#[tokio::test]
+#[serial]
+async fn test_dry_runs_do_not_need_the_job_lock() {
+    let version_data = VersionInfo {
+        commit: "a1b2c3".to_string(),
+        source: "source".to_string(),
+        version: "version".to_string(),
+    };
+    write_version(VERSION_FILE, &version_data);
+    env::set_var("BQ_ACCESS_TOKEN", "a token");
+    let cj = CJ::new_for_job(LogKey::Test, true).await.unwrap();
+    let job_locks = JobLockModel {
+        db_pool: &cj.db_pool,
+    };
+    let held = job_locks
+        .try_lock(&Job::Cleanup.lock_name())
+        .await
+        .unwrap()
+        .expect("Lock should be free");
+    let ran = JobOutcome {
+        succeeded: 1,
+        ..Default::default()
+    };
+
+    let real = cj
+        .run_job(Job::Cleanup, false, async { Ok(ran.clone()) })
+        .await
+        .unwrap();
+    let dry = cj
+        .run_job(Job::Cleanup, true, async { Ok(ran.clone()) })
+        .await
+        .unwrap();
+
+    // The real run is skipped because the lock is held, the dry run isn't
+    assert_eq!(real, JobOutcome::default());
+    assert_eq!(dry, ran);
+    held.release().await.unwrap();
+    cj.shutdown().await.expect("Failed to complete shutdown");
+    env::remove_var("BQ_ACCESS_TOKEN");
+    fs::remove_file(VERSION_FILE).unwrap();
+}
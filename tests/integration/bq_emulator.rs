@@ -0,0 +1,201 @@
+use std::{env, net::TcpListener, path::Path};
+
+use lib::{
+    bigquery::{
+        client::{AccessTokenFromEnv, BQClient},
+        emulator::{
+            load_tables, parse_ndjson, run_emulator, EmulatorConfig, EmulatorState,
+            SharedEmulatorState,
+        },
+    },
+    error::CjmsError,
+    jobs::{
+        check_refunds::fetch_and_process_refunds,
+        check_subscriptions::fetch_and_process_new_subscriptions,
+    },
+    models::{
+        aic::AICModel,
+        refunds::RefundModel,
+        subscriptions::{LineItem, SubscriptionModel},
+    },
+    settings::get_settings,
+    telemetry::StatsD,
+};
+use serial_test::serial;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    models::{aic::make_fake_aic, subscriptions::make_fake_sub},
+    utils::get_test_db_pool,
+};
+
+// Returns the emulator's base url and its state, loaded with the fixture tables
+fn spawn_emulator(config: EmulatorConfig) -> (String, SharedEmulatorState) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let tables = load_tables(Path::new("tests/fixtures/bigquery")).expect("Failed to load tables");
+    let state = Arc::new(Mutex::new(EmulatorState::with_tables(tables)));
+    let server = run_emulator(listener, config, state.clone()).expect("Failed to start emulator");
+    tokio::spawn(server);
+    (format!("http://127.0.0.1:{}", port), state)
+}
+
+async fn emulated_bq_client(url: &str) -> BQClient {
+    env::set_var("BQ_ACCESS_TOKEN", "a token");
+    BQClient::new("a-project", AccessTokenFromEnv {}, Some(url))
+        .await
+        .expect("Failed to make BQClient")
+}
+
+#[tokio::test]
+#[serial]
+async fn check_subscriptions_pages_and_polls_emulated_results() {
+    // SETUP
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let aic_model = AICModel { db_pool: &db_pool };
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    for flow_id in ["flow_1", "flow_2"] {
+        let mut aic = make_fake_aic();
+        aic.flow_id = flow_id.to_string();
+        aic_model.create_from_aic(&aic).await.unwrap();
+    }
+    // A row per page and a job that isn't done the first two times it's checked
+    let (url, _) = spawn_emulator(EmulatorConfig {
+        page_size: 1,
+        incomplete_polls: 2,
+    });
+    let bq = emulated_bq_client(&url).await;
+
+    // GO
+    let outcome = fetch_and_process_new_subscriptions(&bq, &db_pool, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.failed, 0);
+    let sub_1 = sub_model.fetch_one_by_flow_id("flow_1").await.unwrap();
+    assert_eq!(sub_1.subscription_id, "sub_1KftCmKb9q6OnNsLJWrLnxyl");
+    assert_eq!(sub_1.plan_amount, 5988);
+    let sub_2 = sub_model.fetch_one_by_flow_id("flow_2").await.unwrap();
+    assert_eq!(sub_2.coupons, vec!["SPRING".to_string()]);
+
+    // GO - the next run reads the same rows again
+    let outcome = fetch_and_process_new_subscriptions(&bq, &db_pool, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT - they're skipped, not failed
+    assert_eq!(outcome.failed, 0);
+    assert_eq!(outcome.skipped, 2);
+}
+
+#[tokio::test]
+#[serial]
+async fn check_subscriptions_reads_line_items() {
+    // SETUP
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let aic_model = AICModel { db_pool: &db_pool };
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    for flow_id in ["flow_bundle", "flow_plan"] {
+        let mut aic = make_fake_aic();
+        aic.flow_id = flow_id.to_string();
+        aic_model.create_from_aic(&aic).await.unwrap();
+    }
+    let (url, state) = spawn_emulator(EmulatorConfig::default());
+    let table = parse_ndjson(
+        r#"{"flow_id": "flow_bundle", "subscription_id": "sub_bundle", "report_timestamp": "2022-03-21T22:14:50Z", "subscription_created": "2022-03-21T22:10:12Z", "fxa_uid": "a", "quantity": 1, "plan_id": "bundle", "plan_currency": "usd", "plan_amount": 1500, "country": "us", "promotion_codes": null, "line_items": [{"plan_id": "vpn", "quantity": 1, "amount": 1000, "discount": 100}, {"plan_id": "relay", "quantity": 2, "amount": 300, "discount": null}]}
+{"flow_id": "flow_plan", "subscription_id": "sub_plan", "report_timestamp": "2022-03-21T22:14:50Z", "subscription_created": "2022-03-21T22:10:12Z", "fxa_uid": "b", "quantity": 1, "plan_id": "vpn", "plan_currency": "usd", "plan_amount": 1000, "country": "us", "promotion_codes": null, "line_items": []}"#,
+    )
+    .unwrap();
+    state
+        .lock()
+        .unwrap()
+        .tables
+        .insert("cjms_bigquery.subscriptions_v1".to_string(), table);
+    let bq = emulated_bq_client(&url).await;
+
+    // GO
+    let outcome = fetch_and_process_new_subscriptions(&bq, &db_pool, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.failed, 0);
+    let bundle = sub_model.fetch_one_by_flow_id("flow_bundle").await.unwrap();
+    assert_eq!(
+        bundle.line_items(),
+        vec![
+            LineItem {
+                plan_id: "vpn".to_string(),
+                quantity: 1,
+                amount: 1000,
+                discount: 100,
+            },
+            LineItem {
+                plan_id: "relay".to_string(),
+                quantity: 2,
+                amount: 300,
+                discount: 0,
+            },
+        ]
+    );
+    let plan = sub_model.fetch_one_by_flow_id("flow_plan").await.unwrap();
+    assert_eq!(plan.line_items(), vec![]);
+    assert_eq!(plan.items().len(), 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn check_refunds_reads_tables_put_on_the_emulator() {
+    // SETUP
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let refund_model = RefundModel { db_pool: &db_pool };
+    let mut sub = make_fake_sub();
+    sub.subscription_id = "sub_emulated".to_string();
+    sub_model.create_from_sub(&sub).await.unwrap();
+    let (url, state) = spawn_emulator(EmulatorConfig::default());
+    let table = parse_ndjson(
+        r#"{"refund_id": "re_emulated", "subscription_id": "sub_emulated", "amount": 999, "created": "2022-03-21T22:14:50Z", "reason": null, "status": "succeeded"}"#,
+    )
+    .unwrap();
+    state
+        .lock()
+        .unwrap()
+        .tables
+        .insert("cjms_bigquery.refunds_v1".to_string(), table);
+    let bq = emulated_bq_client(&url).await;
+
+    // GO
+    fetch_and_process_refunds(&bq, &db_pool, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    let refund = refund_model
+        .fetch_one_by_refund_id("re_emulated")
+        .await
+        .unwrap();
+    assert_eq!(refund.subscription_id, "sub_emulated");
+    assert_eq!(refund.refund_amount, 999);
+}
+
+#[tokio::test]
+#[serial]
+async fn unknown_tables_are_bigquery_errors() {
+    let (url, _) = spawn_emulator(EmulatorConfig::default());
+    let bq = emulated_bq_client(&url).await;
+    match bq
+        .get_bq_results("SELECT * FROM `cjms_bigquery.nope`;")
+        .await
+    {
+        Err(CjmsError::BigQuery(_)) => {}
+        other => panic!("Expected a BigQuery error, got {:?}", other.map(|_| ())),
+    }
+}
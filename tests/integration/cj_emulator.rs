@@ -0,0 +1,214 @@
+use lib::{
+    cj::{
+        client::CJClient,
+        emulator::{run_emulator, EmulatorConfig, SharedEmulatorState},
+    },
+    controllers::corrections::build_body_from_results,
+    jobs::{report_subscriptions::report_subscriptions, verify_reports::verify_reports},
+    models::{
+        refunds::RefundModel,
+        status_history::{Status, UpdateStatus},
+        subscriptions::{LineItem, SubscriptionModel},
+    },
+    settings::{get_settings, Settings},
+    telemetry::StatsD,
+};
+use std::net::TcpListener;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    models::{refunds::make_fake_refund, subscriptions::make_fake_sub},
+    utils::get_test_db_pool,
+};
+
+// Returns the emulator's base url and its state
+fn spawn_emulator(config: EmulatorConfig) -> (String, SharedEmulatorState) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let port = listener.local_addr().unwrap().port();
+    let state = SharedEmulatorState::default();
+    let server = run_emulator(listener, config, state.clone()).expect("Failed to start emulator");
+    tokio::spawn(server);
+    (format!("http://127.0.0.1:{}", port), state)
+}
+
+fn emulated_cj_client(settings: &Settings, url: &str) -> CJClient {
+    CJClient::new(
+        settings,
+        Some(&format!("{}/u", url)),
+        Some(&format!("{}/query", url)),
+        None,
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn reported_subscriptions_and_corrections_are_verified() {
+    // SETUP
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let refund_model = RefundModel { db_pool: &db_pool };
+    let (url, state) = spawn_emulator(EmulatorConfig::default());
+    let cj_client = emulated_cj_client(&settings, &url);
+    let mut sub = make_fake_sub();
+    sub.plan_currency = "usd".to_string();
+    sub.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    sub_model.create_from_sub(&sub).await.unwrap();
+
+    // GO - report, then verify
+    report_subscriptions(&db_pool, &cj_client, &settings, &statsd, false)
+        .await
+        .unwrap();
+    verify_reports(&db_pool, &cj_client, &settings, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    let verified = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+    assert_eq!(verified.get_status().unwrap(), Status::CJReceived);
+    assert_eq!(state.lock().unwrap().records.len(), 1);
+
+    // GO - send a corrections file with a refund of the sub, then verify
+    let mut refund = make_fake_refund();
+    refund.subscription_id = sub.subscription_id.clone();
+    refund.refund_amount = sub.plan_amount;
+    refund.update_status(Status::Reported);
+    refund_model.create_from_refund(&refund).await.unwrap();
+    let saved_refund = refund_model
+        .fetch_one_by_refund_id(&refund.refund_id)
+        .await
+        .unwrap();
+    let programs = cj_client.programs();
+    let corrections_file = build_body_from_results(
+        programs,
+        programs.for_plan(&sub.plan_id),
+        vec![saved_refund],
+        &db_pool,
+        &statsd,
+    )
+    .await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/corrections", url))
+        .body(corrections_file)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    verify_reports(&db_pool, &cj_client, &settings, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    let verified = refund_model
+        .fetch_one_by_refund_id(&refund.refund_id)
+        .await
+        .unwrap();
+    assert_eq!(verified.get_status().unwrap(), Status::CJReceived);
+    let records = state.lock().unwrap().records.clone();
+    assert_eq!(records.len(), 2);
+    assert!(!records[1].original);
+}
+
+#[tokio::test]
+async fn subscriptions_with_line_items_are_reported_and_verified_item_by_item() {
+    // SETUP
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let (url, state) = spawn_emulator(EmulatorConfig::default());
+    let cj_client = emulated_cj_client(&settings, &url);
+    let mut sub = make_fake_sub();
+    sub.plan_currency = "usd".to_string();
+    sub.coupons = vec![];
+    sub.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    sub.set_line_items(&[
+        LineItem {
+            plan_id: sub.plan_id.clone(),
+            quantity: 1,
+            amount: 1000,
+            discount: 150,
+        },
+        LineItem {
+            plan_id: "add-on".to_string(),
+            quantity: 3,
+            amount: 250,
+            discount: 0,
+        },
+    ]);
+    sub_model.create_from_sub(&sub).await.unwrap();
+
+    // GO
+    report_subscriptions(&db_pool, &cj_client, &settings, &statsd, false)
+        .await
+        .unwrap();
+    verify_reports(&db_pool, &cj_client, &settings, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    let records = state.lock().unwrap().records.clone();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].sale_amount_pub_currency, "16.00");
+    let skus: Vec<&str> = records[0]
+        .items
+        .iter()
+        .map(|item| item["sku"].as_str().unwrap())
+        .collect();
+    assert_eq!(skus, vec![sub.plan_id.as_str(), "add-on"]);
+    let verified = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+    assert_eq!(verified.get_status().unwrap(), Status::CJReceived);
+}
+
+#[tokio::test]
+async fn commission_detail_pages_through_emulated_records() {
+    let settings = get_settings();
+    let (url, _) = spawn_emulator(EmulatorConfig {
+        page_size: 2,
+        ..Default::default()
+    });
+    let cj_client = emulated_cj_client(&settings, &url);
+    for _ in 0..5 {
+        cj_client
+            .report_subscription(&make_fake_sub())
+            .await
+            .unwrap();
+    }
+    let now = OffsetDateTime::now_utc();
+    let result = cj_client
+        .query_commission_detail_api_between_dates(&cj_client.programs().all()[0], now, now)
+        .await
+        .unwrap();
+    assert_eq!(result.count, 5);
+}
+
+#[tokio::test]
+async fn emulator_failures_are_reported_as_failed() {
+    // SETUP
+    let mut settings = get_settings();
+    settings.cj_max_retries = 1;
+    settings.cj_retry_base_delay_ms = 1;
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let (url, state) = spawn_emulator(EmulatorConfig {
+        failure_rate: 1.0,
+        ..Default::default()
+    });
+    let cj_client = emulated_cj_client(&settings, &url);
+    let mut sub = make_fake_sub();
+    sub.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    sub_model.create_from_sub(&sub).await.unwrap();
+
+    // GO
+    let outcome = report_subscriptions(&db_pool, &cj_client, &settings, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.failed, 1);
+    let unreported = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+    assert_eq!(unreported.get_status().unwrap(), Status::NotReported);
+    assert!(state.lock().unwrap().records.is_empty());
+}
@@ -0,0 +1,218 @@
+use clap::Parser;
+use lib::{
+    cjmsctl::{run, Cli, Output},
+    error::CjmsError,
+    models::{
+        aic::AICModel,
+        refunds::RefundModel,
+        status_history::{Status, UpdateStatus},
+        status_overrides::StatusOverrideModel,
+        subscriptions::SubscriptionModel,
+    },
+    settings::get_settings,
+    telemetry::StatsD,
+};
+use serde_json::json;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        aic::make_fake_aic,
+        refunds::{make_fake_refund, save_refund},
+        subscriptions::{make_fake_sub, save_sub},
+    },
+    utils::get_test_db_pool,
+};
+
+async fn cjmsctl(db_pool: &PgPool, args: &[&str]) -> Result<Output, CjmsError> {
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let cli = Cli::parse_from([&["cjmsctl"], args].concat());
+    run(&cli.command, db_pool, &settings, &statsd).await
+}
+
+#[tokio::test]
+async fn test_cjmsctl_counts() {
+    let db_pool = get_test_db_pool().await;
+    let subs = SubscriptionModel { db_pool: &db_pool };
+    for status in [Status::Reported, Status::Reported, Status::CJReceived] {
+        let mut sub = make_fake_sub();
+        sub.update_status(status);
+        save_sub(&subs, &sub).await;
+    }
+    save_refund(&RefundModel { db_pool: &db_pool }, &make_fake_refund()).await;
+
+    let output = cjmsctl(&db_pool, &["counts"]).await.unwrap();
+    assert_eq!(
+        output.value,
+        json!({
+            "subscriptions": {"CJReceived": 1, "Reported": 2},
+            "refunds": {"NotReported": 1},
+        })
+    );
+    assert_eq!(
+        output.render(false),
+        "subscriptions\nCJReceived: 1\nReported: 2\n\nrefunds\nNotReported: 1"
+    );
+}
+
+#[tokio::test]
+async fn test_cjmsctl_inspect_and_list() {
+    let db_pool = get_test_db_pool().await;
+    let subs = SubscriptionModel { db_pool: &db_pool };
+    let mut reported = make_fake_sub();
+    reported.update_status(Status::Reported);
+    save_sub(&subs, &reported).await;
+    save_sub(&subs, &make_fake_sub()).await;
+
+    let output = cjmsctl(&db_pool, &["list", "subscription", "--status", "Reported"])
+        .await
+        .unwrap();
+    let records = output.value.as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["id"], json!(reported.id));
+
+    let output = cjmsctl(
+        &db_pool,
+        &["inspect", "subscription", &reported.id.to_string()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(output.value["flow_id"], json!(reported.flow_id));
+    assert_eq!(output.value["status_overrides"], json!([]));
+
+    assert!(matches!(
+        cjmsctl(&db_pool, &["list", "aic", "--status", "Reported"]).await,
+        Err(CjmsError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        cjmsctl(&db_pool, &["inspect", "subscription", "not-a-uuid"]).await,
+        Err(CjmsError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        cjmsctl(
+            &db_pool,
+            &["inspect", "subscription", &Uuid::new_v4().to_string()]
+        )
+        .await,
+        Err(CjmsError::Database(sqlx::Error::RowNotFound))
+    ));
+}
+
+#[tokio::test]
+async fn test_cjmsctl_requeue() {
+    let db_pool = get_test_db_pool().await;
+    let refunds = RefundModel { db_pool: &db_pool };
+    let mut refund = make_fake_refund();
+    refund.update_status(Status::Reported);
+    save_refund(&refunds, &refund).await;
+
+    let output = cjmsctl(
+        &db_pool,
+        &[
+            "requeue",
+            "refund",
+            &refund.refund_id,
+            "--reason",
+            "Lost by CJ",
+            "--operator",
+            "ops",
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(output.value.as_array().unwrap().len(), 1);
+    let updated = refunds
+        .fetch_one_by_refund_id(&refund.refund_id)
+        .await
+        .unwrap();
+    assert_eq!(updated.get_status(), Some(Status::NotReported));
+    let entries = StatusOverrideModel { db_pool: &db_pool }
+        .fetch_all_by_record_id(&refund.id)
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].reason, "Lost by CJ");
+
+    // Unknown ids make the command fail, saying which ids were requeued anyway
+    let mut other = make_fake_refund();
+    other.update_status(Status::Reported);
+    save_refund(&refunds, &other).await;
+    let result = cjmsctl(
+        &db_pool,
+        &[
+            "requeue",
+            "refund",
+            &other.refund_id,
+            "nope",
+            "--reason",
+            "r",
+            "--operator",
+            "ops",
+        ],
+    )
+    .await;
+    let error = result.err().unwrap();
+    assert_eq!(error.exit_code(), 1);
+    assert!(error.to_string().starts_with(&format!(
+        "Too many records failed: Requeued 1 of 2: {}. Failed: nope",
+        other.refund_id
+    )));
+    let updated = refunds
+        .fetch_one_by_refund_id(&other.refund_id)
+        .await
+        .unwrap();
+    assert_eq!(updated.get_status(), Some(Status::NotReported));
+}
+
+#[tokio::test]
+async fn test_cjmsctl_archive_and_unarchive_aic() {
+    let db_pool = get_test_db_pool().await;
+    let aics = AICModel { db_pool: &db_pool };
+    let aic = make_fake_aic();
+    aics.create_from_aic(&aic).await.unwrap();
+    let id = aic.id.to_string();
+
+    cjmsctl(&db_pool, &["archive-aic", &id]).await.unwrap();
+    assert!(aics.fetch_one_by_id(&aic.id).await.is_err());
+    let output = cjmsctl(&db_pool, &["inspect", "aic", &id]).await.unwrap();
+    assert_eq!(output.value["archived"], json!(true));
+
+    cjmsctl(&db_pool, &["unarchive-aic", &id]).await.unwrap();
+    assert_eq!(aics.fetch_one_by_id(&aic.id).await.unwrap(), aic);
+    assert!(aics.fetch_one_by_id_from_archive(&aic.id).await.is_err());
+    let output = cjmsctl(&db_pool, &["inspect", "aic", &id]).await.unwrap();
+    assert_eq!(output.value["archived"], json!(false));
+}
+
+#[tokio::test]
+async fn test_cjmsctl_corrections() {
+    let db_pool = get_test_db_pool().await;
+    let settings = get_settings();
+    let sub = make_fake_sub();
+    save_sub(&SubscriptionModel { db_pool: &db_pool }, &sub).await;
+    let mut refund = make_fake_refund();
+    refund.subscription_id = sub.subscription_id.clone();
+    let today = OffsetDateTime::now_utc().date();
+    refund.correction_file_date = Some(today);
+    save_refund(&RefundModel { db_pool: &db_pool }, &refund).await;
+
+    let day = today.format("%F");
+    let output = cjmsctl(&db_pool, &["corrections", &day]).await.unwrap();
+    let expected = format!(
+        "&CID={}\n&SUBID={}\nRETRN,,{}",
+        settings.cj_sftp_user, settings.cj_subid, sub.id
+    );
+    assert_eq!(output.render(false), expected);
+    assert_eq!(output.value["body"], json!(expected));
+    assert!(matches!(
+        cjmsctl(&db_pool, &["corrections", "yesterday"]).await,
+        Err(CjmsError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        cjmsctl(&db_pool, &["corrections", &day, "--program", "nope"]).await,
+        Err(CjmsError::CJ(_))
+    ));
+}
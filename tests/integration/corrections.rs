@@ -1,4 +1,9 @@
-use lib::models::{refunds::RefundModel, subscriptions::SubscriptionModel};
+use lib::{
+    cj::programs::CJProgram,
+    models::{refunds::RefundModel, subscriptions::SubscriptionModel},
+    plans::{Plan, PlanCatalog},
+    settings::get_settings,
+};
 use reqwest::Response;
 use time::{date, Date, OffsetDateTime};
 
@@ -7,7 +12,7 @@ use crate::{
         refunds::{make_fake_refund, save_refund},
         subscriptions::{make_fake_sub, save_sub},
     },
-    utils::spawn_app,
+    utils::{spawn_app, spawn_app_with_settings},
 };
 
 const ANOTHER_DAY: Date = date!(2021 - 11 - 07);
@@ -165,3 +170,83 @@ RETRN,,{}"#,
     );
     assert_eq!(actual_body, expected_body);
 }
+
+#[tokio::test]
+async fn test_corrections_are_split_by_program() {
+    let mut relay_refund = make_fake_refund();
+    relay_refund.correction_file_date = Some(ANOTHER_DAY);
+    let mut relay_sub = make_fake_sub();
+    relay_sub.subscription_id = relay_refund.subscription_id.clone();
+    let mut vpn_refund = make_fake_refund();
+    vpn_refund.correction_file_date = Some(ANOTHER_DAY);
+    let mut vpn_sub = make_fake_sub();
+    vpn_sub.subscription_id = vpn_refund.subscription_id.clone();
+    let mut settings = get_settings();
+    settings.cj_programs = vec![CJProgram {
+        name: "relay".to_string(),
+        cj_cid: "relay-cid".to_string(),
+        cj_type: "relay-type".to_string(),
+        cj_signature: "relay-signature".to_string(),
+        cj_sftp_user: "relay-advertiser".to_string(),
+        cj_subid: "relay-subid".to_string(),
+        cj_api_access_token: None,
+        products: vec!["Relay".to_string()],
+    }];
+    settings.plans = PlanCatalog(vec![Plan {
+        plan_id: relay_sub.plan_id.clone(),
+        cj_sku: None,
+        product_name: Some("Relay".to_string()),
+        eligible: true,
+        ineligible_reason: None,
+        cj_program: None,
+    }]);
+    let app = spawn_app_with_settings(settings).await;
+    let refunds = RefundModel {
+        db_pool: &app.db_connection(),
+    };
+    let subs = SubscriptionModel {
+        db_pool: &app.db_connection(),
+    };
+    save_refund(&refunds, &relay_refund).await;
+    save_refund(&refunds, &vpn_refund).await;
+    save_sub(&subs, &relay_sub).await;
+    save_sub(&subs, &vpn_sub).await;
+    let relay_sub = subs
+        .fetch_one_by_subscription_id(&relay_sub.subscription_id)
+        .await
+        .unwrap();
+    let vpn_sub = subs
+        .fetch_one_by_subscription_id(&vpn_sub.subscription_id)
+        .await
+        .unwrap();
+
+    // Only the relay refund is in the relay program's file
+    let path = app.build_url("/corrections/relay/2021-11-07.csv");
+    let r = get_authed_path(&path, &app.settings.authentication).await;
+    assert_eq!(r.status(), 200);
+    let expected_body = format!(
+        r#"&CID=relay-advertiser
+&SUBID=relay-subid
+RETRN,,{}"#,
+        relay_sub.id
+    );
+    assert_eq!(r.text().await.unwrap(), expected_body);
+    // Needs auth, like the default program's file
+    let r = reqwest::Client::new().get(&path).send().await.unwrap();
+    assert_eq!(r.status(), 401);
+
+    // And only the other refund is in the default program's file
+    let path = app.build_url("/corrections/2021-11-07.csv");
+    let r = get_authed_path(&path, &app.settings.authentication).await;
+    let expected_body = format!(
+        r#"&CID={}
+&SUBID={}
+RETRN,,{}"#,
+        app.settings.cj_sftp_user, app.settings.cj_subid, vpn_sub.id
+    );
+    assert_eq!(r.text().await.unwrap(), expected_body);
+
+    let path = app.build_url("/corrections/unknown/2021-11-07.csv");
+    let r = get_authed_path(&path, &app.settings.authentication).await;
+    assert_eq!(r.status(), 404);
+}
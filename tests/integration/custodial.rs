@@ -1,7 +1,12 @@
 use std::fs;
 
 use crate::utils::{send_get_request, spawn_app};
-use lib::version::{VersionInfo, VERSION_FILE};
+use lib::{
+    jobs::outcome::JobOutcome,
+    models::job_runs::JobRunModel,
+    version::{VersionInfo, VERSION_FILE},
+};
+use serde_json::Value;
 
 #[tokio::test]
 async fn index_get() {
@@ -39,3 +44,24 @@ version: the version
     let body: VersionInfo = r.json().await.expect("Couldn't get JSON.");
     assert_eq!(body.source, "a source");
 }
+
+#[tokio::test]
+async fn jobs_get() {
+    let app = spawn_app().await;
+    let model = JobRunModel {
+        db_pool: &app.db_connection(),
+    };
+    let run = model.start("cleanup").await.unwrap();
+    model
+        .finish(&run.id, &JobOutcome::default(), None)
+        .await
+        .unwrap();
+
+    let r = send_get_request(&app, "/__jobs__").await;
+    assert_eq!(r.status(), 200);
+    let body: Value = r.json().await.expect("Couldn't get JSON.");
+    assert_eq!(body["cleanup"]["last_run"]["id"], run.id.to_string());
+    assert_eq!(body["cleanup"]["last_success"]["id"], run.id.to_string());
+    assert_eq!(body["check_refunds"]["last_run"], Value::Null);
+    assert_eq!(body.as_object().unwrap().len(), 8);
+}
@@ -0,0 +1,169 @@
+use async_trait::async_trait;
+use lib::{
+    jobs::{
+        batch_refunds::batch_refunds_by_day, report_subscriptions::report_subscriptions,
+        verify_reports::verify_reports,
+    },
+    models::{
+        refunds::{Refund, RefundModel},
+        status_history::{Status, UpdateStatus},
+        subscriptions::{Subscription, SubscriptionModel},
+    },
+    money::Money,
+    network::{AffiliateNetwork, NetworkCommission, NetworkCommissionItem},
+    settings::get_settings,
+    telemetry::StatsD,
+};
+use std::sync::Mutex;
+use time::{date, time, Duration, OffsetDateTime};
+
+use crate::{
+    models::{refunds::make_fake_refund, subscriptions::make_fake_sub},
+    utils::get_test_db_pool,
+};
+
+// A network other than CJ, keeping what it's sent and returning the records it's given
+#[derive(Default)]
+struct FakeNetwork {
+    fail: bool,
+    conversions: Mutex<Vec<String>>,
+    corrections: Mutex<Vec<String>>,
+    records: Vec<NetworkCommission>,
+}
+
+#[async_trait]
+impl AffiliateNetwork for FakeNetwork {
+    type Error = String;
+
+    fn accounts(&self) -> Vec<&str> {
+        vec!["fake"]
+    }
+
+    fn click_id<'a>(&self, sub: &'a Subscription) -> Option<&'a str> {
+        sub.cj_event_value.as_deref()
+    }
+
+    fn account_for_plan(&self, _plan_id: &str) -> &str {
+        "fake"
+    }
+
+    async fn report_conversion(&self, sub: &Subscription) -> Result<(), String> {
+        if self.fail {
+            return Err("Fake network is down".to_string());
+        }
+        self.conversions.lock().unwrap().push(sub.id.to_string());
+        Ok(())
+    }
+
+    fn describe_conversion(&self, sub: &Subscription) -> String {
+        format!("fake conversion {}", sub.id)
+    }
+
+    async fn submit_correction(&self, refund: &Refund) -> Result<(), String> {
+        if self.fail {
+            return Err("Fake network is down".to_string());
+        }
+        self.corrections
+            .lock()
+            .unwrap()
+            .push(refund.refund_id.clone());
+        Ok(())
+    }
+
+    async fn query_commissions(
+        &self,
+        _account: &str,
+        _min: OffsetDateTime,
+        _max: OffsetDateTime,
+    ) -> Result<Vec<NetworkCommission>, String> {
+        Ok(self.records.clone())
+    }
+}
+
+fn make_record(sub: &Subscription) -> NetworkCommission {
+    NetworkCommission {
+        id: "fake-1".to_string(),
+        order_id: sub.id.to_string(),
+        posting_date: date!(2022 - 06 - 01).with_time(time!(12:00)).assume_utc(),
+        original: true,
+        correction_reason: None,
+        sale_amount: Money::new((sub.plan_amount * sub.quantity).into(), "usd"),
+        commission_amount: None,
+        status: None,
+        validation_status: None,
+        publisher_id: None,
+        items: vec![NetworkCommissionItem {
+            sku: sub.plan_id.clone(),
+            quantity: Some(sub.quantity),
+            discount: None,
+        }],
+    }
+}
+
+#[tokio::test]
+async fn jobs_report_and_verify_with_another_network() {
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let subs = SubscriptionModel { db_pool: &db_pool };
+    let refunds = RefundModel { db_pool: &db_pool };
+    let mut sub = make_fake_sub();
+    sub.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    sub.plan_currency = "usd".to_string();
+    subs.create_from_sub(&sub).await.unwrap();
+    let mut refund = make_fake_refund();
+    refund.refund_status = Some("succeeded".to_string());
+    refunds.create_from_refund(&refund).await.unwrap();
+    let network = FakeNetwork {
+        records: vec![make_record(&sub)],
+        ..Default::default()
+    };
+
+    report_subscriptions(&db_pool, &network, &settings, &statsd, false)
+        .await
+        .unwrap();
+    batch_refunds_by_day(&db_pool, &network, &statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        *network.conversions.lock().unwrap(),
+        vec![sub.id.to_string()]
+    );
+    assert_eq!(
+        *network.corrections.lock().unwrap(),
+        vec![refund.refund_id.clone()]
+    );
+
+    verify_reports(&db_pool, &network, &settings, &statsd, false)
+        .await
+        .unwrap();
+    let verified = subs.fetch_one_by_id(&sub.id).await.unwrap();
+    assert_eq!(verified.get_status(), Some(Status::CJReceived));
+}
+
+#[tokio::test]
+async fn refunds_are_left_not_reported_if_the_correction_cannot_be_submitted() {
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let refunds = RefundModel { db_pool: &db_pool };
+    let mut refund = make_fake_refund();
+    refund.refund_status = Some("succeeded".to_string());
+    refunds.create_from_refund(&refund).await.unwrap();
+    let network = FakeNetwork {
+        fail: true,
+        ..Default::default()
+    };
+
+    let outcome = batch_refunds_by_day(&db_pool, &network, &statsd, false)
+        .await
+        .unwrap();
+
+    assert_eq!(outcome.failed, 1);
+    let not_reported = refunds
+        .fetch_one_by_refund_id(&refund.refund_id)
+        .await
+        .unwrap();
+    assert_eq!(not_reported.get_status(), Some(Status::NotReported));
+    assert!(not_reported.correction_file_date.is_none());
+}
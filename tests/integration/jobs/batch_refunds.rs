@@ -1,4 +1,5 @@
 use lib::{
+    cj::client::CJClient,
     jobs::batch_refunds::batch_refunds_by_day,
     models::{
         refunds::RefundModel,
@@ -42,7 +43,12 @@ async fn batch_refunds_by_day_makes_unreported_subscriptions_reported_and_gives_
     // GO
     std::thread::sleep(std::time::Duration::from_secs(2));
     let now = OffsetDateTime::now_utc();
-    batch_refunds_by_day(&db_pool, &mock_statsd).await;
+    let cj_client = CJClient::new(&settings, None, None, None).unwrap();
+    let outcome = batch_refunds_by_day(&db_pool, &cj_client, &mock_statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.succeeded, 5);
+    assert_eq!(outcome.failed, 0);
 
     // ASSERT
 
@@ -76,7 +82,8 @@ async fn batch_refunds_by_day_makes_unreported_subscriptions_reported_and_gives_
             refund_updated_history.entries[1],
             StatusHistoryEntry {
                 status: Status::Reported,
-                t: now
+                t: refund_updated.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -89,7 +96,8 @@ async fn batch_refunds_by_day_makes_unreported_subscriptions_reported_and_gives_
             refund_updated_history.entries[1],
             StatusHistoryEntry {
                 status: Status::WillNotReport,
-                t: now
+                t: refund_updated.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -27,6 +27,14 @@ fn fixture_bigquery_response() -> Value {
     serde_json::from_str(&data).expect("JSON was not well-formatted")
 }
 
+// The job stamps status_t when it reads the row, which can be a second or more before
+// the expected subscription is made here, so the expectation takes the actual one.
+fn assert_sub_made_from(actual: &Subscription, partial: PartialSubscription) {
+    let mut expected = Subscription::new(partial);
+    expected.set_status_t(actual.get_status_t());
+    assert_eq!(*actual, expected);
+}
+
 #[tokio::test]
 #[serial]
 async fn check_subscriptions() {
@@ -82,7 +90,9 @@ async fn check_subscriptions() {
     // Setup fake bigquery with results to return
     env::set_var("BQ_ACCESS_TOKEN", "a token");
     let mock_bq = MockServer::start().await;
-    let bq = BQClient::new("a project", AccessTokenFromEnv {}, Some(&mock_bq.uri())).await;
+    let bq = BQClient::new("a project", AccessTokenFromEnv {}, Some(&mock_bq.uri()))
+        .await
+        .unwrap();
     let response = ResponseTemplate::new(200).set_body_json(fixture_bigquery_response());
     Mock::given(any())
         .respond_with(response)
@@ -90,7 +100,9 @@ async fn check_subscriptions() {
         .await;
 
     // GO
-    fetch_and_process_new_subscriptions(&bq, &db_pool, &mock_statsd).await;
+    fetch_and_process_new_subscriptions(&bq, &db_pool, &mock_statsd, false)
+        .await
+        .unwrap();
 
     // ASSERT
     let sub_1 = sub_model
@@ -129,9 +141,9 @@ async fn check_subscriptions() {
         // Test that subs have a uuid as "id" (this is used for oid for cj reporting)
         assert_eq!(Some(Version::Random), sub.id.get_version());
     }
-    assert_eq!(
-        sub_1,
-        Subscription::new(PartialSubscription {
+    assert_sub_made_from(
+        &sub_1,
+        PartialSubscription {
             id: sub_1.id, // We can't know this ahead of time
             flow_id: sub_happy_flow_id.to_string(),
             subscription_id: "sub_1Ke0R3Kb9q6OnNsLD1OIZsxm".to_string(),
@@ -147,17 +159,17 @@ async fn check_subscriptions() {
             plan_currency: "usd".to_string(),
             plan_amount: 100,
             country: Some("us - THIS IS SUB 1".to_string()),
-            coupons: None,
+            coupons: vec![],
             aic_id: Some(aic_1.id),
             aic_expires: Some(aic_1.expires),
             cj_event_value: Some(aic_1.cj_event_value.to_string()),
-        })
+        },
     );
     let sub_1_status_history = sub_1.get_status_history().unwrap();
     assert_eq!(sub_1_status_history.entries[0].status, Status::NotReported);
-    assert_eq!(
-        sub_2,
-        Subscription::new(PartialSubscription {
+    assert_sub_made_from(
+        &sub_2,
+        PartialSubscription {
             id: sub_2.id, // We can't know this ahead of time
             flow_id: sub_archived_flow_id.to_string(),
             subscription_id: "sub_1Ke0R3Kb9q6".to_string(),
@@ -174,19 +186,19 @@ async fn check_subscriptions() {
             plan_amount: 100,
             country: Some(
                 "THIS IS AN ENTRY WHOSE AIC IS ALREADY IN THE ARCHIVE TABLE. IT SHOULD SUCCEED"
-                    .to_string()
+                    .to_string(),
             ),
-            coupons: None,
+            coupons: vec![],
             aic_id: Some(pre_archived.id),
             aic_expires: Some(pre_archived.expires),
             cj_event_value: Some(pre_archived.cj_event_value),
-        })
+        },
     );
-    assert_eq!(
-        // Sub three is the failure cases in the test fixture. So if error handling is working as expected this happy path case should be fine.
-        // TODO - LOGGING - when we add logging we could test for those logs to have been created
-        sub_3,
-        Subscription::new(PartialSubscription {
+    // Sub three is the failure cases in the test fixture. So if error handling is working as expected this happy path case should be fine.
+    // TODO - LOGGING - when we add logging we could test for those logs to have been created
+    assert_sub_made_from(
+        &sub_3,
+        PartialSubscription {
             id: sub_3.id, // We can't know this ahead of time
             flow_id: sub_happy_2_flow_id.to_string(),
             subscription_id: "sub_1Ke0CHKb9q6OnNsLe2fSFt2W".to_string(),
@@ -202,16 +214,16 @@ async fn check_subscriptions() {
             plan_currency: "usd".to_string(),
             plan_amount: 4794,
             country: None,
-            coupons: None,
+            coupons: vec![],
             aic_id: Some(aic_4.id),
             aic_expires: Some(aic_4.expires),
             cj_event_value: Some(aic_4.cj_event_value),
-        })
+        },
     );
-    assert_eq!(
-        // Sub four has a coupon code
-        sub_4,
-        Subscription::new(PartialSubscription {
+    // Sub four has a coupon code
+    assert_sub_made_from(
+        &sub_4,
+        PartialSubscription {
             id: sub_4.id, // We can't know this ahead of time
             flow_id: sub_coupons_flow_id.to_string(),
             subscription_id: "sub_1L47CjKb9q6OnNsLz4ekpzfv".to_string(),
@@ -227,16 +239,16 @@ async fn check_subscriptions() {
             plan_currency: "usd".to_string(),
             plan_amount: 5389,
             country: Some("us".to_string()),
-            coupons: Some("VPN10".to_string()),
+            coupons: vec!["VPN10".to_string()],
             aic_id: Some(aic_5.id),
             aic_expires: Some(aic_5.expires),
             cj_event_value: Some(aic_5.cj_event_value),
-        })
+        },
     );
-    assert_eq!(
-        // Sub five whitespace
-        sub_5,
-        Subscription::new(PartialSubscription {
+    // Sub five whitespace
+    assert_sub_made_from(
+        &sub_5,
+        PartialSubscription {
             id: sub_5.id, // We can't know this ahead of time
             flow_id: sub_coupon_whitespace_flow_id.to_string(),
             subscription_id: "sub_5".to_string(),
@@ -252,11 +264,11 @@ async fn check_subscriptions() {
             plan_currency: "usd".to_string(),
             plan_amount: 999,
             country: Some("us".to_string()),
-            coupons: Some("VPN1000".to_string()),
+            coupons: vec!["VPN1000".to_string(), "SPRING".to_string()],
             aic_id: Some(aic_6.id),
             aic_expires: Some(aic_6.expires),
             cj_event_value: Some(aic_6.cj_event_value),
-        })
+        },
     );
     // Expect to NOT have certain entries from the test fixtures
     for bad_flow_id in [
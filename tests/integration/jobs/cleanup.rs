@@ -36,7 +36,11 @@ async fn test_archive_expired_aics() {
         .await
         .expect("Could not create pre-archived AIC.");
 
-    archive_expired_aics(&db_pool, &statsd).await;
+    let outcome = archive_expired_aics(&db_pool, &statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.succeeded, 2);
+    assert_eq!(outcome.failed, 1);
 
     assert!(aic_model
         .fetch_one_by_id_from_archive(&aic_1.id)
@@ -55,3 +59,25 @@ async fn test_archive_expired_aics() {
     assert!(aic_model.fetch_one_by_id(&aic_2.id).await.is_ok());
     assert!(aic_model.fetch_one_by_id(&aic_3.id).await.is_err());
 }
+
+#[tokio::test]
+async fn test_archive_expired_aics_dry_run() {
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let aic_model = AICModel { db_pool: &db_pool };
+    let mut aic = make_fake_aic();
+    aic.expires = OffsetDateTime::now_utc() - Duration::seconds(5);
+    aic_model
+        .create_from_aic(&aic)
+        .await
+        .expect("Could not create AIC");
+
+    archive_expired_aics(&db_pool, &statsd, true).await.unwrap();
+
+    assert!(aic_model.fetch_one_by_id(&aic.id).await.is_ok());
+    assert!(aic_model
+        .fetch_one_by_id_from_archive(&aic.id)
+        .await
+        .is_err());
+}
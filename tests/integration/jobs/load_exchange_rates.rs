@@ -0,0 +1,149 @@
+use std::io::Write;
+use std::time::Duration;
+
+use lib::{
+    error::CjmsError, jobs::load_exchange_rates::load_exchange_rates,
+    models::exchange_rates::ExchangeRateModel, settings::get_settings, telemetry::StatsD,
+};
+use tempfile::NamedTempFile;
+use time::date;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::utils::get_test_db_pool;
+
+const RATES: &str = "date,currency,rate
+2022-06-03,eur,1.0713
+2022-06-03,chf,1.0382
+2022-06-06,eur,1.0720
+2022-06-06,chf,nope
+";
+
+#[tokio::test]
+async fn rates_are_loaded_from_a_file() {
+    // SETUP
+    let mut settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let rates = ExchangeRateModel { db_pool: &db_pool };
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "{}", RATES).unwrap();
+    settings.exchange_rates_source = Some(file.path().to_str().unwrap().to_string());
+
+    // GO - a dry run loads nothing
+    let outcome = load_exchange_rates(&db_pool, &settings, &statsd, true)
+        .await
+        .unwrap();
+    assert_eq!(outcome.succeeded, 3);
+    assert!(rates
+        .fetch_one_on_date("eur", &date!(2022 - 06 - 06))
+        .await
+        .unwrap()
+        .is_none());
+
+    // GO
+    let outcome = load_exchange_rates(&db_pool, &settings, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.succeeded, 3);
+    assert_eq!(outcome.failed, 1);
+    let eur = rates
+        .fetch_one_on_date("EUR", &date!(2022 - 06 - 06))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(eur.rate, 1.0720);
+    // The weekend uses Friday's rate
+    let chf = rates
+        .fetch_one_on_date("chf", &date!(2022 - 06 - 05))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(chf.date, date!(2022 - 06 - 03));
+    assert_eq!(chf.rate, 1.0382);
+    assert!(rates
+        .fetch_one_on_date("eur", &date!(2022 - 06 - 02))
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn rates_are_loaded_from_a_feed() {
+    // SETUP
+    let mut settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let rates = ExchangeRateModel { db_pool: &db_pool };
+    let mock_feed = MockServer::start().await;
+    Mock::given(path("/rates.csv"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(RATES))
+        .expect(1)
+        .mount(&mock_feed)
+        .await;
+    settings.exchange_rates_source = Some(format!("{}/rates.csv", mock_feed.uri()));
+
+    // GO
+    let outcome = load_exchange_rates(&db_pool, &settings, &statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.succeeded, 3);
+    let eur = rates
+        .fetch_one_on_date("eur", &date!(2022 - 06 - 03))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(eur.rate, 1.0713);
+}
+
+#[tokio::test]
+async fn a_feed_that_is_too_slow_is_an_error() {
+    let mut settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let mock_feed = MockServer::start().await;
+    Mock::given(path("/rates.csv"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(RATES)
+                .set_delay(Duration::from_secs(3)),
+        )
+        .mount(&mock_feed)
+        .await;
+    settings.exchange_rates_source = Some(format!("{}/rates.csv", mock_feed.uri()));
+    settings.exchange_rates_timeout_seconds = 1;
+    match load_exchange_rates(&db_pool, &settings, &statsd, false).await {
+        Err(CjmsError::Configuration(_)) => {}
+        other => panic!(
+            "Expected a configuration error, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
+
+#[tokio::test]
+async fn a_source_that_cannot_be_read_is_an_error() {
+    let mut settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    // Nothing to load without a source
+    let outcome = load_exchange_rates(&db_pool, &settings, &statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.processed(), 0);
+    settings.exchange_rates_source = Some("/no/such/rates.csv".to_string());
+    match load_exchange_rates(&db_pool, &settings, &statsd, false).await {
+        Err(CjmsError::Configuration(_)) => {}
+        other => panic!(
+            "Expected a configuration error, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
@@ -1,6 +1,9 @@
+mod affiliate_network;
 mod batch_refunds;
 mod check_refunds;
 mod check_subscriptions;
 mod cleanup;
+mod load_exchange_rates;
+mod reconcile;
 mod report_subscriptions;
 mod verify_reports;
@@ -0,0 +1,235 @@
+use lib::{
+    jobs::reconcile::{find_discrepancies, reconcile, DiscrepancyKind},
+    models::{
+        commissions::{Commission, CommissionModel},
+        refunds::{Refund, RefundModel},
+        status_history::{Status, UpdateStatus},
+        subscriptions::{Subscription, SubscriptionModel},
+    },
+    settings::get_settings,
+    telemetry::StatsD,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    models::{
+        commissions::make_fake_commission,
+        refunds::{make_fake_refund, save_refund},
+        subscriptions::{make_fake_sub, save_sub},
+    },
+    utils::get_test_db_pool,
+};
+
+// Kind, order id, commission ids and refund id
+type Found = (DiscrepancyKind, Option<String>, Vec<String>, Option<String>);
+
+// A record for the subscription, checked by verify_reports with the given reasons
+fn make_checked_commission(
+    commission_id: &str,
+    sub: &Subscription,
+    mismatch_reasons: serde_json::Value,
+) -> Commission {
+    let mut commission = make_fake_commission(commission_id, &sub.id.to_string());
+    commission.mismatch_reasons = Some(mismatch_reasons);
+    commission
+}
+
+fn make_refund_for(sub: &Subscription) -> Refund {
+    let mut refund = make_fake_refund();
+    refund.subscription_id = sub.subscription_id.clone();
+    refund
+}
+
+#[tokio::test]
+async fn reconcile_finds_each_kind_of_discrepancy() {
+    // SETUP
+    let settings = get_settings();
+    let statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let refund_model = RefundModel { db_pool: &db_pool };
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    let mut commissions = vec![];
+    let mut refunds = vec![];
+    let amount_off = json!([{"reason": "amount", "expected": 5988, "found": 5989, "tolerance": 0}]);
+
+    // Matches
+    let sub_ok = make_fake_sub();
+    commissions.push(make_checked_commission("1", &sub_ok, json!([])));
+    // verify_reports found it didn't match
+    let sub_mismatch = make_fake_sub();
+    commissions.push(make_checked_commission(
+        "2",
+        &sub_mismatch,
+        amount_off.clone(),
+    ));
+    // Not checked by verify_reports
+    let sub_unchecked = make_fake_sub();
+    commissions.push(make_fake_commission("3", &sub_unchecked.id.to_string()));
+    // Two originals
+    let sub_duplicate = make_fake_sub();
+    commissions.push(make_checked_commission("4", &sub_duplicate, json!([])));
+    commissions.push(make_checked_commission("5", &sub_duplicate, json!([])));
+    // Not ours
+    commissions.push(make_fake_commission("6", "not-an-order-of-ours"));
+    // Refunded and corrected, but the correction doesn't match
+    let sub_refunded = make_fake_sub();
+    let refund = make_refund_for(&sub_refunded);
+    commissions.push(make_checked_commission("7", &sub_refunded, json!([])));
+    let mut correction = make_checked_commission("8", &sub_refunded, amount_off);
+    correction.original = false;
+    commissions.push(correction);
+    // Corrected without a refund
+    let sub_not_refunded = make_fake_sub();
+    let mut correction = make_checked_commission("9", &sub_not_refunded, json!([]));
+    correction.original = false;
+    commissions.push(correction);
+    // One correction for two refunds
+    let sub_refunded_twice = make_fake_sub();
+    commissions.push(make_checked_commission(
+        "10",
+        &sub_refunded_twice,
+        json!([]),
+    ));
+    let mut correction = make_checked_commission("11", &sub_refunded_twice, json!([]));
+    correction.original = false;
+    commissions.push(correction);
+    refunds.push(make_refund_for(&sub_refunded_twice));
+    refunds.push(make_refund_for(&sub_refunded_twice));
+    // Reported too long ago and not verified, and reported recently
+    let mut sub_stale = make_fake_sub();
+    sub_stale.update_status(Status::Reported);
+    sub_stale.set_status_t(Some(OffsetDateTime::now_utc() - Duration::hours(40)));
+    let mut sub_fresh = make_fake_sub();
+    sub_fresh.update_status(Status::Reported);
+
+    for sub in [
+        &sub_ok,
+        &sub_mismatch,
+        &sub_unchecked,
+        &sub_duplicate,
+        &sub_refunded,
+        &sub_not_refunded,
+        &sub_refunded_twice,
+        &sub_stale,
+        &sub_fresh,
+    ] {
+        save_sub(&sub_model, sub).await;
+    }
+    save_refund(&refund_model, &refund).await;
+    for refund in &refunds {
+        save_refund(&refund_model, refund).await;
+    }
+    for commission in &commissions {
+        commission_model.upsert(commission).await.unwrap();
+    }
+
+    // GO
+    let outcome = reconcile(&db_pool, &settings, &statsd).await.unwrap();
+    let now = OffsetDateTime::now_utc();
+    let reconciliation = find_discrepancies(&db_pool, &settings, &(now - Duration::days(1)), &now)
+        .await
+        .unwrap();
+
+    // ASSERT
+    // 8 orders and 2 reported subscriptions
+    assert_eq!(reconciliation.checked, 10);
+    assert_eq!(outcome.failed, 7);
+    assert_eq!(outcome.succeeded, 3);
+    let found: Vec<Found> = reconciliation
+        .discrepancies
+        .iter()
+        .map(|d| {
+            (
+                d.kind,
+                d.order_id.clone(),
+                d.commission_ids.clone(),
+                d.refund_id.clone(),
+            )
+        })
+        .collect();
+    for expected in [
+        (DiscrepancyKind::Mismatch, &sub_mismatch, vec!["2"], None),
+        (
+            DiscrepancyKind::Duplicate,
+            &sub_duplicate,
+            vec!["4", "5"],
+            None,
+        ),
+        (
+            DiscrepancyKind::Mismatch,
+            &sub_refunded,
+            vec!["8"],
+            Some(refund.refund_id.clone()),
+        ),
+        (DiscrepancyKind::Orphan, &sub_not_refunded, vec!["9"], None),
+        (
+            DiscrepancyKind::Ambiguous,
+            &sub_refunded_twice,
+            vec!["11"],
+            None,
+        ),
+        (DiscrepancyKind::Stale, &sub_stale, vec![], None),
+    ] {
+        let (kind, sub, ids, refund_id) = expected;
+        let expected = (
+            kind,
+            Some(sub.id.to_string()),
+            ids.iter().map(|id| id.to_string()).collect(),
+            refund_id,
+        );
+        assert!(found.contains(&expected), "Missing {:?}", expected);
+    }
+    assert!(found.contains(&(
+        DiscrepancyKind::Orphan,
+        Some("not-an-order-of-ours".to_string()),
+        vec!["6".to_string()],
+        None
+    )));
+    let mismatch = reconciliation
+        .discrepancies
+        .iter()
+        .find(|d| d.commission_ids == vec!["2".to_string()])
+        .unwrap();
+    assert_eq!(
+        mismatch.cause,
+        r#"verify_reports found: [{"expected":5988,"found":5989,"reason":"amount","tolerance":0}]"#
+    );
+}
+
+#[tokio::test]
+async fn reconcile_only_checks_orders_posted_in_the_range() {
+    let settings = get_settings();
+    let db_pool = get_test_db_pool().await;
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    let now = OffsetDateTime::now_utc();
+
+    // Posted before the range
+    let mut commission = make_fake_commission("1", "old-order");
+    commission.posting_date = now - Duration::days(40);
+    commission_model.upsert(&commission).await.unwrap();
+    // The original is old but the correction is in range, so both are checked
+    let mut original = make_fake_commission("2", "corrected-order");
+    original.posting_date = now - Duration::days(40);
+    commission_model.upsert(&original).await.unwrap();
+    let mut correction = make_fake_commission("3", "corrected-order");
+    correction.original = false;
+    commission_model.upsert(&correction).await.unwrap();
+
+    let reconciliation = find_discrepancies(&db_pool, &settings, &(now - Duration::days(30)), &now)
+        .await
+        .unwrap();
+    assert_eq!(reconciliation.checked, 1);
+    assert_eq!(reconciliation.discrepancies.len(), 1);
+    assert_eq!(
+        reconciliation.discrepancies[0].commission_ids,
+        vec!["2".to_string(), "3".to_string()]
+    );
+
+    let reconciliation = find_discrepancies(&db_pool, &settings, &(now - Duration::days(50)), &now)
+        .await
+        .unwrap();
+    assert_eq!(reconciliation.checked, 2);
+}
@@ -1,10 +1,13 @@
 use lib::{
-    cj::{client::CJClient, country_codes::get_iso_code_3_from_iso_code_2},
-    jobs::report_subscriptions::report_subscriptions_to_cj,
+    cj::{client::CJClient, country_codes::get_iso_code_3_from_iso_code_2, programs::CJProgram},
+    coupons::{CouponRule, CouponRules},
+    jobs::report_subscriptions::report_subscriptions,
     models::{
         status_history::{Status, StatusHistoryEntry, UpdateStatus},
         subscriptions::SubscriptionModel,
     },
+    money::Money,
+    plans::{Plan, PlanCatalog},
     settings::{get_settings, Settings},
     telemetry::StatsD,
 };
@@ -27,7 +30,7 @@ fn when_sending_to_cj(settings: &Settings) -> MockBuilder {
 }
 
 #[tokio::test]
-async fn report_subscriptions() {
+async fn report_subscriptions_reports_or_skips_each_not_reported_subscription() {
     // SETUP
 
     let settings = get_settings();
@@ -110,11 +113,11 @@ async fn report_subscriptions() {
             (sub_3.subscription_created + random_minutes).format(format_str),
         ))
         .and(query_param("OID", sub_3.id.to_string()))
-        .and(query_param("CURRENCY", sub_3.plan_currency))
+        .and(query_param("CURRENCY", &sub_3.plan_currency))
         .and(query_param("ITEM1", sub_3.plan_id))
         .and(query_param(
             "AMT1",
-            format!("{}", sub_3.plan_amount as f32 / 100.0),
+            Money::new(sub_3.plan_amount.into(), &sub_3.plan_currency).to_decimal_string(),
         ))
         .and(query_param("QTY1", format!("{}", sub_3.quantity)))
         .and(query_param(
@@ -133,11 +136,11 @@ async fn report_subscriptions() {
             (sub_4.subscription_created + random_minutes).format(format_str),
         ))
         .and(query_param("OID", sub_4.id.to_string()))
-        .and(query_param("CURRENCY", sub_4.plan_currency))
+        .and(query_param("CURRENCY", &sub_4.plan_currency))
         .and(query_param("ITEM1", sub_4.plan_id))
         .and(query_param(
             "AMT1",
-            format!("{}", sub_4.plan_amount as f32 / 100.0),
+            Money::new(sub_4.plan_amount.into(), &sub_4.plan_currency).to_decimal_string(),
         ))
         .and(query_param("QTY1", format!("{}", sub_4.quantity)))
         .and(query_param("CUST_COUNTRY", "N/A"))
@@ -164,18 +167,23 @@ async fn report_subscriptions() {
             "CUST_COUNTRY",
             get_iso_code_3_from_iso_code_2(sub_6.country.as_ref().unwrap()),
         ))
-        .and(query_param("COUPON", sub_6.coupons.unwrap()))
+        .and(query_param("COUPON", sub_6.coupons.join(",")))
         .respond_with(ResponseTemplate::new(200))
         .up_to_n_times(1)
         .expect(1)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, Some(&mock_cj.uri()), None, Some(random_minutes));
+    let mock_cj_client =
+        CJClient::new(&settings, Some(&mock_cj.uri()), None, Some(random_minutes)).unwrap();
 
     // GO
     std::thread::sleep(std::time::Duration::from_secs(2));
-    let now = OffsetDateTime::now_utc();
-    report_subscriptions_to_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    let outcome = report_subscriptions(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+    // Subs 1, 4, 6 reported, subs 2, 5 marked WillNotReport, sub 3 failed
+    assert_eq!(outcome.succeeded, 5);
+    assert_eq!(outcome.failed, 1);
 
     // ASSERT
 
@@ -213,7 +221,8 @@ async fn report_subscriptions() {
             updated_history.entries[1],
             StatusHistoryEntry {
                 status: Status::Reported,
-                t: now
+                t: report_sub.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -225,11 +234,15 @@ async fn report_subscriptions() {
         sub_3_updated_history.entries[1],
         StatusHistoryEntry {
             status: Status::NotReported,
-            t: now
+            t: sub_3_updated.get_status_t().unwrap(),
+            reason: None,
         }
     );
 
-    for will_not_report_sub in [&sub_2_updated, &sub_5_updated] {
+    for (will_not_report_sub, reason) in [
+        (&sub_2_updated, "AIC expired before subscription created"),
+        (&sub_5_updated, "No AIC expiry"),
+    ] {
         println!("Testing sub: {}", will_not_report_sub.flow_id);
         assert_eq!(
             will_not_report_sub.get_status().unwrap(),
@@ -241,8 +254,247 @@ async fn report_subscriptions() {
             updated_history.entries[1],
             StatusHistoryEntry {
                 status: Status::WillNotReport,
-                t: now
+                t: will_not_report_sub.get_status_t().unwrap(),
+                reason: Some(reason.to_string()),
             }
         );
     }
 }
+
+#[tokio::test]
+async fn report_subscriptions_dry_run_sends_and_writes_nothing() {
+    let settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+
+    // Would be reported
+    let mut sub_1 = make_fake_sub();
+    sub_1.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    // Would be marked WillNotReport
+    let mut sub_2 = make_fake_sub();
+    sub_2.aic_expires = None;
+    for sub in [&sub_1, &sub_2] {
+        sub_model
+            .create_from_sub(sub)
+            .await
+            .expect("Failed to create sub.");
+    }
+    let mock_cj = MockServer::start().await;
+    when_sending_to_cj(&settings)
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, Some(&mock_cj.uri()), None, None).unwrap();
+
+    report_subscriptions(&db_pool, &mock_cj_client, &settings, &mock_statsd, true)
+        .await
+        .unwrap();
+
+    for sub in [&sub_1, &sub_2] {
+        let unchanged = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+        assert_eq!(unchanged.get_status(), Some(Status::NotReported));
+        assert_eq!(unchanged.get_status_history().unwrap().entries.len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn report_subscriptions_uses_the_plan_catalog() {
+    let mut settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+
+    // Reported as its catalog SKU
+    let mut sub_1 = make_fake_sub();
+    sub_1.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    // On an internal plan, so marked WillNotReport
+    let mut sub_2 = make_fake_sub();
+    sub_2.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    settings.plans = PlanCatalog(vec![
+        Plan {
+            plan_id: sub_1.plan_id.clone(),
+            cj_sku: Some("vpn-monthly".to_string()),
+            product_name: Some("VPN monthly".to_string()),
+            eligible: true,
+            ineligible_reason: None,
+            cj_program: None,
+        },
+        Plan {
+            plan_id: sub_2.plan_id.clone(),
+            cj_sku: None,
+            product_name: None,
+            eligible: false,
+            ineligible_reason: Some("internal".to_string()),
+            cj_program: None,
+        },
+    ]);
+    for sub in [&sub_1, &sub_2] {
+        sub_model
+            .create_from_sub(sub)
+            .await
+            .expect("Failed to create sub.");
+    }
+    let mock_cj = MockServer::start().await;
+    when_sending_to_cj(&settings)
+        .and(query_param("OID", sub_1.id.to_string()))
+        .and(query_param("ITEM1", "vpn-monthly"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_cj)
+        .await;
+    when_sending_to_cj(&settings)
+        .and(query_param("OID", sub_2.id.to_string()))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, Some(&mock_cj.uri()), None, None).unwrap();
+
+    let outcome = report_subscriptions(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.succeeded, 2);
+
+    let sub_1_updated = sub_model.fetch_one_by_id(&sub_1.id).await.unwrap();
+    assert_eq!(sub_1_updated.get_status(), Some(Status::Reported));
+    let sub_2_updated = sub_model.fetch_one_by_id(&sub_2.id).await.unwrap();
+    assert_eq!(sub_2_updated.get_status(), Some(Status::WillNotReport));
+    assert_eq!(
+        sub_2_updated.get_status_history().unwrap().entries[1].reason,
+        Some("Plan not eligible: internal".to_string())
+    );
+}
+
+#[tokio::test]
+async fn report_subscriptions_uses_the_routed_programs_credentials() {
+    let mut settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+
+    let mut relay_sub = make_fake_sub();
+    relay_sub.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    let mut vpn_sub = make_fake_sub();
+    vpn_sub.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    settings.cj_programs = vec![CJProgram {
+        name: "relay".to_string(),
+        cj_cid: "relay-cid".to_string(),
+        cj_type: "relay-type".to_string(),
+        cj_signature: "relay-signature".to_string(),
+        cj_sftp_user: "relay-advertiser".to_string(),
+        cj_subid: "relay-subid".to_string(),
+        cj_api_access_token: None,
+        products: vec![],
+    }];
+    settings.plans = PlanCatalog(vec![Plan {
+        plan_id: relay_sub.plan_id.clone(),
+        cj_sku: None,
+        product_name: None,
+        eligible: true,
+        ineligible_reason: None,
+        cj_program: Some("relay".to_string()),
+    }]);
+    for sub in [&relay_sub, &vpn_sub] {
+        sub_model
+            .create_from_sub(sub)
+            .await
+            .expect("Failed to create sub.");
+    }
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .and(method("GET"))
+        .and(query_param("CID", "relay-cid"))
+        .and(query_param("TYPE", "relay-type"))
+        .and(query_param("SIGNATURE", "relay-signature"))
+        .and(query_param("OID", relay_sub.id.to_string()))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_cj)
+        .await;
+    when_sending_to_cj(&settings)
+        .and(query_param("OID", vpn_sub.id.to_string()))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, Some(&mock_cj.uri()), None, None).unwrap();
+
+    let outcome = report_subscriptions(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.succeeded, 2);
+}
+
+#[tokio::test]
+async fn report_subscriptions_applies_coupon_rules() {
+    let mut settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+
+    // Used an employee code along with another, so marked WillNotReport
+    let mut sub_1 = make_fake_sub();
+    sub_1.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    sub_1.coupons = vec!["SPRING".to_string(), "Employee".to_string()];
+    // Used a partner code, so reported at its amount as its action type
+    let mut sub_2 = make_fake_sub();
+    sub_2.aic_expires = Some(OffsetDateTime::now_utc() + Duration::days(10));
+    sub_2.plan_currency = "usd".to_string();
+    sub_2.coupons = vec!["SPRING".to_string(), "PARTNER".to_string()];
+    settings.coupon_rules = CouponRules(vec![
+        CouponRule {
+            code: "EMPLOYEE".to_string(),
+            exclude: true,
+            reason: Some("employee".to_string()),
+            amount: None,
+            cj_type: None,
+        },
+        CouponRule {
+            code: "PARTNER".to_string(),
+            exclude: false,
+            reason: None,
+            amount: Some(100),
+            cj_type: Some("partner-type".to_string()),
+        },
+    ]);
+    for sub in [&sub_1, &sub_2] {
+        sub_model
+            .create_from_sub(sub)
+            .await
+            .expect("Failed to create sub.");
+    }
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .and(query_param("OID", sub_1.id.to_string()))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&mock_cj)
+        .await;
+    Mock::given(path("/"))
+        .and(query_param("CID", &settings.cj_cid))
+        .and(query_param("TYPE", "partner-type"))
+        .and(query_param("OID", sub_2.id.to_string()))
+        .and(query_param("AMT1", "1.00"))
+        .and(query_param("COUPON", "SPRING,PARTNER"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, Some(&mock_cj.uri()), None, None).unwrap();
+
+    let outcome = report_subscriptions(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome.succeeded, 2);
+
+    let sub_1_updated = sub_model.fetch_one_by_id(&sub_1.id).await.unwrap();
+    assert_eq!(sub_1_updated.get_status(), Some(Status::WillNotReport));
+    assert_eq!(
+        sub_1_updated.get_status_history().unwrap().entries[1].reason,
+        Some("Coupon excluded: employee".to_string())
+    );
+    let sub_2_updated = sub_model.fetch_one_by_id(&sub_2.id).await.unwrap();
+    assert_eq!(sub_2_updated.get_status(), Some(Status::Reported));
+}
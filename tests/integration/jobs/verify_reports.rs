@@ -3,20 +3,24 @@ use crate::{
     utils::get_test_db_pool,
 };
 use lib::{
-    cj::client::{convert_amount_to_decimal, CJClient},
-    jobs::verify_reports::verify_reports_with_cj,
+    cj::{client::CJClient, programs::CJProgram},
+    jobs::verify_reports::verify_reports,
     models::{
+        commissions::CommissionModel,
+        exchange_rates::{ExchangeRate, ExchangeRateModel},
         refunds::{Refund, RefundModel},
         status_history::{Status, StatusHistoryEntry, UpdateStatus},
         subscriptions::{Subscription, SubscriptionModel},
     },
+    money::Money,
+    plans::{Plan, PlanCatalog},
     settings::{get_settings, Settings},
     telemetry::StatsD,
 };
 use serde_json::{json, Value};
-use time::{Duration, OffsetDateTime};
+use time::{date, Duration, OffsetDateTime};
 use wiremock::{
-    matchers::{body_json, header, method, path},
+    matchers::{body_json, body_string_contains, header, method, path},
     Mock, MockServer, ResponseTemplate,
 };
 
@@ -37,8 +41,9 @@ struct VerifyReportsTestSetup {
     response_body: Value,
 }
 
-fn make_refund_amount(amount: i32) -> f32 {
-    (-1.00 * convert_amount_to_decimal(amount)) as f32
+// As CJ sends it, in the publisher currency
+fn decimal(amount: i32) -> String {
+    Money::new(amount.into(), "usd").to_decimal_string()
 }
 
 async fn setup_test(
@@ -173,13 +178,25 @@ async fn setup_test(
             beforePostingDate:"{}T00:00:00Z",
         ) {{
             count
+            payloadComplete
+            maxCommissionId
             records {{
+                commissionId
+                postingDate
                 original
                 orderId
                 correctionReason
                 saleAmountPubCurrency
+                pubCommissionAmountPubCurrency
+                actionStatus
+                validationStatus
+                publisherId
+                coupon
                 items {{
                     sku
+                    quantity
+                    perItemSaleAmountPubCurrency
+                    discountPubCurrency
                 }}
             }}
         }}}}"#,
@@ -195,10 +212,12 @@ async fn setup_test(
                     "count": 18,
                     "records": [
                         {
+                            "commissionId": "1",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": sub_1.id,
                             "correctionReason": null,
-                            "saleAmountPubCurrency": convert_amount_to_decimal(sub_1.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(sub_1.plan_amount),
                             "items": [
                                 {
                                     "sku": sub_1.plan_id
@@ -207,10 +226,12 @@ async fn setup_test(
                         },
                         // This refund exists so that we can check that the subscription data correctly picks out original: true record above
                         {
+                            "commissionId": "2",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": false,
                             "orderId": sub_1.id,
                             "correctionReason": "RETURNED_MERCHANDISE",
-                            "saleAmountPubCurrency": make_refund_amount(sub_1.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(-sub_1.plan_amount),
                             "items": [
                                 {
                                     "sku": sub_1.plan_id
@@ -218,6 +239,8 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "3",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": sub_2.id,
                             "correctionReason": null,
@@ -229,10 +252,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "4",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": sub_3.id,
                             "correctionReason": null,
-                            "saleAmountPubCurrency": convert_amount_to_decimal(sub_3.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(sub_3.plan_amount),
                             "items": [
                                 {
                                     "sku": "WRONG SKU"
@@ -240,10 +265,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "5",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": "WRONGID",
                             "correctionReason": null,
-                            "saleAmountPubCurrency": convert_amount_to_decimal(sub_4.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(sub_4.plan_amount),
                             "items": [
                                 {
                                     "sku": sub_4.plan_id
@@ -251,10 +278,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "6",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": "WRONGID",
                             "correctionReason": null,
-                            "saleAmountPubCurrency": convert_amount_to_decimal(sub_5.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(sub_5.plan_amount),
                             "items": [
                                 {
                                     "sku": sub_5.plan_id
@@ -262,11 +291,13 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "7",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": sub_6.id,
                             "correctionReason": null,
                             // Adding an arbitrary amount because it was a euro purchase so we get a different amount back from CJ
-                            "saleAmountPubCurrency": (convert_amount_to_decimal(sub_6.plan_amount) + 1.11).to_string(),
+                            "saleAmountPubCurrency": decimal(sub_6.plan_amount + 111),
                             "items": [
                                 {
                                     "sku": sub_6.plan_id
@@ -279,10 +310,12 @@ async fn setup_test(
                         // ------------ REFUND ENTRIES
                         // This subscription exists so that we can check that the refund check correctly picks out original: false record
                         {
+                            "commissionId": "8",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": refund_1_sub.id,
                             "correctionReason": null,
-                            "saleAmountPubCurrency": convert_amount_to_decimal(refund_1_sub.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(refund_1_sub.plan_amount),
                             "items": [
                                 {
                                     "sku": refund_1_sub.plan_id
@@ -290,10 +323,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "9",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": false,
                             "orderId": refund_1_sub.id,
                             "correctionReason": "RETURNED_MERCHANDISE",
-                            "saleAmountPubCurrency": make_refund_amount(refund_1.refund_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(-refund_1.refund_amount),
                             "items": [
                                 {
                                     "sku": refund_1_sub.plan_id
@@ -301,6 +336,8 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "10",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": false,
                             "orderId": refund_2_sub.id,
                             "correctionReason": "RETURNED_MERCHANDISE",
@@ -312,10 +349,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "11",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": refund_3_sub.id,
                             "correctionReason": "RETURNED_MERCHANDISE",
-                            "saleAmountPubCurrency": make_refund_amount(refund_3.refund_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(-refund_3.refund_amount),
                             "items": [
                                 {
                                     "sku": "WRONG SKU"
@@ -323,10 +362,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "12",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": "WRONGID",
                             "correctionReason": "RETURNED_MERCHANDISE",
-                            "saleAmountPubCurrency": make_refund_amount(refund_4.refund_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(-refund_4.refund_amount),
                             "items": [
                                 {
                                     "sku": refund_4_sub.plan_id
@@ -334,10 +375,12 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "13",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": "WRONGID",
                             "correctionReason": "RETURNED_MERCHANDISE",
-                            "saleAmountPubCurrency": make_refund_amount(refund_5.refund_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(-refund_5.refund_amount),
                             "items": [
                                 {
                                     "sku": refund_5_sub.plan_id
@@ -345,11 +388,13 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "14",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": refund_6_sub.id,
                             "correctionReason": null,
                             // Adding an arbitrary amount because it was a euro purchase so we get a different amount back from CJ
-                            "saleAmountPubCurrency": (convert_amount_to_decimal(refund_6_sub.plan_amount) + 1.11).to_string(),
+                            "saleAmountPubCurrency": decimal(refund_6_sub.plan_amount + 111),
                             "items": [
                                 {
                                     "sku": refund_6_sub.plan_id
@@ -357,11 +402,13 @@ async fn setup_test(
                             ]
                         },
                         {
+                            "commissionId": "15",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": false,
                             "orderId": refund_6_sub.id,
                             "correctionReason": "RETURNED_MERCHANDISE",
                             // Adding an arbitrary amount because it was a euro purchase so we get a different amount back from CJ
-                            "saleAmountPubCurrency": (make_refund_amount(refund_6.refund_amount) + 1.11).to_string(),
+                            "saleAmountPubCurrency": decimal(-refund_6.refund_amount + 111),
                             "items": [
                                 {
                                     "sku": refund_6_sub.plan_id
@@ -392,14 +439,13 @@ async fn setup_test(
 }
 
 #[tokio::test]
-#[should_panic(expected = "Got no data from CJ. [{\"locations\":[{\"colu")]
 async fn test_when_cj_sends_errors() {
     let settings = get_settings();
     let mock_statsd = StatsD::new(&settings);
     let db_pool = get_test_db_pool().await;
     let sub_model = SubscriptionModel { db_pool: &db_pool };
     let refund_model = RefundModel { db_pool: &db_pool };
-    let _ = setup_test(&settings, &sub_model, &refund_model).await;
+    let setup = setup_test(&settings, &sub_model, &refund_model).await;
     let mock_cj = MockServer::start().await;
     let response_body = json!({
       "data": null,
@@ -424,10 +470,24 @@ async fn test_when_cj_sends_errors() {
         .expect(1)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None);
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
 
     // GO
-    verify_reports_with_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    let outcome = verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT - GraphQL errors aren't retried, everything is left to check next run
+    assert_eq!(outcome.succeeded, 0);
+    assert_eq!(outcome.failed, 0);
+    assert_eq!(outcome.skipped, outcome.processed());
+    let sub_1 = sub_model.fetch_one_by_id(&setup.sub_1.id).await.unwrap();
+    assert_eq!(sub_1.get_status().unwrap(), Status::Reported);
+    let refund_1 = refund_model
+        .fetch_one_by_refund_id(&setup.refund_1.refund_id)
+        .await
+        .unwrap();
+    assert_eq!(refund_1.get_status().unwrap(), Status::Reported);
 }
 
 #[tokio::test]
@@ -453,16 +513,17 @@ async fn test_correct_and_incorrectly_received_subscriptions_are_handled_correct
             "Authorization",
             format!("Bearer {}", settings.cj_api_access_token).as_str(),
         ))
-        .and(body_json(&json!({ "query": test_setup.required_query })))
+        .and(body_json(json!({ "query": test_setup.required_query })))
         .respond_with(response)
         .expect(1)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None);
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
 
     // GO
-    let now = OffsetDateTime::now_utc();
-    verify_reports_with_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
 
     // ASSERT
     let sub_1_updated = sub_model
@@ -499,7 +560,8 @@ async fn test_correct_and_incorrectly_received_subscriptions_are_handled_correct
             updated_history.entries[2],
             StatusHistoryEntry {
                 status: Status::CJReceived,
-                t: now
+                t: found_sub.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -512,7 +574,8 @@ async fn test_correct_and_incorrectly_received_subscriptions_are_handled_correct
             updated_history.entries[2],
             StatusHistoryEntry {
                 status: Status::CJNotReceived,
-                t: now
+                t: not_found_sub.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -546,16 +609,17 @@ async fn test_correct_and_incorrectly_received_refunds_are_handled_correctly() {
             "Authorization",
             format!("Bearer {}", settings.cj_api_access_token).as_str(),
         ))
-        .and(body_json(&json!({ "query": test_setup.required_query })))
+        .and(body_json(json!({ "query": test_setup.required_query })))
         .respond_with(response)
         .expect(1)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None);
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
 
     // GO
-    let now = OffsetDateTime::now_utc();
-    verify_reports_with_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
 
     // ASSERT
     let refund_1_updated = refund_model
@@ -592,7 +656,8 @@ async fn test_correct_and_incorrectly_received_refunds_are_handled_correctly() {
             updated_history.entries[2],
             StatusHistoryEntry {
                 status: Status::CJReceived,
-                t: now
+                t: found_refund.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -608,7 +673,8 @@ async fn test_correct_and_incorrectly_received_refunds_are_handled_correctly() {
             updated_history.entries[2],
             StatusHistoryEntry {
                 status: Status::CJNotReceived,
-                t: now
+                t: not_found_refund.get_status_t().unwrap(),
+                reason: None,
             }
         );
     }
@@ -640,10 +706,12 @@ async fn test_correct_when_only_one_sub() {
                     "count": 1,
                     "records": [
                         {
+                            "commissionId": "16",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": true,
                             "orderId": sub_1.id,
                             "correctionReason": null,
-                            "saleAmountPubCurrency": convert_amount_to_decimal(sub_1.plan_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(sub_1.plan_amount),
                             "items": [
                                 {
                                     "sku": sub_1.plan_id
@@ -662,10 +730,12 @@ async fn test_correct_when_only_one_sub() {
         .expect(1)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None);
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
 
     // GO
-    verify_reports_with_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
 
     // ASSERT
     let sub_1_updated = sub_model
@@ -703,10 +773,12 @@ async fn test_correct_when_only_one_refund() {
                     "count": 1,
                     "records": [
                         {
+                            "commissionId": "17",
+                            "postingDate": "2022-06-01T12:00:00Z",
                             "original": false,
                             "orderId": related_sub.id,
                             "correctionReason": "RETURNED_MERCHANDISE",
-                            "saleAmountPubCurrency": make_refund_amount(refund_1.refund_amount).to_string(),
+                            "saleAmountPubCurrency": decimal(-refund_1.refund_amount),
                             "items": [
                                 {
                                     "sku": related_sub.plan_id
@@ -725,10 +797,12 @@ async fn test_correct_when_only_one_refund() {
         .expect(1)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None);
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
 
     // GO
-    verify_reports_with_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
 
     // ASSERT
     let refund_1_updated = refund_model
@@ -761,8 +835,618 @@ async fn test_graceful_exit_when_nothing_to_check() {
         .expect(0)
         .mount(&mock_cj)
         .await;
-    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None);
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_cj_outage_leaves_reports_to_check_next_run() {
+    // SETUP
+    let mut settings = get_settings();
+    settings.cj_max_retries = 1;
+    settings.cj_retry_base_delay_ms = 1;
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let mut sub = make_fake_sub();
+    sub.update_status(Status::Reported);
+    sub_model
+        .create_from_sub(&sub)
+        .await
+        .expect("Failed to create sub.");
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
 
     // GO
-    verify_reports_with_cj(&db_pool, &mock_cj_client, &mock_statsd).await;
+    let outcome = verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.skipped, 1);
+    assert_eq!(outcome.failed, 0);
+    let unchanged = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+    assert_eq!(unchanged.get_status().unwrap(), Status::Reported);
+}
+
+#[tokio::test]
+async fn test_every_commission_record_is_kept() {
+    // SETUP
+    let settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let refund_model = RefundModel { db_pool: &db_pool };
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    let test_setup = setup_test(&settings, &sub_model, &refund_model).await;
+    let sub_1 = test_setup.sub_1;
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(test_setup.response_body))
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO - a dry run doesn't keep them
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, true)
+        .await
+        .unwrap();
+    assert!(commission_model
+        .fetch_all_by_order_id(&sub_1.id.to_string())
+        .await
+        .unwrap()
+        .is_empty());
+
+    // GO - twice, to check CJ returning a record again updates it
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+    let first_seen = commission_model
+        .fetch_one_by_commission_id("1")
+        .await
+        .unwrap()
+        .first_seen;
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    let commissions = commission_model
+        .fetch_all_by_order_id(&sub_1.id.to_string())
+        .await
+        .unwrap();
+    assert_eq!(commissions.len(), 2);
+    let original = &commissions[0];
+    assert_eq!(original.commission_id, "1");
+    assert!(original.original);
+    assert_eq!(original.sale_amount, i64::from(sub_1.plan_amount));
+    assert_eq!(original.skus, vec![sub_1.plan_id.clone()]);
+    assert_eq!(original.first_seen, first_seen);
+    assert!(original.last_seen > first_seen);
+    let correction = &commissions[1];
+    assert!(!correction.original);
+    assert_eq!(
+        correction.correction_reason,
+        Some("RETURNED_MERCHANDISE".to_string())
+    );
+}
+
+fn empty_cj_response() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!(
+        {"data":
+            {"advertiserCommissions":
+                {
+                    "count": 0,
+                    "records": []
+                }
+            }
+        }
+    ))
+}
+
+#[tokio::test]
+async fn test_unreceived_subscriptions_are_re_reported_up_to_max_re_reports() {
+    // SETUP
+    let mut settings = get_settings();
+    settings.max_re_reports = 1;
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let now = OffsetDateTime::now_utc();
+    let mut sub = make_fake_sub();
+    sub.update_status(Status::Reported);
+    // Re-reported once already, so out of re-reports
+    let mut re_reported_sub = make_fake_sub();
+    re_reported_sub.update_status(Status::Reported);
+    re_reported_sub.update_status(Status::CJNotReceived);
+    re_reported_sub.update_status(Status::NotReported);
+    re_reported_sub.update_status(Status::Reported);
+    re_reported_sub.re_report_count = 1;
+    // Set to CJNotReceived and back by hand, which isn't a re-report
+    let mut overridden_sub = make_fake_sub();
+    overridden_sub.update_status(Status::Reported);
+    overridden_sub.update_status(Status::CJNotReceived);
+    overridden_sub.update_status(Status::Reported);
+    let overridden_sub_id = overridden_sub.id;
+    for mut sub in [sub, re_reported_sub, overridden_sub] {
+        sub.set_status_t(Some(now - Duration::hours(48)));
+        sub_model
+            .create_from_sub(&sub)
+            .await
+            .expect("Failed to create sub.");
+    }
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(empty_cj_response())
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    let outcome = verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.succeeded, 3);
+    let subs = sub_model.fetch_all().await.unwrap();
+    let (re_reported, not_received): (Vec<&Subscription>, Vec<&Subscription>) = subs
+        .iter()
+        .partition(|s| s.get_status() == Some(Status::NotReported));
+    assert_eq!(re_reported.len(), 2);
+    for sub in re_reported {
+        let history = sub.get_status_history().unwrap();
+        let statuses: Vec<Status> = history.entries.into_iter().map(|e| e.status).collect();
+        assert_eq!(
+            statuses[statuses.len() - 2..],
+            [Status::CJNotReceived, Status::NotReported]
+        );
+        assert_eq!(sub.re_report_count, 1);
+    }
+    assert!(subs
+        .iter()
+        .any(|s| s.id == overridden_sub_id && s.get_status() == Some(Status::NotReported)));
+    assert_eq!(not_received.len(), 1);
+    assert_eq!(not_received[0].get_status(), Some(Status::CJNotReceived));
+    assert_eq!(not_received[0].re_report_count, 1);
+}
+
+#[tokio::test]
+async fn test_verify_window_is_set_in_settings() {
+    // SETUP
+    let mut settings = get_settings();
+    settings.verify_window_hours = 72;
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let mut sub = make_fake_sub();
+    sub.update_status(Status::Reported);
+    sub.set_status_t(Some(OffsetDateTime::now_utc() - Duration::hours(48)));
+    sub_model
+        .create_from_sub(&sub)
+        .await
+        .expect("Failed to create sub.");
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(empty_cj_response())
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    let outcome = verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT - 48 hours is inside the window, so it's checked again next run
+    assert_eq!(outcome.skipped, 1);
+    let unchanged = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+    assert_eq!(unchanged.get_status(), Some(Status::Reported));
+}
+
+#[tokio::test]
+async fn test_quantity_discounts_and_tolerance_are_matched() {
+    // SETUP
+    let mut settings = get_settings();
+    settings.verify_amount_tolerance_cents = 1;
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    let make_sub = |quantity: i32, coupons: Option<&str>| {
+        let mut sub = make_fake_sub();
+        sub.update_status(Status::Reported);
+        sub.plan_currency = "usd".to_string();
+        sub.plan_amount = 1000;
+        sub.quantity = quantity;
+        sub.coupons = coupons.map(String::from).into_iter().collect();
+        sub
+    };
+    // Two seats with a 2.00 coupon discount
+    let discounted_sub = make_sub(2, Some("SPRING"));
+    // A cent out, within tolerance
+    let rounded_sub = make_sub(1, None);
+    // CJ recorded one seat of two
+    let wrong_quantity_sub = make_sub(2, None);
+    for sub in [&discounted_sub, &rounded_sub, &wrong_quantity_sub] {
+        sub_model.create_from_sub(sub).await.unwrap();
+    }
+    let record = |id: &str, sub: &Subscription, sale: &str, quantity: i32, discount: &str| {
+        json!({
+            "commissionId": id,
+            "postingDate": "2022-06-01T12:00:00Z",
+            "original": true,
+            "orderId": sub.id,
+            "saleAmountPubCurrency": sale,
+            "items": [{
+                "sku": sub.plan_id,
+                "quantity": quantity,
+                "perItemSaleAmountPubCurrency": "10.00",
+                "discountPubCurrency": discount,
+            }]
+        })
+    };
+    let response_body = json!({"data": {"advertiserCommissions": {
+        "count": 3,
+        "records": [
+            record("1", &discounted_sub, "18.00", 2, "2.00"),
+            record("2", &rounded_sub, "9.99", 1, "0.00"),
+            record("3", &wrong_quantity_sub, "10.00", 1, "0.00"),
+        ]
+    }}});
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    for sub in [&discounted_sub, &rounded_sub] {
+        let updated = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+        assert_eq!(updated.get_status(), Some(Status::CJReceived));
+    }
+    let not_matched = sub_model
+        .fetch_one_by_id(&wrong_quantity_sub.id)
+        .await
+        .unwrap();
+    assert_eq!(not_matched.get_status(), Some(Status::CJNotReceived));
+    let matched = commission_model
+        .fetch_one_by_commission_id("1")
+        .await
+        .unwrap();
+    assert_eq!(matched.mismatch_reasons, Some(json!([])));
+    let mismatched = commission_model
+        .fetch_one_by_commission_id("3")
+        .await
+        .unwrap();
+    assert_eq!(
+        mismatched.mismatch_reasons,
+        Some(json!([
+            {"reason": "quantity", "sku": wrong_quantity_sub.plan_id, "expected": 2, "found": 1},
+            {"reason": "amount", "expected": 2000, "found": 1000, "tolerance": 1},
+        ]))
+    );
+}
+
+#[tokio::test]
+async fn test_other_currencies_are_converted_at_the_posting_date_rate() {
+    // SETUP
+    let settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let rate_model = ExchangeRateModel { db_pool: &db_pool };
+    for (date, currency, rate) in [
+        (date!(2022 - 05 - 31), "eur", 1.07),
+        // More than max_exchange_rate_age_days before the posting date
+        (date!(2022 - 05 - 01), "gbp", 1.25),
+    ] {
+        rate_model
+            .upsert(&ExchangeRate {
+                date,
+                currency: currency.to_string(),
+                rate,
+                loaded: OffsetDateTime::now_utc(),
+            })
+            .await
+            .unwrap();
+    }
+    let make_sub = |currency: &str| {
+        let mut sub = make_fake_sub();
+        sub.update_status(Status::Reported);
+        sub.plan_currency = currency.to_string();
+        sub.plan_amount = 5000;
+        sub.coupons = vec![];
+        sub
+    };
+    let eur_sub = make_sub("eur");
+    let wrong_eur_sub = make_sub("EUR");
+    // No rate, so the amount can't be checked
+    let chf_sub = make_sub("chf");
+    // Only a stale rate, so the amount can't be checked either
+    let gbp_sub = make_sub("gbp");
+    for sub in [&eur_sub, &wrong_eur_sub, &chf_sub, &gbp_sub] {
+        sub_model.create_from_sub(sub).await.unwrap();
+    }
+    let record = |id: &str, sub: &Subscription, sale: &str| {
+        json!({
+            "commissionId": id,
+            "postingDate": "2022-06-01T12:00:00Z",
+            "original": true,
+            "orderId": sub.id,
+            "saleAmountPubCurrency": sale,
+            "items": [{"sku": sub.plan_id, "quantity": 1}]
+        })
+    };
+    // 50.00 EUR is 53.50 USD at our rate, CJ's was a little different
+    let response_body = json!({"data": {"advertiserCommissions": {
+        "count": 4,
+        "records": [
+            record("1", &eur_sub, "53.20"),
+            record("2", &wrong_eur_sub, "60.00"),
+            record("3", &chf_sub, "1.00"),
+            record("4", &gbp_sub, "1.00"),
+        ]
+    }}});
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    for (sub, status) in [
+        (&eur_sub, Status::CJReceived),
+        (&wrong_eur_sub, Status::CJNotReceived),
+        (&chf_sub, Status::CJReceived),
+        (&gbp_sub, Status::CJReceived),
+    ] {
+        let updated = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+        assert_eq!(updated.get_status(), Some(status));
+    }
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    let mismatched = commission_model
+        .fetch_one_by_commission_id("2")
+        .await
+        .unwrap();
+    assert_eq!(
+        mismatched.mismatch_reasons,
+        Some(json!([{"reason": "amount", "expected": 5350, "found": 6000, "tolerance": 107}]))
+    );
+}
+
+#[tokio::test]
+async fn test_exchange_rate_errors_fail_the_record_and_the_run_goes_on() {
+    // SETUP
+    let settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let make_sub = |currency: &str| {
+        let mut sub = make_fake_sub();
+        sub.update_status(Status::Reported);
+        sub.plan_currency = currency.to_string();
+        sub.plan_amount = 5000;
+        sub.coupons = vec![];
+        sub
+    };
+    let eur_sub = make_sub("eur");
+    let usd_sub = make_sub("usd");
+    for sub in [&eur_sub, &usd_sub] {
+        sub_model.create_from_sub(sub).await.unwrap();
+    }
+    // Looking up a rate fails
+    sqlx::query("DROP TABLE exchange_rates")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    let record = |id: &str, sub: &Subscription| {
+        json!({
+            "commissionId": id,
+            "postingDate": "2022-06-01T12:00:00Z",
+            "original": true,
+            "orderId": sub.id,
+            "saleAmountPubCurrency": "50.00",
+            "items": [{"sku": sub.plan_id, "quantity": 1}]
+        })
+    };
+    let response_body = json!({"data": {"advertiserCommissions": {
+        "count": 2,
+        "records": [record("1", &eur_sub), record("2", &usd_sub)]
+    }}});
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    let outcome = verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    assert_eq!(outcome.failed, 1);
+    assert_eq!(outcome.succeeded, 1);
+    // Checked again next run
+    let eur_updated = sub_model.fetch_one_by_id(&eur_sub.id).await.unwrap();
+    assert_eq!(eur_updated.get_status(), Some(Status::Reported));
+    let usd_updated = sub_model.fetch_one_by_id(&usd_sub.id).await.unwrap();
+    assert_eq!(usd_updated.get_status(), Some(Status::CJReceived));
+}
+
+#[tokio::test]
+async fn test_skus_are_matched_with_the_plan_catalog() {
+    // SETUP
+    let mut settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let make_sub = || {
+        let mut sub = make_fake_sub();
+        sub.update_status(Status::Reported);
+        sub.plan_currency = "usd".to_string();
+        sub.plan_id = "price_Monthly".to_string();
+        sub
+    };
+    settings.plans = PlanCatalog(vec![Plan {
+        plan_id: "price_Monthly".to_string(),
+        cj_sku: Some("vpn-monthly".to_string()),
+        product_name: None,
+        eligible: true,
+        ineligible_reason: None,
+        cj_program: None,
+    }]);
+    // CJ has the catalog SKU
+    let mapped_sub = make_sub();
+    // CJ has the plan id, which isn't what we report
+    let unmapped_sub = make_sub();
+    for sub in [&mapped_sub, &unmapped_sub] {
+        sub_model.create_from_sub(sub).await.unwrap();
+    }
+    let record = |id: &str, sub: &Subscription, sku: &str| {
+        json!({
+            "commissionId": id,
+            "postingDate": "2022-06-01T12:00:00Z",
+            "original": true,
+            "orderId": sub.id,
+            "saleAmountPubCurrency": decimal(sub.plan_amount * sub.quantity),
+            "items": [{"sku": sku, "quantity": sub.quantity}]
+        })
+    };
+    let response_body = json!({"data": {"advertiserCommissions": {
+        "count": 2,
+        "records": [
+            record("1", &mapped_sub, "vpn-monthly"),
+            record("2", &unmapped_sub, "price_Monthly"),
+        ]
+    }}});
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    let matched = sub_model.fetch_one_by_id(&mapped_sub.id).await.unwrap();
+    assert_eq!(matched.get_status(), Some(Status::CJReceived));
+    let not_matched = sub_model.fetch_one_by_id(&unmapped_sub.id).await.unwrap();
+    assert_eq!(not_matched.get_status(), Some(Status::CJNotReceived));
+}
+
+#[tokio::test]
+async fn test_each_program_is_queried_with_its_own_advertiser_and_token() {
+    // SETUP
+    let mut settings = get_settings();
+    let mock_statsd = StatsD::new(&settings);
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let make_sub = || {
+        let mut sub = make_fake_sub();
+        sub.update_status(Status::Reported);
+        sub.set_status_t(Some(OffsetDateTime::now_utc() - Duration::hours(48)));
+        sub.plan_currency = "usd".to_string();
+        sub
+    };
+    let relay_sub = make_sub();
+    let vpn_sub = make_sub();
+    settings.cj_programs = vec![CJProgram {
+        name: "relay".to_string(),
+        cj_cid: "relay-cid".to_string(),
+        cj_type: "relay-type".to_string(),
+        cj_signature: "relay-signature".to_string(),
+        cj_sftp_user: "relay-advertiser".to_string(),
+        cj_subid: "relay-subid".to_string(),
+        cj_api_access_token: Some("relay-token".to_string()),
+        products: vec![],
+    }];
+    settings.plans = PlanCatalog(vec![Plan {
+        plan_id: relay_sub.plan_id.clone(),
+        cj_sku: None,
+        product_name: None,
+        eligible: true,
+        ineligible_reason: None,
+        cj_program: Some("relay".to_string()),
+    }]);
+    for sub in [&relay_sub, &vpn_sub] {
+        sub_model.create_from_sub(sub).await.unwrap();
+    }
+    let response = |id: &str, sub: &Subscription| {
+        ResponseTemplate::new(200).set_body_json(json!({"data": {"advertiserCommissions": {
+            "count": 1,
+            "records": [{
+                "commissionId": id,
+                "postingDate": "2022-06-01T12:00:00Z",
+                "original": true,
+                "orderId": sub.id,
+                "saleAmountPubCurrency": decimal(sub.plan_amount * sub.quantity),
+                "items": [{"sku": sub.plan_id, "quantity": sub.quantity}]
+            }]
+        }}}))
+    };
+    let mock_cj = MockServer::start().await;
+    Mock::given(path("/"))
+        .and(header("Authorization", "Bearer relay-token"))
+        .and(body_string_contains("relay-advertiser"))
+        .respond_with(response("1", &relay_sub))
+        .expect(1)
+        .mount(&mock_cj)
+        .await;
+    Mock::given(path("/"))
+        .and(header(
+            "Authorization",
+            format!("Bearer {}", settings.cj_api_access_token).as_str(),
+        ))
+        .and(body_string_contains(settings.cj_sftp_user.as_str()))
+        .respond_with(response("2", &vpn_sub))
+        .expect(1)
+        .mount(&mock_cj)
+        .await;
+    let mock_cj_client = CJClient::new(&settings, None, Some(&mock_cj.uri()), None).unwrap();
+
+    // GO
+    verify_reports(&db_pool, &mock_cj_client, &settings, &mock_statsd, false)
+        .await
+        .unwrap();
+
+    // ASSERT
+    for sub in [&relay_sub, &vpn_sub] {
+        let verified = sub_model.fetch_one_by_id(&sub.id).await.unwrap();
+        assert_eq!(verified.get_status(), Some(Status::CJReceived));
+    }
+    let commission_model = CommissionModel { db_pool: &db_pool };
+    for id in ["1", "2"] {
+        assert!(commission_model
+            .fetch_one_by_commission_id(id)
+            .await
+            .is_ok());
+    }
 }
@@ -1,5 +1,9 @@
+mod admin;
 mod aic;
 mod appconfig;
+mod bq_emulator;
+mod cj_emulator;
+mod cjmsctl;
 mod corrections;
 mod custodial;
 mod jobs;
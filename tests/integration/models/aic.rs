@@ -166,6 +166,13 @@ async fn test_aic_archive_creates_and_deletes() {
         .await
         .expect("Could not retrieve from archive table");
     assert!(model.fetch_one_by_id(&aic.id).await.is_err());
+    // and remember when it was archived
+    let archived = model
+        .fetch_archived_t(&aic.id)
+        .await
+        .expect("Could not retrieve archived timestamp")
+        .expect("archived should be set");
+    assert!(OffsetDateTime::now_utc() - archived < Duration::minutes(1));
 }
 
 #[tokio::test]
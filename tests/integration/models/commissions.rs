@@ -0,0 +1,55 @@
+use lib::models::commissions::{Commission, CommissionModel};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+
+use crate::utils::get_test_db_pool;
+
+pub fn make_fake_commission(commission_id: &str, order_id: &str) -> Commission {
+    let now = OffsetDateTime::now_utc();
+    Commission {
+        commission_id: commission_id.to_string(),
+        order_id: order_id.to_string(),
+        original: true,
+        correction_reason: None,
+        posting_date: now - Duration::hours(1),
+        sale_amount: 5988,
+        commission_amount: Some(599),
+        action_status: Some("new".to_string()),
+        validation_status: Some("ACCEPTED".to_string()),
+        publisher_id: Some("1234567".to_string()),
+        skus: vec!["price_123".to_string()],
+        first_seen: now,
+        last_seen: now,
+        mismatch_reasons: None,
+    }
+}
+
+#[tokio::test]
+async fn test_upsert_updates_a_commission_and_keeps_first_seen() {
+    let db_pool = get_test_db_pool().await;
+    let model = CommissionModel { db_pool: &db_pool };
+    let mut commission = make_fake_commission("100", "order-1");
+    let mismatch_reasons = json!([{"reason": "missing_item", "sku": "price_123"}]);
+    commission.mismatch_reasons = Some(mismatch_reasons.clone());
+    let created = model
+        .upsert(&commission)
+        .await
+        .expect("Failed to create commission.");
+    assert_eq!(created.commission_amount, Some(599));
+
+    let mut locked = make_fake_commission("100", "order-1");
+    locked.action_status = Some("locked".to_string());
+    locked.first_seen = created.first_seen + Duration::days(1);
+    locked.last_seen = created.last_seen + Duration::days(1);
+    let updated = model.upsert(&locked).await.expect("Failed to update.");
+    assert_eq!(updated.action_status, Some("locked".to_string()));
+    assert_eq!(updated.first_seen, created.first_seen);
+    assert_eq!(updated.last_seen, locked.last_seen);
+    // Not checked this time, so the last reasons are kept
+    assert_eq!(updated.mismatch_reasons, Some(mismatch_reasons));
+    assert_eq!(
+        model.fetch_all_by_order_id("order-1").await.unwrap().len(),
+        1
+    );
+}
@@ -0,0 +1,50 @@
+use lib::models::job_locks::JobLockModel;
+
+use crate::utils::get_test_db_pool;
+
+#[tokio::test]
+async fn test_lock_is_exclusive_until_released() {
+    let db_pool = get_test_db_pool().await;
+    let model = JobLockModel { db_pool: &db_pool };
+    let name = format!("test-{}", uuid::Uuid::new_v4());
+    let lock = model
+        .try_lock(&name)
+        .await
+        .expect("Failed to try lock.")
+        .expect("Lock should be free.");
+    assert!(model.try_lock(&name).await.unwrap().is_none());
+    // Other names are unaffected
+    let other = model
+        .try_lock(&format!("{}-other", name))
+        .await
+        .unwrap()
+        .expect("Other lock should be free.");
+    lock.release().await.expect("Failed to release lock.");
+    let again = model
+        .try_lock(&name)
+        .await
+        .unwrap()
+        .expect("Lock should be free after release.");
+    again.release().await.unwrap();
+    other.release().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_lock_is_released_when_dropped() {
+    let db_pool = get_test_db_pool().await;
+    let model = JobLockModel { db_pool: &db_pool };
+    let name = format!("test-{}", uuid::Uuid::new_v4());
+    let lock = model.try_lock(&name).await.unwrap();
+    assert!(lock.is_some());
+    drop(lock);
+    // Postgres notices the closed connection asynchronously
+    let mut relocked = None;
+    for _ in 0..50 {
+        relocked = model.try_lock(&name).await.unwrap();
+        if relocked.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    relocked.expect("Lock should be free once the connection closes.");
+}
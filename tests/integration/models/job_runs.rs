@@ -0,0 +1,66 @@
+use lib::{jobs::outcome::JobOutcome, models::job_runs::JobRunModel};
+use pretty_assertions::assert_eq;
+
+use crate::utils::get_test_db_pool;
+
+#[tokio::test]
+async fn test_start_and_finish_records_counts() {
+    let db_pool = get_test_db_pool().await;
+    let model = JobRunModel { db_pool: &db_pool };
+    let started = model.start("cleanup").await.expect("Failed to start run.");
+    assert_eq!(started.job, "cleanup");
+    assert_eq!(started.ended, None);
+    assert_eq!(started.processed, 0);
+
+    let finished = model
+        .finish(
+            &started.id,
+            &JobOutcome {
+                succeeded: 3,
+                skipped: 2,
+                failed: 1,
+            },
+            None,
+        )
+        .await
+        .expect("Failed to finish run.");
+    assert!(finished.ended.is_some());
+    assert_eq!(finished.processed, 6);
+    assert_eq!(finished.succeeded, 3);
+    assert_eq!(finished.skipped, 2);
+    assert_eq!(finished.failed, 1);
+    assert_eq!(finished.error, None);
+    let fetched = model.fetch_one_by_id(&started.id).await.unwrap();
+    assert_eq!(fetched.processed, 6);
+}
+
+#[tokio::test]
+async fn test_last_success_skips_panicked_and_unfinished_runs() {
+    let db_pool = get_test_db_pool().await;
+    let model = JobRunModel { db_pool: &db_pool };
+    let ok = model.start("cleanup").await.unwrap();
+    model
+        .finish(&ok.id, &JobOutcome::default(), None)
+        .await
+        .unwrap();
+    let panicked = model.start("cleanup").await.unwrap();
+    model
+        .finish(
+            &panicked.id,
+            &JobOutcome::default(),
+            Some("boom".to_string()),
+        )
+        .await
+        .unwrap();
+    let running = model.start("cleanup").await.unwrap();
+    let other = model.start("batch_refunds").await.unwrap();
+
+    let last_runs = model.fetch_last_run_per_job().await.unwrap();
+    assert_eq!(last_runs.len(), 2);
+    assert_eq!(last_runs[0].id, other.id);
+    assert_eq!(last_runs[1].id, running.id);
+
+    let last_successes = model.fetch_last_success_per_job().await.unwrap();
+    assert_eq!(last_successes.len(), 1);
+    assert_eq!(last_successes[0].id, ok.id);
+}
@@ -1,3 +1,7 @@
 pub mod aic;
+pub mod commissions;
+pub mod job_locks;
+pub mod job_runs;
 pub mod refunds;
+pub mod status_overrides;
 pub mod subscriptions;
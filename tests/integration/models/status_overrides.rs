@@ -0,0 +1,98 @@
+use lib::models::{
+    refunds::RefundModel,
+    status_history::{Status, UpdateStatus},
+    status_overrides::{StatusOverrideError, StatusOverrideModel},
+    subscriptions::SubscriptionModel,
+};
+use pretty_assertions::assert_eq;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        refunds::{make_fake_refund, save_refund},
+        subscriptions::{make_fake_sub, save_sub},
+    },
+    utils::get_test_db_pool,
+};
+
+#[tokio::test]
+async fn test_override_subscription_status_records_audit_entry() {
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let model = StatusOverrideModel { db_pool: &db_pool };
+    let mut sub = make_fake_sub();
+    sub.update_status(Status::Reported);
+    save_sub(&sub_model, &sub).await;
+
+    let updated = model
+        .override_subscription_status(&sub.id, Status::CJReceived, "Confirmed by CJ", "ops")
+        .await
+        .expect("Failed to override status.");
+    assert_eq!(updated.get_status(), Some(Status::CJReceived));
+    let history = updated.get_status_history().unwrap();
+    assert_eq!(history.entries.len(), 3);
+    assert_eq!(sub_model.fetch_one_by_id(&sub.id).await.unwrap(), updated);
+
+    let entries = model.fetch_all_by_record_id(&sub.id).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].record_type, "Subscription");
+    assert_eq!(entries[0].from_status, Some("Reported".to_string()));
+    assert_eq!(entries[0].to_status, "CJReceived");
+    assert_eq!(entries[0].reason, "Confirmed by CJ");
+    assert_eq!(entries[0].operator, "ops");
+}
+
+#[tokio::test]
+async fn test_override_refund_status_records_audit_entry() {
+    let db_pool = get_test_db_pool().await;
+    let refund_model = RefundModel { db_pool: &db_pool };
+    let model = StatusOverrideModel { db_pool: &db_pool };
+    let refund = make_fake_refund();
+    save_refund(&refund_model, &refund).await;
+
+    let updated = model
+        .override_refund_status(&refund.refund_id, Status::WillNotReport, "Duplicate", "ops")
+        .await
+        .expect("Failed to override status.");
+    assert_eq!(updated.get_status(), Some(Status::WillNotReport));
+
+    let entries = model.fetch_all_by_record_id(&refund.id).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].record_type, "Refund");
+    assert_eq!(entries[0].from_status, Some("NotReported".to_string()));
+}
+
+#[tokio::test]
+async fn test_override_requires_reason_and_leaves_record_untouched() {
+    let db_pool = get_test_db_pool().await;
+    let sub_model = SubscriptionModel { db_pool: &db_pool };
+    let model = StatusOverrideModel { db_pool: &db_pool };
+    let sub = make_fake_sub();
+    save_sub(&sub_model, &sub).await;
+
+    let result = model
+        .override_subscription_status(&sub.id, Status::Reported, "", "ops")
+        .await;
+    assert!(matches!(result, Err(StatusOverrideError::MissingReason)));
+    assert_eq!(sub_model.fetch_one_by_id(&sub.id).await.unwrap(), sub);
+    assert!(model
+        .fetch_all_by_record_id(&sub.id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_override_missing_record_writes_no_audit_entry() {
+    let db_pool = get_test_db_pool().await;
+    let model = StatusOverrideModel { db_pool: &db_pool };
+    let id = Uuid::new_v4();
+    let result = model
+        .override_subscription_status(&id, Status::Reported, "Why not", "ops")
+        .await;
+    assert!(matches!(
+        result,
+        Err(StatusOverrideError::Database(sqlx::Error::RowNotFound))
+    ));
+    assert!(model.fetch_all_by_record_id(&id).await.unwrap().is_empty());
+}
@@ -27,7 +27,7 @@ impl TestApp {
 async fn create_test_database(database_url: &str) -> String {
     let randomized_test_database_url = format!("{}_test_{}", database_url, Uuid::new_v4());
     let url_parts: Vec<&str> = randomized_test_database_url.rsplit('/').collect();
-    let database_name = url_parts.get(0).unwrap().to_string();
+    let database_name = url_parts.first().unwrap().to_string();
     let mut connection = PgConnection::connect(database_url)
         .await
         .expect("Failed to connect to postgres.");
@@ -42,11 +42,16 @@ async fn create_test_database(database_url: &str) -> String {
 pub async fn get_test_db_pool() -> Pool<Postgres> {
     let settings = get_settings();
     let test_database_url = create_test_database(&settings.database_url).await;
-    connect_to_database_and_migrate(&test_database_url).await
+    connect_to_database_and_migrate(&test_database_url)
+        .await
+        .expect("Failed to connect to and migrate test database.")
 }
 
 pub async fn spawn_app() -> TestApp {
-    let mut settings = get_settings();
+    spawn_app_with_settings(get_settings()).await
+}
+
+pub async fn spawn_app_with_settings(mut settings: Settings) -> TestApp {
     let test_subid = random_simple_ascii_string();
     let test_aic_expiration_days = random_integer();
     let test_auth_password = random_ascii_string();
@@ -62,10 +67,12 @@ pub async fn spawn_app() -> TestApp {
     settings.database_url = test_database_url;
     settings.port = port;
     let statsd = StatsD::new(&settings);
-    let db_pool = connect_to_database_and_migrate(&settings.database_url).await;
+    let db_pool = connect_to_database_and_migrate(&settings.database_url)
+        .await
+        .expect("Failed to connect to and migrate test database.");
     let server =
         run_server(settings.clone(), listener, db_pool, statsd).expect("Failed to start server");
-    let _ = tokio::spawn(server);
+    tokio::spawn(server);
     TestApp { settings }
 }
 